@@ -0,0 +1,116 @@
+//! Compares the histogram distances Kmeans can cluster with
+//! run with `cargo bench --bench distance`
+//! add `--features simd` to compare l2_dist_simd with the scalar l2_dist
+
+extern crate criterion;
+extern crate rand;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use rust_solver::kmeans::{self, DistanceMetric, FitOptions, Histogram, Kmeans};
+use rust_solver::sparse;
+
+const METRICS: [DistanceMetric; 4] = [
+    DistanceMetric::L2,
+    DistanceMetric::L1,
+    DistanceMetric::Emd,
+    DistanceMetric::EmdApprox,
+];
+
+/// normalized histograms like the equity histograms of gen_abstraction
+fn histograms(n: usize, n_bins: usize) -> Vec<Histogram> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    return (0..n)
+        .map(|_| {
+            let mut h: Histogram = (0..n_bins).map(|_| rng.gen::<f32>()).collect();
+            kmeans::normalize(&mut h);
+            h
+        })
+        .collect();
+}
+
+fn bench_distance(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distance");
+    for n_bins in [30, 50].iter() {
+        let dataset = histograms(2, *n_bins);
+        for metric in METRICS.iter() {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", metric), n_bins),
+                &dataset,
+                |b, dataset| {
+                    b.iter(|| metric.compute(black_box(&dataset[0]), black_box(&dataset[1])))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// a single Lloyd iteration over 10k hands with 50 centers
+fn bench_fit_iteration(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fit_iteration");
+    group.sample_size(20);
+    let dataset = histograms(10_000, 30);
+    let opts = FitOptions {
+        max_iters: 1,
+        ..FitOptions::default()
+    };
+    for metric in METRICS.iter() {
+        group.bench_function(format!("{:?}", metric), |b| {
+            b.iter(|| {
+                let mut estimator = Kmeans::from_centers(dataset[..50].to_vec());
+                estimator.set_verbose(false);
+                estimator.fit_with_opts(&dataset, *metric, &opts)
+            })
+        });
+    }
+    group.finish();
+}
+
+/// dense and sparse l2_dist from a histogram with 5 of 500 bins set
+/// to a dense center, like a turn hand over river buckets
+fn bench_sparse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sparse");
+    let center = histograms(1, 500).remove(0);
+    let mut item: Histogram = vec![0.0; 500];
+    for i in 0..5 {
+        item[i * 97] = 0.2;
+    }
+    let sparse_item = sparse::SparseHistogram::from_dense(&item);
+    let prepared = sparse::DenseCenter::new(&center);
+    group.bench_function("dense", |b| {
+        b.iter(|| kmeans::l2_dist(black_box(&item), black_box(&center)))
+    });
+    group.bench_function("sparse", |b| {
+        b.iter(|| sparse::l2_dist_dense(black_box(&sparse_item), black_box(&prepared)))
+    });
+    group.finish();
+}
+
+/// scalar and eight lane l2_dist on 50 bin histograms
+#[cfg(feature = "simd")]
+fn bench_l2_simd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("l2_simd");
+    let dataset = histograms(2, 50);
+    group.bench_function("scalar", |b| {
+        b.iter(|| kmeans::l2_dist_scalar(black_box(&dataset[0]), black_box(&dataset[1])))
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| kmeans::l2_dist_simd(black_box(&dataset[0]), black_box(&dataset[1])))
+    });
+    group.finish();
+}
+
+#[cfg(not(feature = "simd"))]
+criterion_group!(benches, bench_distance, bench_fit_iteration, bench_sparse);
+#[cfg(feature = "simd")]
+criterion_group!(
+    benches,
+    bench_distance,
+    bench_fit_iteration,
+    bench_sparse,
+    bench_l2_simd
+);
+criterion_main!(benches);
@@ -1,104 +1,781 @@
-extern crate rust_poker;
-// extern crate rayon;
 extern crate bytepack;
 extern crate crossbeam;
+extern crate rand;
+extern crate rayon;
+extern crate rust_poker;
 
-use bytepack::LEPacker;
-use std::fs::OpenOptions;
+use rayon::prelude::*;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::Write; // <--- ring flush() into scope
+use std::io::{Seek, SeekFrom};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Instant;
 
 use hand_indexer::HandIndexer;
-use rust_poker::constants::{RANK_TO_CHAR, SUIT_TO_CHAR};
-use rust_poker::equity_calculator::approx_equity;
+use rand::Rng;
+use rust_poker::equity_calculator::{approx_equity, exact_equity};
 use rust_poker::hand_range::{Combo, HandRange};
+use rust_solver::ehs_format;
 
 const N_THREADS: u64 = 8;
 
+/// rounds with at least this many hands are written through a memory map,
+/// smaller rounds are buffered and appended
+const MMAP_THRESHOLD: u64 = 1 << 24;
+
+/// most players rust_poker can simulate, hero included
+const MAX_PLAYERS: usize = 6;
+
+const USAGE: &str =
+    "usage: gen_ehs [--threads <n>] [--opponents <n>] [--mc-threads <n>[,<n>,<n>,<n>]] \
+                     [--mc-stderr <x>[,<x>,<x>,<x>]] [--round <0-3>] [--out <path>] [--force] [--verify <n>] \
+                     [--estimate] [--indexers <cards>[,<cards>..];<cards>..;..] [--precision <f32|f64>] \
+                     [--concurrent]";
+
+/// cards dealt in each round of the hand indexer of every table round
+/// table round i holds the hands of the last round of indexer i
+const DEFAULT_INDEXERS: [&[u8]; 4] = [&[2], &[2, 3], &[2, 4], &[2, 5]];
+
+/// command line options
+#[derive(Debug, PartialEq)]
+struct Args {
+    /// number of threads per round
+    n_threads: u64,
+    /// number of random opponents, the runtime grows with every opponent
+    opponents: usize,
+    /// simulator threads per hand for each round
+    mc_threads: [u8; 4],
+    /// standard error approx_equity stops at for each round,
+    /// halving it quadruples the runtime, the river has by far the most hands
+    mc_stderr: [f64; 4],
+    /// only compute this round, all rounds if None
+    round: Option<usize>,
+    /// path of the equity table, ehs_round_{round}.dat by default with --round
+    out: String,
+    /// overwrite out if it already exists
+    force: bool,
+    /// only compare n random hands per round against exact enumeration
+    verify: Option<u64>,
+    /// only print the size of each round, of the table and the expected runtime
+    estimate: bool,
+    /// cards per round of the hand indexer of each table round,
+    /// e.g. [2, 3, 1] indexes turn hands with the flop and turn isomorphism
+    /// kept apart, readers of the table have to use the same indexers
+    indexers: [Vec<u8>; 4],
+    /// how the equities are stored, f32 halves the table and is precise
+    /// enough for the abstraction, see ehs_format::Precision
+    precision: ehs_format::Precision,
+    /// compute the rounds at the same time instead of one after the other,
+    /// they share the n_threads of the pool, so the cores a round leaves idle
+    /// at the end of each block and all of the cheap rounds are picked up by the others
+    concurrent: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            n_threads: N_THREADS,
+            opponents: 1,
+            // small sample count and more cores postflop
+            mc_threads: [1, 2, 2, 2],
+            mc_stderr: [0.001, 0.01, 0.01, 0.01],
+            round: None,
+            out: String::from("ehs.dat"),
+            force: false,
+            verify: None,
+            estimate: false,
+            indexers: [
+                DEFAULT_INDEXERS[0].to_vec(),
+                DEFAULT_INDEXERS[1].to_vec(),
+                DEFAULT_INDEXERS[2].to_vec(),
+                DEFAULT_INDEXERS[3].to_vec(),
+            ],
+            precision: ehs_format::Precision::F64,
+            concurrent: false,
+        }
+    }
+}
+
+/// parses a single value applied to all rounds or one value per round
+fn parse_per_round<T: FromStr + Copy>(value: &str) -> Option<[T; 4]> {
+    let values: Vec<T> = match value.split(',').map(|v| v.parse::<T>()).collect() {
+        Ok(values) => values,
+        Err(_) => return None,
+    };
+    return match values.len() {
+        1 => Some([values[0]; 4]),
+        4 => Some([values[0], values[1], values[2], values[3]]),
+        _ => None,
+    };
+}
+
+/// parses the cards per round of four hand indexers separated by ';'
+/// every indexer starts with the 2 hole cards and deals at most 7 cards
+fn parse_indexers(value: &str) -> Option<[Vec<u8>; 4]> {
+    let mut indexers: Vec<Vec<u8>> = Vec::with_capacity(4);
+    for config in value.split(';') {
+        let cards: Vec<u8> = match config.split(',').map(|v| v.trim().parse::<u8>()).collect() {
+            Ok(cards) => cards,
+            Err(_) => return None,
+        };
+        let total: usize = cards.iter().map(|&c| c as usize).sum();
+        if cards[0] != 2 || cards.iter().any(|&c| c == 0) || total > 7 {
+            return None;
+        }
+        indexers.push(cards);
+    }
+    if indexers.len() != 4 {
+        return None;
+    }
+    let mut indexers = indexers.into_iter();
+    return Some([
+        indexers.next().unwrap(),
+        indexers.next().unwrap(),
+        indexers.next().unwrap(),
+        indexers.next().unwrap(),
+    ]);
+}
+
+/// parses the command line arguments (without the program name)
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => {
+                parsed.n_threads = match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) if n > 0 => n,
+                    _ => return Err(String::from("--threads expects a positive integer")),
+                };
+            }
+            "--opponents" => {
+                parsed.opponents = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 && n < MAX_PLAYERS => n,
+                    _ => {
+                        return Err(format!(
+                            "--opponents expects an integer between 1 and {}",
+                            MAX_PLAYERS - 1
+                        ))
+                    }
+                };
+            }
+            "--mc-threads" => {
+                parsed.mc_threads = match args.next().and_then(|v| parse_per_round::<u8>(&v)) {
+                    Some(n) if n.iter().all(|&n| n > 0) => n,
+                    _ => {
+                        return Err(String::from(
+                            "--mc-threads expects one or four positive integers",
+                        ))
+                    }
+                };
+            }
+            "--mc-stderr" => {
+                parsed.mc_stderr = match args.next().and_then(|v| parse_per_round::<f64>(&v)) {
+                    Some(e) if e.iter().all(|&e| e > 0.0) => e,
+                    _ => {
+                        return Err(String::from(
+                            "--mc-stderr expects one or four positive numbers",
+                        ))
+                    }
+                };
+            }
+            "--round" => {
+                parsed.round = match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n < 4 => Some(n),
+                    _ => return Err(String::from("--round expects a round between 0 and 3")),
+                };
+            }
+            "--out" => {
+                out = match args.next() {
+                    Some(path) => Some(path),
+                    None => return Err(String::from("--out expects a path")),
+                };
+            }
+            "--indexers" => {
+                parsed.indexers = match args.next().and_then(|v| parse_indexers(&v)) {
+                    Some(indexers) => indexers,
+                    None => {
+                        return Err(String::from(
+                            "--indexers expects four lists of cards per round separated by ';'",
+                        ))
+                    }
+                };
+            }
+            "--precision" => {
+                parsed.precision = match args.next().as_deref() {
+                    Some("f32") => ehs_format::Precision::F32,
+                    Some("f64") => ehs_format::Precision::F64,
+                    _ => return Err(String::from("--precision expects f32 or f64")),
+                };
+            }
+            "--concurrent" => parsed.concurrent = true,
+            "--force" => parsed.force = true,
+            "--estimate" => parsed.estimate = true,
+            "--verify" => {
+                parsed.verify = match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) if n > 0 => Some(n),
+                    _ => return Err(String::from("--verify expects a positive integer")),
+                };
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    parsed.out = match (out, parsed.round) {
+        (Some(out), _) => out,
+        (None, Some(round)) => format!("ehs_round_{}.dat", round),
+        (None, None) => parsed.out,
+    };
+    return Ok(parsed);
+}
+
+/// rounds to compute
+fn rounds(args: &Args) -> std::ops::RangeInclusive<usize> {
+    return match args.round {
+        Some(round) => round..=round,
+        None => 0..=3,
+    };
+}
+
+/// round sizes written to the header of the table,
+/// rounds that are not computed are empty
+fn table_sizes(round_sizes: &[u64; 4], args: &Args) -> [u64; 4] {
+    let mut sizes = [0u64; 4];
+    for i in rounds(args) {
+        sizes[i] = round_sizes[i];
+    }
+    return sizes;
+}
+
+/// number of hands computed between checkpoints
+const CHECKPOINT_SIZE: u64 = 1 << 16;
+
+/// sidecar file recording how far generation of out got
+fn checkpoint_path(out: &str) -> String {
+    return format!("{}.ckpt", out);
+}
+
+/// reads the next hand index to compute of every round from a checkpoint
+/// older checkpoints of sequential runs hold a single (round, hand index)
+fn read_checkpoint(path: &str, round_sizes: &[u64; 4]) -> io::Result<[u64; 4]> {
+    let invalid = || {
+        return io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid checkpoint {}", path),
+        );
+    };
+    let contents = fs::read_to_string(path)?;
+    let values: Vec<u64> = contents
+        .split_whitespace()
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let mut positions = [0u64; 4];
+    match values.len() {
+        2 => {
+            if values[0] > 4 {
+                return Err(invalid());
+            }
+            let round = values[0] as usize;
+            positions[..round].copy_from_slice(&round_sizes[..round]);
+            if round < 4 {
+                positions[round] = values[1];
+            }
+        }
+        4 => positions.copy_from_slice(&values),
+        // resuming from a misread checkpoint would truncate the table
+        _ => return Err(invalid()),
+    }
+    if (0..4).any(|i| positions[i] > round_sizes[i]) {
+        return Err(invalid());
+    }
+    return Ok(positions);
+}
+
+/// records the next hand index to compute of every round
+/// written to a temporary file first so a crash never leaves a torn checkpoint
+fn write_checkpoint(path: &str, positions: &[u64; 4]) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let line: Vec<String> = positions.iter().map(|p| p.to_string()).collect();
+    fs::write(&tmp, format!("{}\n", line.join(" ")))?;
+    return fs::rename(&tmp, path);
+}
+
+/// number of progress updates printed per round
+const PROGRESS_UPDATES: u64 = 1000;
+/// number of progress lines printed per round with --concurrent
+const CONCURRENT_PROGRESS_UPDATES: u64 = 100;
+
+/// formats seconds as 1h02m03s
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        return format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60);
+    }
+    if secs >= 60 {
+        return format!("{}m{:02}s", secs / 60, secs % 60);
+    }
+    return format!("{}s", secs);
+}
+
+/// hands of a round that are done, shared by all threads
+struct Progress {
+    done: AtomicU64,
+    total: u64,
+    /// hands that were already done when the round (re)started
+    initial: u64,
+    start: Instant,
+    print_every: u64,
+    /// round printed on every line when several rounds report at once,
+    /// a single round overwrites its line instead
+    round: Option<usize>,
+}
+
+impl Progress {
+    fn new(total: u64, initial: u64, round: Option<usize>) -> Progress {
+        let updates = match round {
+            Some(_) => CONCURRENT_PROGRESS_UPDATES,
+            None => PROGRESS_UPDATES,
+        };
+        Progress {
+            done: AtomicU64::new(initial),
+            total,
+            initial,
+            start: Instant::now(),
+            print_every: (total / updates).max(1),
+            round,
+        }
+    }
+
+    /// marks one hand as done, prints the progress every print_every hands
+    fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done % self.print_every == 0 {
+            self.print(done);
+        }
+    }
+
+    fn print(&self, done: u64) {
+        let rate = (done - self.initial) as f64 / self.start.elapsed().as_secs_f64();
+        let eta = if rate > 0.0 {
+            format_duration(((self.total - done) as f64 / rate) as u64)
+        } else {
+            String::from("?")
+        };
+        let percent = (100 * done) as f64 / self.total as f64;
+        match self.round {
+            Some(round) => println!(
+                "round {}: {:.3}% ({}/{}) eta {}",
+                round, percent, done, self.total, eta
+            ),
+            None => {
+                print!(
+                    "{:.3}% ({}/{}) eta {}    \r",
+                    percent, done, self.total, eta
+                );
+                io::stdout().flush().unwrap();
+            }
+        }
+    }
+
+    /// prints the final progress of the round
+    fn finish(&self) {
+        self.print(self.total);
+        if self.round.is_none() {
+            println!();
+        }
+    }
+}
+
+/// hand indexers of the table rounds
+fn build_indexers(args: &Args) -> Vec<HandIndexer> {
+    return args
+        .indexers
+        .iter()
+        .map(|cards| HandIndexer::init(cards.len() as u32, cards.clone()))
+        .collect();
+}
+
+/// round of indexer i whose hands are stored in table round i
+fn index_round(i: usize, args: &Args) -> u32 {
+    return args.indexers[i].len() as u32 - 1;
+}
+
+/// number of hole and board cards of the hands of table round i
+fn n_cards(i: usize, args: &Args) -> usize {
+    return args.indexers[i].iter().map(|&c| c as usize).sum();
+}
+
+/// mask of the cards no opponent can be dealt,
+/// the hole cards and the board of a hand
+fn dead_mask(cards: &[u8]) -> u64 {
+    return cards.iter().fold(0u64, |mask, c| mask | (1u64 << c));
+}
+
+/// hero range followed by opponents random ranges, and the board mask
+/// for a hand of hole cards followed by the board
+fn hand_ranges(cards: &[u8], opponents: usize) -> (Vec<HandRange>, u64) {
+    let combo = Combo(cards[0], cards[1], 100);
+    let board_mask = dead_mask(&cards[2..]);
+
+    let mut range_strs = vec![combo.to_string()];
+    range_strs.resize(opponents + 1, "random".to_string());
+    let mut hand_ranges = HandRange::from_strings(range_strs);
+    // the simulation deals opponents from their ranges,
+    // so remove every combo with a dead card up front
+    let dead_mask = dead_mask(cards);
+    hand_ranges[1..]
+        .iter_mut()
+        .for_each(|h| h.remove_conflicting_combos(dead_mask));
+    return (hand_ranges, board_mask);
+}
+
+/// whether round i is enumerated exactly instead of simulated
+/// heads up on the river only the opponent hands are left to enumerate
+fn is_exact(i: usize, args: &Args) -> bool {
+    return n_cards(i, args) == 7 && args.opponents == 1;
+}
+
+/// equity of a hand of round i
+fn hand_equity(cards: &[u8], i: usize, args: &Args) -> f64 {
+    let (hand_ranges, board_mask) = hand_ranges(cards, args.opponents);
+    if is_exact(i, args) {
+        return exact_equity(&hand_ranges, board_mask, 1).unwrap()[0];
+    }
+    return approx_equity(
+        &hand_ranges,
+        board_mask,
+        args.mc_threads[i],
+        args.mc_stderr[i],
+    )
+    .unwrap()[0];
+}
+
+/// computes the equity of hands start..start + len of a round
+/// against args.opponents random hands, runs on the current rayon pool
+fn compute_block(
+    indexers: &[HandIndexer],
+    i: usize,
+    start: u64,
+    len: u64,
+    args: &Args,
+    progress: &Progress,
+) -> Vec<f64> {
+    let mut equity_table = vec![0f64; len as usize];
+    let round = index_round(i, args);
+    equity_table.par_iter_mut().enumerate().for_each_init(
+        || vec![0u8; n_cards(i, args)],
+        |cards, (k, equity)| {
+            indexers[i].get_hand(round, start + k as u64, cards.as_mut_slice());
+            *equity = hand_equity(cards, i, args);
+            progress.inc();
+        },
+    );
+    return equity_table;
+}
+
+/// max and mean of absolute errors
+fn error_stats(errors: &[f64]) -> (f64, f64) {
+    let max = errors.iter().cloned().fold(0f64, f64::max);
+    let mean = errors.iter().sum::<f64>() / errors.len().max(1) as f64;
+    return (max, mean);
+}
+
+/// compares the equity of n random hands per round against exact enumeration
+/// enumerating preflop and multiway hands is very slow, keep n small
+fn verify(indexers: &[HandIndexer], n: u64, args: &Args) {
+    let mut rng = rand::thread_rng();
+    for i in rounds(args) {
+        if is_exact(i, args) {
+            println!("round {}: enumerated exactly", i);
+            continue;
+        }
+        let round = index_round(i, args);
+        let size = indexers[i].size(round);
+        let indices: Vec<u64> = (0..n).map(|_| rng.gen_range(0, size)).collect();
+        let progress = Progress::new(n, 0, None);
+        let errors: Vec<f64> = indices
+            .par_iter()
+            .map(|&index| {
+                let mut cards = vec![0u8; n_cards(i, args)];
+                indexers[i].get_hand(round, index, cards.as_mut_slice());
+                let approx = hand_equity(&cards, i, args);
+                let (hand_ranges, board_mask) = hand_ranges(&cards, args.opponents);
+                let exact = exact_equity(&hand_ranges, board_mask, 1).unwrap()[0];
+                progress.inc();
+                return (approx - exact).abs();
+            })
+            .collect();
+        progress.finish();
+        let (max, mean) = error_stats(&errors);
+        println!(
+            "round {}: max error {:.5}, mean error {:.5} over {} hands (stderr target {})",
+            i, max, mean, n, args.mc_stderr[i]
+        );
+    }
+}
+
+/// number of random hands per round timed by --estimate
+const ESTIMATE_HANDS: u64 = 256;
+
+/// size in bytes of a table
+fn table_bytes(header: &ehs_format::Header) -> u64 {
+    return ehs_format::round_offset(header, 4);
+}
+
+/// prints the size of each round and of the table and estimates the runtime
+/// from the equity of ESTIMATE_HANDS random hands per round on the current pool
+fn estimate(indexers: &[HandIndexer], header: &ehs_format::Header, args: &Args) {
+    let mut rng = rand::thread_rng();
+    let mut total_secs = 0f64;
+    for i in rounds(args) {
+        let round = index_round(i, args);
+        let size = header.round_sizes[i];
+        let indices: Vec<u64> = (0..ESTIMATE_HANDS.min(size))
+            .map(|_| rng.gen_range(0, size))
+            .collect();
+        let start = Instant::now();
+        indices.par_iter().for_each(|&index| {
+            let mut cards = vec![0u8; n_cards(i, args)];
+            indexers[i].get_hand(round, index, cards.as_mut_slice());
+            hand_equity(&cards, i, args);
+        });
+        let secs = start.elapsed().as_secs_f64() * size as f64 / indices.len().max(1) as f64;
+        total_secs += secs;
+        println!(
+            "round {}: {} hands, {} bytes, ~{}",
+            i,
+            size,
+            size * header.precision.elem_size() as u64,
+            format_duration(secs as u64)
+        );
+    }
+    println!(
+        "{}: {} bytes, ~{} on {} threads",
+        args.out,
+        table_bytes(header),
+        format_duration(total_secs as u64),
+        args.n_threads
+    );
+}
+
 fn main() {
-    let cards_per_round: [usize; 4] = [2, 5, 6, 7];
-
-    // create preflop indexer
-    let indexers = [
-        HandIndexer::init(1, [2].to_vec()),
-        HandIndexer::init(2, [2, 3].to_vec()),
-        HandIndexer::init(2, [2, 4].to_vec()),
-        HandIndexer::init(2, [2, 5].to_vec()),
-    ];
-
-    // let mut file = File::create("ehs.dat").unwrap();
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open("ehs.dat")
-        .unwrap();
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    let checkpoint = checkpoint_path(&args.out);
 
+    let indexers = build_indexers(&args);
+    let mut round_sizes = [0u64; 4];
     for i in 0..4 {
-        let start_time = Instant::now();
-        // number of isomorphic hands in this street
-        let round = if i == 0 { 0 } else { 1 };
-        let batch_size = indexers[i].size(round);
-        println!("{} combinations in round {}", batch_size, i);
-        // num hands per thread
-        let size_per_thread = batch_size / N_THREADS;
-        // equity table
-        let mut equity_table = vec![0f64; batch_size as usize];
-        // current round 0->preflop, 3->river
+        round_sizes[i] = indexers[i].size(index_round(i, &args));
+    }
+    // a table with a single round keeps the layout with the other rounds empty
+    let header = ehs_format::Header {
+        round_sizes: table_sizes(&round_sizes, &args),
+        precision: args.precision,
+    };
+    let round_sizes = header.round_sizes;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.n_threads as usize)
+        .build()
+        .unwrap();
+
+    if let Some(n) = args.verify {
+        pool.install(|| verify(&indexers, n, &args));
+        return;
+    }
+
+    // nothing is written
+    if args.estimate {
+        pool.install(|| estimate(&indexers, &header, &args));
+        return;
+    }
+
+    // resume from the checkpoint unless forced to start over
+    let resume = if args.force {
+        None
+    } else {
+        match read_checkpoint(&checkpoint, &round_sizes) {
+            Ok(positions) => Some(positions),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                eprintln!("{}, use --force to start over", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    let positions = match resume {
+        Some(positions) => {
+            // drop anything written after the last checkpoint
+            let elem_size = args.precision.elem_size() as u64;
+            let end = (0..4)
+                .filter(|&i| positions[i] > 0)
+                .map(|i| ehs_format::round_offset(&header, i) + positions[i] * elem_size)
+                .max()
+                .unwrap_or(ehs_format::HEADER_SIZE);
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&args.out)
+                .unwrap();
+            match ehs_format::read_header(&mut file) {
+                Ok(found) if found == header => {}
+                Ok(found) if found.round_sizes == round_sizes => {
+                    eprintln!(
+                        "{} is stored as {:?}, use --force to start over",
+                        args.out, found.precision
+                    );
+                    process::exit(1);
+                }
+                Ok(_) => {
+                    eprintln!(
+                        "{} has different round sizes, use --force to start over",
+                        args.out
+                    );
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("{}: {}, use --force to start over", args.out, e);
+                    process::exit(1);
+                }
+            }
+            if file.metadata().unwrap().len() < end {
+                eprintln!(
+                    "{} is shorter than its checkpoint, use --force to start over",
+                    args.out
+                );
+                process::exit(1);
+            }
+            file.set_len(end).unwrap();
+            for i in rounds(&args) {
+                if positions[i] > 0 && positions[i] < round_sizes[i] {
+                    println!("Resuming round {} at hand {}", i, positions[i]);
+                }
+            }
+            positions
+        }
+        None => {
+            let created = if args.force {
+                File::create(&args.out)
+            } else {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&args.out)
+            };
+            let mut file = match created {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("{}: {}, use --force to overwrite", args.out, e);
+                    process::exit(1);
+                }
+            };
+            ehs_format::write_header(&mut file, &header).unwrap();
+            file.sync_data().unwrap();
+            write_checkpoint(&checkpoint, &[0; 4]).unwrap();
+            [0; 4]
+        }
+    };
+
+    let run = Run {
+        indexers: &indexers,
+        header,
+        args: &args,
+        pool: &pool,
+        checkpoint: &checkpoint,
+        positions: Mutex::new(positions),
+    };
+    if args.concurrent {
+        // one driver per round, the equities are computed on the shared pool
         crossbeam::scope(|scope| {
-            for (j, slice) in equity_table
-                .chunks_mut(size_per_thread as usize)
-                .enumerate()
-            {
-                scope.spawn(move |_| {
-                    let mut board_mask: u64;
-                    let mut combo: Combo;
-                    let mut hand_ranges: Vec<HandRange>;
-                    let mut cards: Vec<u8> = vec![0; cards_per_round[i]];
-                    for k in 0..slice.len() {
-                        // update percent every 1000 hands on thread 0
-                        if (j == 0) && (k & 0xfff == 0) {
-                            print!("{:.3}% \r", (100 * k) as f64 / size_per_thread as f64);
-                            io::stdout().flush().unwrap();
-                        }
-
-                        indexers[i].get_hand(
-                            round,
-                            ((j as u64) * size_per_thread) + (k as u64),
-                            cards.as_mut_slice(),
-                        );
-                        combo = Combo(cards[0], cards[1], 100);
-
-                        // create board
-                        board_mask = 0;
-                        let mut board_str = String::new();
-                        for n in 2..cards_per_round[i as usize] {
-                            board_mask |= 1u64 << cards[n];
-                            board_str.push(RANK_TO_CHAR[(cards[n] >> 2) as usize]);
-                            board_str.push(SUIT_TO_CHAR[(cards[n] & 3) as usize]);
-                        }
-
-                        hand_ranges = HandRange::from_strings(
-                            [combo.to_string(), "random".to_string()].to_vec(),
-                        );
-
-                        // run sim
-                        if i == 0 {
-                            slice[k] =
-                                approx_equity(&mut hand_ranges, board_mask, 1, 0.001).unwrap()[0];
-                        } else {
-                            // small sample count and more cores
-                            slice[k] =
-                                approx_equity(&mut hand_ranges, board_mask, 2, 0.01).unwrap()[0];
-                        }
-                    }
-                });
+            for i in rounds(&args) {
+                let run = &run;
+                scope.spawn(move |_| run.round(i));
             }
         })
         .unwrap();
+    } else {
+        for i in rounds(&args) {
+            run.round(i);
+        }
+    }
+}
 
-        // write to file
-        file.pack_all(&equity_table[..]).unwrap();
+/// what the rounds of a run share
+struct Run<'a> {
+    indexers: &'a [HandIndexer],
+    header: ehs_format::Header,
+    args: &'a Args,
+    /// computes the equities of every round
+    pool: &'a rayon::ThreadPool,
+    checkpoint: &'a str,
+    /// next hand index to compute of every round
+    positions: Mutex<[u64; 4]>,
+}
+
+impl<'a> Run<'a> {
+    /// computes the rest of round i in blocks of CHECKPOINT_SIZE hands,
+    /// each block is written to the table and then recorded in the checkpoint
+    fn round(&self, i: usize) {
+        let (args, header) = (self.args, &self.header);
+        // number of isomorphic hands in this street
+        let batch_size = header.round_sizes[i];
+        let mut index = self.positions.lock().unwrap()[i];
+        if index == batch_size {
+            return;
+        }
+        let start_time = Instant::now();
+        println!("{} combinations in round {}", batch_size, i);
+        let progress = Progress::new(batch_size, index, Some(i).filter(|_| args.concurrent));
+        // every round seeks its own handle
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&args.out)
+            .unwrap();
+        // blocks are written into the page cache instead of appended for the large rounds
+        let mut mapped = if batch_size >= MMAP_THRESHOLD {
+            Some(ehs_format::MappedRound::new(&file, header, i).unwrap())
+        } else {
+            None
+        };
+        while index < batch_size {
+            let len = CHECKPOINT_SIZE.min(batch_size - index);
+            let equity_table = self
+                .pool
+                .install(|| compute_block(self.indexers, i, index, len, args, &progress));
+            // write to file, then record the progress
+            match mapped.as_mut() {
+                Some(map) => {
+                    map.write(index as usize, &equity_table);
+                    map.flush(index as usize, len as usize).unwrap();
+                }
+                None => {
+                    let elem_size = args.precision.elem_size() as u64;
+                    file.seek(SeekFrom::Start(
+                        ehs_format::round_offset(header, i) + index * elem_size,
+                    ))
+                    .unwrap();
+                    ehs_format::write_equities(&mut file, &equity_table, args.precision).unwrap();
+                    file.sync_data().unwrap();
+                }
+            }
+            index += len;
+            let mut positions = self.positions.lock().unwrap();
+            positions[i] = index;
+            write_checkpoint(self.checkpoint, &positions).unwrap();
+        }
+        progress.finish();
 
         let duration = start_time.elapsed().as_millis();
         println!(
@@ -109,3 +786,212 @@ fn main() {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        return parse_args(args.iter().map(|a| a.to_string()));
+    }
+
+    #[test]
+    fn test_parse_mc_args() {
+        let args = parse(&["--mc-threads", "4", "--mc-stderr", "0.001,0.005,0.01,0.02"]).unwrap();
+        assert_eq!(args.mc_threads, [4; 4]);
+        assert_eq!(args.mc_stderr, [0.001, 0.005, 0.01, 0.02]);
+        assert_eq!(parse_per_round::<u8>("1,2,3,4"), Some([1, 2, 3, 4]));
+        assert_eq!(parse_per_round::<u8>("1,x,3,4"), None);
+    }
+
+    #[test]
+    fn test_error_stats() {
+        assert_eq!(error_stats(&[0.01, 0.03, 0.02]), (0.03, 0.02));
+        assert_eq!(error_stats(&[]), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_hand_equity() {
+        let args = Args::default();
+        // spade royal flush on the river can't lose
+        let royal = [51u8, 47, 43, 39, 35, 0, 5];
+        assert!(is_exact(3, &args));
+        assert_eq!(hand_equity(&royal, 3, &args), 1.0);
+        // turn simulation is within a few standard errors of enumeration
+        let cards = [51u8, 46, 41, 20, 9, 0];
+        let (hand_ranges, board_mask) = hand_ranges(&cards, 1);
+        assert_eq!(hand_ranges[1].hands.len(), 1035);
+        let exact = exact_equity(&hand_ranges, board_mask, 1).unwrap()[0];
+        assert!((hand_equity(&cards, 2, &args) - exact).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_hand_ranges_dead_cards() {
+        // a hand of every round
+        let hands: [&[u8]; 4] = [
+            &[51, 50],
+            &[51, 50, 0, 4, 8],
+            &[12, 13, 0, 4, 8, 49],
+            &[40, 41, 0, 4, 8, 49, 50],
+        ];
+        for cards in hands.iter() {
+            let dead = dead_mask(cards);
+            assert_eq!(dead.count_ones() as usize, cards.len());
+            for opponents in 1..4 {
+                let (hand_ranges, board_mask) = hand_ranges(cards, opponents);
+                assert_eq!(board_mask, dead_mask(&cards[2..]));
+                assert_eq!(hand_ranges.len(), opponents + 1);
+                assert_eq!(hand_ranges[0].hands.len(), 1);
+                let live = 52 - cards.len();
+                for range in &hand_ranges[1..] {
+                    // every combo of the live cards and nothing else
+                    assert_eq!(range.hands.len(), live * (live - 1) / 2);
+                    for combo in &range.hands {
+                        assert_eq!(dead & (1u64 << combo.0), 0, "{}", combo);
+                        assert_eq!(dead & (1u64 << combo.1), 0, "{}", combo);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_indexers() {
+        let args = parse(&["--indexers", "2;2,3;2,3,1;2,5"]).unwrap();
+        assert_eq!(args.indexers[2], vec![2, 3, 1]);
+        assert_eq!(index_round(2, &args), 2);
+        assert_eq!(n_cards(2, &args), 6);
+        assert_eq!(index_round(0, &args), 0);
+        assert!(is_exact(3, &args));
+        assert!(!is_exact(2, &args));
+        assert_eq!(parse_indexers("2;2,3;2,4"), None);
+        assert_eq!(parse_indexers("2;2,3;2,4;2,6"), None);
+        assert_eq!(parse_indexers("1;2,3;2,4;2,5"), None);
+        assert_eq!(parse_indexers("2;2,0,3;2,4;2,5"), None);
+        assert!(parse(&["--indexers", "2;2,x;2,4;2,5"]).is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let args = parse(&["--estimate", "--round", "1"]).unwrap();
+        assert!(args.estimate);
+        assert_eq!(args.round, Some(1));
+        let mut header = ehs_format::Header {
+            round_sizes: [0, 0, 0, 0],
+            precision: ehs_format::Precision::F64,
+        };
+        assert_eq!(table_bytes(&header), ehs_format::HEADER_SIZE);
+        header.round_sizes = [169, 1286792, 0, 0];
+        assert_eq!(
+            table_bytes(&header),
+            ehs_format::HEADER_SIZE + (169 + 1286792) * 8
+        );
+        header.precision = ehs_format::Precision::F32;
+        assert_eq!(
+            table_bytes(&header),
+            ehs_format::HEADER_SIZE + (169 + 1286792) * 4
+        );
+    }
+
+    #[test]
+    fn test_parse_args_default() {
+        assert_eq!(parse(&[]), Ok(Args::default()));
+    }
+
+    #[test]
+    fn test_parse_round() {
+        let args = parse(&["--round", "3"]).unwrap();
+        assert_eq!(args.round, Some(3));
+        assert_eq!(args.out, "ehs_round_3.dat");
+        assert_eq!(rounds(&args), 3..=3);
+        assert_eq!(table_sizes(&[1, 2, 3, 4], &args), [0, 0, 0, 4]);
+        let args = parse(&["--out", "river.dat", "--round", "3"]).unwrap();
+        assert_eq!(args.out, "river.dat");
+        assert!(parse(&["--round", "4"]).is_err());
+        assert!(parse(&["--round"]).is_err());
+        let args = Args::default();
+        assert_eq!(rounds(&args), 0..=3);
+        assert_eq!(table_sizes(&[1, 2, 3, 4], &args), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path = env::temp_dir().join("gen_ehs_test.ckpt");
+        let path = path.to_str().unwrap();
+        let sizes = [169, 1 << 20, 1 << 22, 1 << 24];
+        write_checkpoint(path, &[169, 65536, 0, 131072]).unwrap();
+        assert_eq!(
+            read_checkpoint(path, &sizes).unwrap(),
+            [169, 65536, 0, 131072]
+        );
+        // a sequential checkpoint of round 2 at hand 65536
+        fs::write(path, "2 65536\n").unwrap();
+        assert_eq!(
+            read_checkpoint(path, &sizes).unwrap(),
+            [169, 1 << 20, 65536, 0]
+        );
+        fs::write(path, "4 0\n").unwrap();
+        assert_eq!(read_checkpoint(path, &sizes).unwrap(), sizes);
+        // anything else is rejected rather than read as a fresh start
+        for contents in ["garbage", "", "7\n", "1 2 3\n", "5 0\n", "1 2 3 4 5\n"].iter() {
+            fs::write(path, contents).unwrap();
+            assert_eq!(
+                read_checkpoint(path, &sizes).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                contents
+            );
+        }
+        // past the end of the preflop
+        write_checkpoint(path, &[170, 0, 0, 0]).unwrap();
+        assert!(read_checkpoint(path, &sizes).is_err());
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            read_checkpoint(path, &sizes).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(5), "5s");
+        assert_eq!(format_duration(65), "1m05s");
+        assert_eq!(format_duration(3723), "1h02m03s");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&[
+            "--threads",
+            "32",
+            "--opponents",
+            "5",
+            "--out",
+            "run1.dat",
+            "--force",
+        ])
+        .unwrap();
+        assert_eq!(args.n_threads, 32);
+        assert_eq!(args.opponents, 5);
+        assert_eq!(args.out, "run1.dat");
+        assert!(args.force);
+        assert!(!args.concurrent);
+        assert!(parse(&["--concurrent"]).unwrap().concurrent);
+        assert!(parse(&["--threads", "0"]).is_err());
+        assert!(parse(&["--threads"]).is_err());
+        assert!(parse(&["--opponents", "0"]).is_err());
+        assert!(parse(&["--opponents", "6"]).is_err());
+        assert!(parse(&["--mc-threads", "0"]).is_err());
+        assert!(parse(&["--mc-stderr", "0.01,0.01"]).is_err());
+        assert!(parse(&["--mc-stderr", "-1"]).is_err());
+        assert_eq!(parse(&["--verify", "100"]).unwrap().verify, Some(100));
+        assert!(parse(&["--verify", "0"]).is_err());
+        assert!(parse(&["--out"]).is_err());
+        assert_eq!(
+            parse(&["--precision", "f32"]).unwrap().precision,
+            ehs_format::Precision::F32
+        );
+        assert!(parse(&["--precision", "f16"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
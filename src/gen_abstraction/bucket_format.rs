@@ -0,0 +1,185 @@
+/**
+ * Layout of the bucket tables written by gen_abstraction buckets
+ *
+ * little endian header:
+ *   magic: u32, version: u32, round: u32, n_buckets: u32, n_hands: u64
+ * followed by the u32 bucket of every hand index of the round
+ */
+use bytepack::{LEPacker, LEUnpacker};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Read, Result, Write};
+
+/// "BKT\0"
+pub const MAGIC: u32 = 0x0054_4b42;
+pub const VERSION: u32 = 1;
+/// size in bytes of one bucket
+pub const ELEM_SIZE: u64 = 4;
+/// size in bytes of the header, the buckets start here
+pub const HEADER_SIZE: u64 = 4 * 4 + 8;
+
+/// what a bucket table holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    /// betting round (0 -> preflop, 3 -> river)
+    pub round: u32,
+    /// buckets are in 0..n_buckets
+    pub n_buckets: u32,
+    /// number of hand indices of the round
+    pub n_hands: u64,
+}
+
+pub fn write_header<W: Write>(writer: &mut W, header: &Header) -> Result<()> {
+    writer.pack(MAGIC)?;
+    writer.pack(VERSION)?;
+    writer.pack(header.round)?;
+    writer.pack(header.n_buckets)?;
+    writer.pack(header.n_hands)?;
+    return Ok(());
+}
+
+/// reads and validates the header
+pub fn read_header<R: Read>(reader: &mut R) -> Result<Header> {
+    let magic: u32 = reader.unpack()?;
+    if magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a bucket table"));
+    }
+    let version: u32 = reader.unpack()?;
+    if version != VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported bucket table version {}", version),
+        ));
+    }
+    return Ok(Header {
+        round: reader.unpack()?,
+        n_buckets: reader.unpack()?,
+        n_hands: reader.unpack()?,
+    });
+}
+
+/// writes the bucket of every hand index of round to path
+/// n_buckets is the number of centers of the model, the highest buckets
+/// can be empty, fails if a bucket isn't below n_buckets
+pub fn write_buckets(path: &str, round: u32, n_buckets: u32, buckets: &[u32]) -> Result<()> {
+    if let Some(bucket) = buckets.iter().find(|b| **b >= n_buckets) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("bucket {} is not below {} buckets", bucket, n_buckets),
+        ));
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    let header = Header {
+        round,
+        n_buckets,
+        n_hands: buckets.len() as u64,
+    };
+    write_header(&mut file, &header)?;
+    file.pack_all(buckets)?;
+    return file.flush();
+}
+
+/**
+ * A bucket table mapped into memory
+ * only the pages that are read are loaded, so opening it is instant
+ * the buckets are viewed in place, which needs a little endian machine
+ */
+#[derive(Debug)]
+pub struct BucketTable {
+    map: Mmap,
+    header: Header,
+}
+
+impl BucketTable {
+    pub fn header(&self) -> &Header {
+        return &self.header;
+    }
+
+    /// the bucket of every hand index
+    pub fn buckets(&self) -> &[u32] {
+        // the map is page aligned and HEADER_SIZE is a multiple of 4
+        unsafe {
+            return std::slice::from_raw_parts(
+                self.map.as_ptr().add(HEADER_SIZE as usize) as *const u32,
+                self.header.n_hands as usize,
+            );
+        }
+    }
+
+    /// bucket of a hand index
+    pub fn get(&self, hand_index: usize) -> u32 {
+        return self.buckets()[hand_index];
+    }
+}
+
+/// maps a bucket table, fails if the file is shorter than its header says
+pub fn map_buckets(path: &str) -> Result<BucketTable> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "mapped bucket tables need a little endian machine",
+        ));
+    }
+    let mut file = File::open(path)?;
+    let header = read_header(&mut file)?;
+    let end = HEADER_SIZE + header.n_hands * ELEM_SIZE;
+    if file.metadata()?.len() < end {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is shorter than its {} bytes of buckets", path, end),
+        ));
+    }
+    let map = unsafe { Mmap::map(&file)? };
+    return Ok(BucketTable { map, header });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_buckets() {
+        let path = std::env::temp_dir().join("test_map_buckets.dat");
+        let path = path.to_str().unwrap();
+        let buckets = vec![2u32, 0, 1, 2, 4];
+        // bucket 3 and 5 are empty
+        write_buckets(path, 1, 6, &buckets).unwrap();
+
+        let table = map_buckets(path).unwrap();
+        assert_eq!(
+            *table.header(),
+            Header {
+                round: 1,
+                n_buckets: 6,
+                n_hands: 5
+            }
+        );
+        assert_eq!(table.buckets(), &buckets[..]);
+        assert_eq!(table.get(4), 4);
+        drop(table);
+
+        // truncated tables are rejected
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(HEADER_SIZE + 4 * ELEM_SIZE).unwrap();
+        drop(file);
+        assert_eq!(
+            map_buckets(path).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+
+        // headerless tables are rejected
+        let mut file = File::create(path).unwrap();
+        file.pack_all(&buckets[..]).unwrap();
+        drop(file);
+        assert_eq!(
+            map_buckets(path).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            write_buckets(path, 1, 4, &buckets).err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
@@ -1,5 +1,5 @@
 // use bytepack::{ LEUnpacker };
-use combine::parser::byte::num::le_f32;
+use combine::parser::byte::num::{le_f32, le_f64};
 use combine::Parser;
 use std::fs::File;
 use std::io::prelude::*;
@@ -9,6 +9,8 @@ use std::io::{Error, ErrorKind};
 
 use hand_indexer::HandIndexer;
 
+use crate::ehs_format;
+
 /**
  * structur to interface with EHS.dat table
  */
@@ -16,6 +18,8 @@ pub struct EHS {
     pub indexers: [HandIndexer; 4],
     // offsets for lookup table
     offsets: [u64; 4],
+    // f32 or f64 equities, as recorded in the header
+    precision: ehs_format::Precision,
     file: File, // file pointer to lookup table
                 // file_location: str
 }
@@ -35,10 +39,18 @@ impl EHS {
         for i in 1..4 {
             offsets[i] = offsets[i - 1] + indexers[i - 1].size(if i == 1 { 0 } else { 1 });
         }
+        let mut file = File::open("ehs.dat").unwrap();
+        let header = ehs_format::read_header(&mut file).unwrap();
+        for i in 0..4 {
+            if header.round_sizes[i] != indexers[i].size(if i == 0 { 0 } else { 1 }) {
+                panic!("ehs.dat does not match the hand indexers");
+            }
+        }
         EHS {
             indexers: indexers,
             offsets: offsets,
-            file: File::open("ehs.dat").unwrap(),
+            precision: header.precision,
+            file: file,
         }
     }
 
@@ -58,9 +70,17 @@ impl EHS {
         };
 
         let index = self.indexers[i].get_index(cards);
-        reader.seek(SeekFrom::Start((index + self.offsets[i]) * 8))?;
+        let elem_size = self.precision.elem_size() as u64;
+        reader.seek(SeekFrom::Start(
+            ehs_format::HEADER_SIZE + (index + self.offsets[i]) * elem_size,
+        ))?;
         let buffer = reader.fill_buf()?;
-        let result = le_f32().parse(buffer);
+        let result = match self.precision {
+            ehs_format::Precision::F32 => le_f32().parse(buffer),
+            ehs_format::Precision::F64 => {
+                le_f64().parse(buffer).map(|(val, rest)| (val as f32, rest))
+            }
+        };
         match result {
             Ok((val, _)) => {
                 return Ok(val);
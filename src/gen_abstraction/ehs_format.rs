@@ -0,0 +1,423 @@
+/**
+ * Layout of the EHS lookup table written by gen_ehs
+ *
+ * little endian header:
+ *   magic: u32, version: u32, element size: u32, n_rounds: u32,
+ *   n_rounds * round size: u64
+ * followed by one block of equities per round (preflop -> river),
+ * f64 or f32 as given by the element size
+ */
+use bytepack::{LEPacker, LEUnpacker};
+use memmap2::{Mmap, MmapMut, MmapOptions};
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+/// "EHS\0"
+pub const MAGIC: u32 = 0x0053_4845;
+pub const VERSION: u32 = 1;
+pub const N_ROUNDS: usize = 4;
+/// size in bytes of the header, the first round starts here
+pub const HEADER_SIZE: u64 = 4 * 4 + 8 * N_ROUNDS as u64;
+
+/**
+ * How the equities are stored
+ *
+ * f32 keeps 7 significant digits, far more than the Monte Carlo error
+ * of the equities or the width of any histogram bin, so it is enough
+ * for the abstraction and halves the file and the memory bandwidth
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    F32,
+    F64,
+}
+
+impl Precision {
+    /// size in bytes of one equity
+    pub fn elem_size(self) -> u32 {
+        return match self {
+            Precision::F32 => 4,
+            Precision::F64 => 8,
+        };
+    }
+
+    fn from_elem_size(elem_size: u32) -> Option<Precision> {
+        return match elem_size {
+            4 => Some(Precision::F32),
+            8 => Some(Precision::F64),
+            _ => None,
+        };
+    }
+}
+
+/// what an EHS table holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    /// number of equities per round
+    pub round_sizes: [u64; N_ROUNDS],
+    pub precision: Precision,
+}
+
+/// writes the header for a table with round_sizes equities per round
+pub fn write_header<W: Write>(writer: &mut W, header: &Header) -> Result<()> {
+    writer.pack(MAGIC)?;
+    writer.pack(VERSION)?;
+    writer.pack(header.precision.elem_size())?;
+    writer.pack(N_ROUNDS as u32)?;
+    writer.pack_all(&header.round_sizes[..])?;
+    return Ok(());
+}
+
+/// reads and validates the header
+pub fn read_header<R: Read>(reader: &mut R) -> Result<Header> {
+    let magic: u32 = reader.unpack()?;
+    if magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not an EHS table"));
+    }
+    let version: u32 = reader.unpack()?;
+    let elem_size: u32 = reader.unpack()?;
+    let n_rounds: u32 = reader.unpack()?;
+    let precision = match Precision::from_elem_size(elem_size) {
+        Some(precision) if version == VERSION && n_rounds as usize == N_ROUNDS => precision,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unsupported EHS table (version {}, element size {}, {} rounds)",
+                    version, elem_size, n_rounds
+                ),
+            ))
+        }
+    };
+    let mut round_sizes = [0u64; N_ROUNDS];
+    reader.unpack_exact(&mut round_sizes[..])?;
+    return Ok(Header {
+        round_sizes,
+        precision,
+    });
+}
+
+/// writes equities with the given precision
+pub fn write_equities<W: Write>(
+    writer: &mut W,
+    equities: &[f64],
+    precision: Precision,
+) -> Result<()> {
+    match precision {
+        Precision::F64 => writer.pack_all(equities)?,
+        Precision::F32 => {
+            let equities: Vec<f32> = equities.iter().map(|e| *e as f32).collect();
+            writer.pack_all(&equities[..])?;
+        }
+    }
+    return Ok(());
+}
+
+/// An EHS table loaded into memory
+/// f32 tables are widened to f64
+pub struct EhsTable {
+    rounds: Vec<Vec<f64>>,
+}
+
+impl EhsTable {
+    /// equities of a round indexed by hand index, 0 -> preflop, 3 -> river
+    pub fn round(&self, round: usize) -> &[f64] {
+        return &self.rounds[round];
+    }
+}
+
+/// reads a complete EHS table of either precision
+pub fn read_ehs(path: &str) -> Result<EhsTable> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = read_header(&mut reader)?;
+    let mut rounds = Vec::with_capacity(N_ROUNDS);
+    for size in header.round_sizes.iter() {
+        let size = *size as usize;
+        let round = match header.precision {
+            Precision::F64 => {
+                let mut round = vec![0f64; size];
+                reader.unpack_exact(&mut round[..])?;
+                round
+            }
+            Precision::F32 => {
+                let mut round = vec![0f32; size];
+                reader.unpack_exact(&mut round[..])?;
+                round.iter().map(|e| *e as f64).collect()
+            }
+        };
+        rounds.push(round);
+    }
+    return Ok(EhsTable { rounds });
+}
+
+/// byte offset of the first equity of round
+pub fn round_offset(header: &Header, round: usize) -> u64 {
+    let elem_size = header.precision.elem_size() as u64;
+    return HEADER_SIZE + header.round_sizes[..round].iter().sum::<u64>() * elem_size;
+}
+
+/// equities are little endian, maps view them in place
+fn check_endian() -> Result<()> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "mapped EHS tables need a little endian machine, use read_ehs",
+        ));
+    }
+    return Ok(());
+}
+
+/**
+ * An EHS table mapped into memory
+ * only the pages that are read are loaded, so opening it is instant
+ * the equities are viewed in place, which needs a little endian machine
+ */
+pub struct EhsMap {
+    map: Mmap,
+    header: Header,
+}
+
+impl EhsMap {
+    pub fn header(&self) -> &Header {
+        return &self.header;
+    }
+
+    /// equities of a round of an f64 table indexed by hand index, 0 -> preflop, 3 -> river
+    /// None for f32 tables, see get
+    pub fn round(&self, round: usize) -> Option<&[f64]> {
+        if self.header.precision != Precision::F64 {
+            return None;
+        }
+        let offset = round_offset(&self.header, round) as usize;
+        let len = self.header.round_sizes[round] as usize;
+        // the map is page aligned and HEADER_SIZE is a multiple of 8
+        unsafe {
+            return Some(std::slice::from_raw_parts(
+                self.map.as_ptr().add(offset) as *const f64,
+                len,
+            ));
+        }
+    }
+
+    /// equity of a hand index of round in either precision
+    pub fn get(&self, round: usize, index: usize) -> f64 {
+        if index as u64 >= self.header.round_sizes[round] {
+            panic!("hand index {} is out of round {}", index, round);
+        }
+        let elem_size = self.header.precision.elem_size() as usize;
+        let offset = round_offset(&self.header, round) as usize + index * elem_size;
+        let bytes = &self.map[offset..offset + elem_size];
+        return match self.header.precision {
+            Precision::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Precision::F64 => {
+                let mut le = [0u8; 8];
+                le.copy_from_slice(bytes);
+                f64::from_le_bytes(le)
+            }
+        };
+    }
+}
+
+/// maps a complete EHS table, fails if the file is shorter than its header says
+pub fn map_ehs(path: &str) -> Result<EhsMap> {
+    check_endian()?;
+    let mut file = File::open(path)?;
+    let header = read_header(&mut file)?;
+    let end = round_offset(&header, N_ROUNDS);
+    if file.metadata()?.len() < end {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is shorter than its {} bytes of equities", path, end),
+        ));
+    }
+    let map = unsafe { Mmap::map(&file)? };
+    return Ok(EhsMap { map, header });
+}
+
+/**
+ * A round of an EHS table mapped into memory for writing
+ * equities are written into the page cache instead of through a buffered
+ * file, rounds can be written in any order
+ */
+pub struct MappedRound {
+    map: MmapMut,
+    len: usize,
+    precision: Precision,
+}
+
+impl MappedRound {
+    /// maps round of the table in file, extends the file to the end of the round
+    /// file has to be opened for reading and writing
+    pub fn new(file: &File, header: &Header, round: usize) -> Result<MappedRound> {
+        check_endian()?;
+        let offset = round_offset(header, round);
+        let bytes = header.round_sizes[round] * header.precision.elem_size() as u64;
+        if file.metadata()?.len() < offset + bytes {
+            file.set_len(offset + bytes)?;
+        }
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(offset)
+                .len(bytes as usize)
+                .map_mut(file)?
+        };
+        return Ok(MappedRound {
+            map,
+            len: header.round_sizes[round] as usize,
+            precision: header.precision,
+        });
+    }
+
+    /// stores the equities of hand indices start..start + equities.len()
+    /// in the precision of the table
+    pub fn write(&mut self, start: usize, equities: &[f64]) {
+        if start + equities.len() > self.len {
+            panic!(
+                "{} equities at {} overflow a round of {}",
+                equities.len(),
+                start,
+                self.len
+            );
+        }
+        let size = self.precision.elem_size() as usize;
+        let bytes = &mut self.map[start * size..(start + equities.len()) * size];
+        for (chunk, e) in bytes.chunks_exact_mut(size).zip(equities.iter()) {
+            match self.precision {
+                Precision::F32 => chunk.copy_from_slice(&(*e as f32).to_le_bytes()),
+                Precision::F64 => chunk.copy_from_slice(&e.to_le_bytes()),
+            }
+        }
+    }
+
+    /// writes equities start..start + len to the file
+    pub fn flush(&self, start: usize, len: usize) -> Result<()> {
+        let size = self.precision.elem_size() as usize;
+        return self.map.flush_range(start * size, len * size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ehs() {
+        let path = std::env::temp_dir().join("test_read_ehs.dat");
+        let path = path.to_str().unwrap();
+        let rounds = vec![vec![0.5, 0.25], vec![0.75], vec![], vec![0.0, 1.0, 0.125]];
+        for precision in [Precision::F64, Precision::F32].iter() {
+            let header = Header {
+                round_sizes: [2, 1, 0, 3],
+                precision: *precision,
+            };
+            let mut file = File::create(path).unwrap();
+            write_header(&mut file, &header).unwrap();
+            for round in rounds.iter() {
+                write_equities(&mut file, &round[..], *precision).unwrap();
+            }
+            drop(file);
+
+            // the values are exact in f32 too
+            let table = read_ehs(path).unwrap();
+            for i in 0..N_ROUNDS {
+                assert_eq!(table.round(i), &rounds[i][..]);
+            }
+            let size = std::fs::metadata(path).unwrap().len();
+            assert_eq!(size, round_offset(&header, N_ROUNDS));
+        }
+
+        // raw tables without a header are rejected
+        let mut file = File::create(path).unwrap();
+        file.pack_all(&[0.5f64, 0.25][..]).unwrap();
+        drop(file);
+        let err = read_ehs(path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_map_ehs() {
+        let path = std::env::temp_dir().join("test_map_ehs.dat");
+        let path = path.to_str().unwrap();
+        let round_sizes = [2, 3, 0, 1];
+        let header = Header {
+            round_sizes,
+            precision: Precision::F64,
+        };
+        let mut file = File::create(path).unwrap();
+        write_header(&mut file, &header).unwrap();
+        file.pack_all(&[0.5f64, 0.25][..]).unwrap();
+        drop(file);
+
+        // the header says there are 6 equities
+        assert_eq!(map_ehs(path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        // rounds are written in any order through their maps
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let mut river = MappedRound::new(&file, &header, 3).unwrap();
+        river.write(0, &[1.0]);
+        river.flush(0, 1).unwrap();
+        let mut flop = MappedRound::new(&file, &header, 1).unwrap();
+        flop.write(1, &[0.375, 0.75]);
+        flop.write(0, &[0.125]);
+        flop.flush(0, 3).unwrap();
+        drop(river);
+        drop(flop);
+
+        let table = map_ehs(path).unwrap();
+        let expected = read_ehs(path).unwrap();
+        let rounds: Vec<Vec<f64>> =
+            vec![vec![0.5, 0.25], vec![0.125, 0.375, 0.75], vec![], vec![1.0]];
+        for i in 0..N_ROUNDS {
+            assert_eq!(table.round(i).unwrap(), &rounds[i][..]);
+            assert_eq!(expected.round(i), &rounds[i][..]);
+        }
+        assert_eq!(round_offset(&header, 3), HEADER_SIZE + 5 * 8);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_map_ehs_f32() {
+        let path = std::env::temp_dir().join("test_map_ehs_f32.dat");
+        let path = path.to_str().unwrap();
+        let header = Header {
+            round_sizes: [1, 2, 0, 1],
+            precision: Precision::F32,
+        };
+        let mut file = File::create(path).unwrap();
+        write_header(&mut file, &header).unwrap();
+        drop(file);
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        for (round, equities) in [vec![0.5], vec![0.1, 0.9], vec![], vec![1.0]]
+            .iter()
+            .enumerate()
+        {
+            let mut map = MappedRound::new(&file, &header, round).unwrap();
+            map.write(0, equities);
+        }
+        drop(file);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), HEADER_SIZE + 4 * 4);
+
+        let table = map_ehs(path).unwrap();
+        assert_eq!(*table.header(), header);
+        // f32 tables are read through get
+        assert!(table.round(1).is_none());
+        assert_eq!(table.get(0, 0), 0.5);
+        assert_eq!(table.get(1, 1), 0.9f32 as f64);
+        assert_eq!(table.get(3, 0), 1.0);
+        assert_eq!(
+            read_ehs(path).unwrap().round(1),
+            &[0.1f32 as f64, 0.9f32 as f64][..]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -3,8 +3,8 @@
 *
 * https://www.hindawi.com/journals/mpe/2014/406358/
 */
-
 // use std::cmp;
+use num_traits::{Float, NumAssign};
 
 macro_rules! min {
     ($x: expr) => ($x);
@@ -51,14 +51,14 @@ fn get_bins_1d(b: isize, bins: &mut Vec<isize>, u: isize) {
 /**
  * Computes a close linear approximation of the EMD between two one-dimensional histograms
  */
-pub fn emd_1d(p: &Vec<f32>, q: &Vec<f32>) -> f32 {
+pub fn emd_1d<F: Float + NumAssign>(p: &[F], q: &[F]) -> F {
     // normalize p and q
-    let mut p = p.clone();
-    let mut q = q.clone();
-    let p_sum = p.iter().sum::<f32>();
-    let q_sum = q.iter().sum::<f32>();
-    if p_sum == 0.0 || q_sum == 0.0 {
-        return 0.0;
+    let mut p = p.to_vec();
+    let mut q = q.to_vec();
+    let p_sum = p.iter().fold(F::zero(), |acc, x| acc + *x);
+    let q_sum = q.iter().fold(F::zero(), |acc, x| acc + *x);
+    if p_sum == F::zero() || q_sum == F::zero() {
+        return F::zero();
     }
     for i in 0..p.len() {
         p[i] /= p_sum;
@@ -66,8 +66,8 @@ pub fn emd_1d(p: &Vec<f32>, q: &Vec<f32>) -> f32 {
     }
 
     // main computation
-    let mut cost = 0.0;
-    let mut w = 0.0;
+    let mut cost = F::zero();
+    let mut w = F::zero();
 
     // corresponding bins (no cost)
     for i in 0..q.len() {
@@ -81,13 +81,16 @@ pub fn emd_1d(p: &Vec<f32>, q: &Vec<f32>) -> f32 {
     // w: 0.64 -> q.len() / 2
     // w: 0.9 -> q.len() / 4
     // y = 4.45 - 0.32
-    let mut factor = 4.45 * w - 1.5;
-    if factor < 1.0 {
-        factor = 1.0;
-    } else if factor > 4.0 {
-        factor = 4.0;
+    let mut factor = F::from(4.45).unwrap() * w - F::from(1.5).unwrap();
+    if factor < F::one() {
+        factor = F::one();
+    } else if factor > F::from(4.0).unwrap() {
+        factor = F::from(4.0).unwrap();
     }
-    let u: isize = (q.len() as f32 / factor).round() as isize;
+    let u: isize = (F::from(q.len()).unwrap() / factor)
+        .round()
+        .to_isize()
+        .unwrap();
 
     let mut b: Vec<isize> = Vec::new();
     get_bins_1d(0, &mut b, u);
@@ -96,12 +99,12 @@ pub fn emd_1d(p: &Vec<f32>, q: &Vec<f32>) -> f32 {
     // cross bin
     for i in 0..b.len() {
         for j in 0..p.len() {
-            if p[j] != 0.0 && (j as isize + b[i]) >= 0 {
+            if p[j] != F::zero() && (j as isize + b[i]) >= 0 {
                 let k = (j as isize + b[i]) as usize;
-                if k < q.len() && q[k] != 0.0 {
+                if k < q.len() && q[k] != F::zero() {
                     let mass = min!(p[j], q[k]);
                     w += mass;
-                    cost += mass * (j as f32 - k as f32).abs();
+                    cost += mass * F::from(j as isize - k as isize).unwrap().abs();
                     p[j] -= mass;
                     q[k] -= mass;
                 }
@@ -109,7 +112,7 @@ pub fn emd_1d(p: &Vec<f32>, q: &Vec<f32>) -> f32 {
         }
     }
 
-    return (cost + (1.0 - w) * u as f32).abs();
+    return (cost + (F::one() - w) * F::from(u).unwrap()).abs();
 }
 
 #[cfg(test)]
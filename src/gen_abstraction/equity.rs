@@ -0,0 +1,206 @@
+/**
+ * Equity histograms of single hands, the features the abstraction clusters
+ *
+ * cards are indexed 0->51 where index is 4 * rank + suit
+ */
+use rand::distributions::Uniform;
+use rand::Rng;
+use rust_poker::hand_evaluator::{evaluate, Hand, CARDS};
+use rust_poker::hand_range::Combo;
+
+use crate::kmeans::Histogram;
+
+/**
+ * Get index of bin in histogram
+ * bins split [0, 1] evenly and include their lower edge,
+ * 1.0 goes to the last bin instead of past it
+ * @param bins: number of bins in histogram
+ * @param value: the probability
+ */
+pub fn get_bin(value: f32, bins: usize) -> usize {
+    // the cast saturates, negative values go to the first bin
+    return ((value * bins as f32).floor() as usize).min(bins - 1);
+}
+
+/// equity of hero against one random hand on a complete board
+/// enumerates every opponent hand without a dead card, ties count half
+fn river_equity(hero: &Combo, board_mask: u64) -> f32 {
+    let board = Hand::from_bit_mask(board_mask);
+    let hero_score = evaluate(&(board + CARDS[usize::from(hero.0)] + CARDS[usize::from(hero.1)]));
+    let dead_mask = board_mask | (1u64 << hero.0) | (1u64 << hero.1);
+    // in half wins
+    let mut wins = 0u32;
+    let mut total = 0u32;
+    for a in 0..52usize {
+        if dead_mask & (1u64 << a) != 0 {
+            continue;
+        }
+        for b in (a + 1)..52 {
+            if dead_mask & (1u64 << b) != 0 {
+                continue;
+            }
+            let score = evaluate(&(board + CARDS[a] + CARDS[b]));
+            if hero_score > score {
+                wins += 2;
+            } else if hero_score == score {
+                wins += 1;
+            }
+            total += 2;
+        }
+    }
+    return wins as f32 / total as f32;
+}
+
+/**
+ * Histogram of the equity of hero over random completions of the board
+ *
+ * each rollout deals the missing board cards and bins the equity against
+ * one random hand on the complete board, raised to the power of n_opponents
+ * for multiway pots (EHS^n, card removal between opponents is ignored)
+ * the bins sum to 1, the nuts put everything in the last bin
+ *
+ * # Arguments
+ *
+ * * `hero` hole cards, the weight is ignored
+ * * `board_mask` 64 bit mask of 0, 3, 4 or 5 board cards
+ * * `n_opponents` number of random opponents, at least 1
+ * * `n_bins` number of evenly spaced bins over [0, 1]
+ * * `rollouts` number of board completions
+ * * `rng` seeded rng, the histogram only depends on its draws
+ */
+pub fn equity_histogram<R: Rng>(
+    hero: &Combo,
+    board_mask: u64,
+    n_opponents: usize,
+    n_bins: usize,
+    rollouts: usize,
+    rng: &mut R,
+) -> Histogram {
+    let hero_mask = (1u64 << hero.0) | (1u64 << hero.1);
+    if hero.0 == hero.1 || hero.0 >= 52 || hero.1 >= 52 || hero_mask & board_mask != 0 {
+        panic!("{} conflicts with the board", hero);
+    }
+    if board_mask >> 52 != 0 || board_mask.count_ones() > 5 {
+        panic!("invalid board mask {:#x}", board_mask);
+    }
+    if n_opponents == 0 || n_bins == 0 || rollouts == 0 {
+        panic!("equity_histogram needs at least one opponent, bin and rollout");
+    }
+
+    let card_dist: Uniform<usize> = Uniform::from(0..52);
+    let mut histogram = vec![0f32; n_bins];
+    for _ in 0..rollouts {
+        // fill remaining board cards
+        let mut board = board_mask;
+        while board.count_ones() < 5 {
+            let card = rng.sample(card_dist);
+            if (board | hero_mask) & (1u64 << card) == 0 {
+                board |= 1u64 << card;
+            }
+        }
+        let equity = river_equity(hero, board).powi(n_opponents as i32);
+        histogram[get_bin(equity, n_bins)] += 1.0;
+    }
+    for bin in histogram.iter_mut() {
+        *bin /= rollouts as f32;
+    }
+    return histogram;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    /// card of rank 0 (deuce) -> 12 (ace) and suit 0 -> 3
+    fn card(rank: u8, suit: u8) -> u8 {
+        return 4 * rank + suit;
+    }
+
+    /// expected equity given the bin centers
+    fn mean(histogram: &Histogram) -> f32 {
+        let n_bins = histogram.len() as f32;
+        return histogram
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i as f32 + 0.5) / n_bins * p)
+            .sum();
+    }
+
+    #[test]
+    fn test_get_bin() {
+        for bins in [1, 10, 50, 51].iter() {
+            assert_eq!(get_bin(0.0, *bins), 0);
+            assert_eq!(get_bin(1.0, *bins), bins - 1);
+        }
+        // bins include their lower edge
+        assert_eq!(get_bin(0.5, 10), 5);
+        assert_eq!(get_bin(0.49, 10), 4);
+        assert_eq!(get_bin(0.99, 10), 9);
+        assert_eq!(get_bin(0.5, 51), 25);
+        // every bin gets a value
+        let bins: Vec<usize> = (0..1000).map(|i| get_bin(i as f32 / 999.0, 50)).collect();
+        assert!((0..50).all(|b| bins.contains(&b)));
+    }
+
+    #[test]
+    fn test_equity_histogram_nuts() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        // royal flush on the flop
+        let hero = Combo(card(12, 0), card(11, 0), 100);
+        let board = (1u64 << card(10, 0)) | (1u64 << card(9, 0)) | (1u64 << card(8, 0));
+        let histogram = equity_histogram(&hero, board, 1, 10, 50, &mut rng);
+        assert_eq!(histogram[9], 1.0);
+        assert_eq!(histogram[..9].iter().sum::<f32>(), 0.0);
+    }
+
+    #[test]
+    fn test_equity_histogram_preflop() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let aces = Combo(card(12, 0), card(12, 1), 100);
+        let histogram = equity_histogram(&aces, 0, 1, 20, 200, &mut rng);
+        assert_eq!(histogram.len(), 20);
+        assert!((histogram.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        // aces have 85% equity heads up
+        assert!(
+            (mean(&histogram) - 0.85).abs() < 0.05,
+            "{}",
+            mean(&histogram)
+        );
+        // and less against two opponents
+        let multiway = equity_histogram(&aces, 0, 2, 20, 200, &mut rng);
+        assert!(mean(&multiway) < mean(&histogram) - 0.05);
+
+        // same seed, same histogram
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(equity_histogram(&aces, 0, 1, 20, 200, &mut rng), histogram);
+    }
+
+    #[test]
+    fn test_river_equity() {
+        // the board plays, every hand ties
+        let board: u64 = [
+            card(12, 0),
+            card(11, 0),
+            card(10, 0),
+            card(9, 0),
+            card(8, 0),
+        ]
+        .iter()
+        .map(|c| 1u64 << c)
+        .sum();
+        assert_eq!(
+            river_equity(&Combo(card(0, 1), card(1, 2), 100), board),
+            0.5
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with the board")]
+    fn test_equity_histogram_conflict() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let hero = Combo(card(12, 0), card(11, 0), 100);
+        equity_histogram(&hero, 1u64 << card(12, 0), 1, 10, 1, &mut rng);
+    }
+}
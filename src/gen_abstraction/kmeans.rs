@@ -1,92 +1,671 @@
 use crossbeam::atomic::AtomicCell;
 
 use std::cmp::Ordering::Equal;
-use std::collections::HashSet;
+use std::fmt::{self, Debug, Display};
+use std::fs::File;
 use std::io;
-use std::io::Write;
-use std::sync::Arc;
+use std::io::{BufReader, BufWriter, Write};
+use std::iter::Sum;
 use std::time::Instant;
 
+use bytepack::{LEPacker, LEUnpacker, Packed};
+use num_traits::{Float, NumAssign};
+
 use crate::rand::prelude::SliceRandom;
 use rand::distributions::{Distribution, Uniform, WeightedIndex};
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 use std::cmp::Ordering;
 
-use crate::Histogram;
+use crate::emd::emd_1d;
+use crate::sparse::{self, DenseCenter, SparseHistogram};
+#[cfg(feature = "f16")]
+use half::f16;
 
 const N_THREADS: usize = 16;
 
-macro_rules! max {
-    ($x: expr) => ($x);
-    ($x: expr, $($z: expr),+) => {{
-        let y = max!($($z),*);
-        if $x > y {
-            $x
-        } else {
-            y
+/// A histogram of equities over evenly spaced bins, e.g. 50 bins of width 0.02
+/// bins sum to 1, see normalize
+pub type Histogram = Vec<f32>;
+/// A histogram in half precision, see Kmeans::fit_half
+#[cfg(feature = "f16")]
+pub type HalfHistogram = Vec<f16>;
+
+/// Distance between two histograms used by Kmeans
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    /// l2_dist
+    L2,
+    /// l1_dist
+    L1,
+    /// emd_dist, exact one-dimensional EMD
+    Emd,
+    /// emd::emd_1d, linear approximation of EMD
+    EmdApprox,
+    /// cosine_dist, compares shapes independent of magnitude
+    Cosine,
+    /// kl_dist, symmetrized Kullback-Leibler divergence
+    KL,
+    /// chi_squared_dist
+    ChiSquared,
+    /// js_dist, for histograms over unordered buckets
+    JensenShannon,
+}
+
+impl DistanceMetric {
+    pub fn compute<F: Element>(&self, a: &[F], b: &[F]) -> F {
+        return match self {
+            DistanceMetric::L2 => l2_dist(a, b),
+            DistanceMetric::L1 => l1_dist(a, b),
+            DistanceMetric::Emd => emd_dist(a, b),
+            DistanceMetric::EmdApprox => emd_1d(a, b),
+            DistanceMetric::Cosine => cosine_dist(a, b),
+            DistanceMetric::KL => kl_dist(a, b),
+            DistanceMetric::ChiSquared => chi_squared_dist(a, b),
+            DistanceMetric::JensenShannon => js_dist(a, b),
+        };
+    }
+
+    /// true if the distance satisfies the triangle inequality
+    /// which predict needs to skip centers
+    pub fn is_metric(&self) -> bool {
+        return matches!(
+            self,
+            DistanceMetric::L2
+                | DistanceMetric::L1
+                | DistanceMetric::Emd
+                | DistanceMetric::JensenShannon
+        );
+    }
+}
+
+/// Element type of the histograms Kmeans clusters
+/// f32 is the default, f64 avoids drift when accumulating centers
+/// over millions of items
+pub trait Element: Float + NumAssign + Sum + Send + Sync + Debug + Display + 'static {}
+
+impl<T: Float + NumAssign + Sum + Send + Sync + Debug + Display + 'static> Element for T {}
+
+/// A dataset that is read one histogram at a time, e.g. from a mapped file,
+/// so that datasets larger than memory can be clustered, see Kmeans::fit_streaming
+pub trait HistogramSource<F>: Sync {
+    /// number of histograms
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// number of bins of every histogram
+    fn n_bins(&self) -> usize;
+
+    /// copies histogram i into out, which has n_bins elements
+    fn read(&self, i: usize, out: &mut [F]);
+}
+
+impl<F: Element> HistogramSource<F> for [Vec<F>] {
+    fn len(&self) -> usize {
+        return <[Vec<F>]>::len(self);
+    }
+
+    fn n_bins(&self) -> usize {
+        return self.first().map_or(0, |h| h.len());
+    }
+
+    fn read(&self, i: usize, out: &mut [F]) {
+        out.copy_from_slice(&self[i]);
+    }
+}
+
+/// default stop condition for fit
+/// fraction of items that changed cluster in an iteration
+const EPSILON: f32 = 0.005;
+
+/// Stop conditions for Kmeans::fit_with_opts
+#[derive(Debug, Clone)]
+pub struct FitOptions {
+    /// stop once at most this fraction of items changed cluster
+    pub epsilon: f32,
+    /// stop after this many iterations even if not converged
+    pub max_iters: usize,
+    /// stop once the fraction of items that changed cluster hasn't dropped
+    /// by more than `min_improvement` below its lowest value for this many
+    /// iterations, usize::MAX never stops early
+    pub patience: usize,
+    pub min_improvement: f32,
+    /// scan the dataset for NaN and infinite bins before fitting,
+    /// they would otherwise silently corrupt the assignments, see check_finite
+    pub check_finite: bool,
+}
+
+impl Default for FitOptions {
+    fn default() -> Self {
+        FitOptions {
+            epsilon: EPSILON,
+            max_iters: usize::MAX,
+            patience: 20,
+            min_improvement: 1e-4,
+            check_finite: false,
         }
-    }}
+    }
+}
+
+/// Why a fit stopped, see FitReport
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// at most epsilon of the items changed cluster
+    Converged,
+    /// ran max_iters iterations
+    MaxIters,
+    /// the items kept changing cluster without getting closer to epsilon,
+    /// e.g. a few items oscillating between two centers
+    Stalled,
 }
 
-macro_rules! min {
-    ($x: expr) => ($x);
-    ($x: expr, $($z: expr),+) => {{
-        let y = min!($($z),*);
-        if $x < y {
-            $x
+/// lowest fraction of changed items so far, for FitOptions::patience
+struct StallTracker {
+    best: f32,
+    since_best: usize,
+}
+
+impl StallTracker {
+    fn new() -> StallTracker {
+        return StallTracker {
+            best: f32::INFINITY,
+            since_best: 0,
+        };
+    }
+
+    /// true once changed hasn't improved on the best for opts.patience iterations
+    fn stalled(&mut self, changed: f32, opts: &FitOptions) -> bool {
+        if changed < self.best - opts.min_improvement {
+            self.best = changed;
+            self.since_best = 0;
         } else {
-            y
+            self.since_best += 1;
+        }
+        return self.since_best >= opts.patience;
+    }
+}
+
+/// estimates the iterations left until the fraction of changed items drops
+/// to epsilon for the verbose output of fit, assuming it keeps decreasing
+/// by the same ratio each iteration, which most datasets roughly do
+struct ConvergenceEstimate {
+    /// fraction of changed items of the previous iteration
+    previous: f32,
+    /// mean of the logs of the ratios between consecutive fractions,
+    /// recent iterations weigh more
+    log_rate: Option<f32>,
+}
+
+impl ConvergenceEstimate {
+    fn new() -> ConvergenceEstimate {
+        return ConvergenceEstimate {
+            previous: 0.0,
+            log_rate: None,
+        };
+    }
+
+    /// adds the fraction of changed items of an iteration, returns the
+    /// estimated iterations left or None while the fraction isn't decreasing
+    fn update(&mut self, changed: f32, epsilon: f32) -> Option<usize> {
+        if self.previous > 0.0 && changed > 0.0 {
+            let log_ratio = (changed / self.previous).ln();
+            self.log_rate = Some(match self.log_rate {
+                Some(rate) => 0.5 * rate + 0.5 * log_ratio,
+                None => log_ratio,
+            });
+        }
+        self.previous = changed;
+        if changed <= epsilon {
+            return Some(0);
         }
-    }}
+        return match self.log_rate {
+            Some(rate) if rate < 0.0 => Some(((epsilon / changed).ln() / rate).ceil() as usize),
+            _ => None,
+        };
+    }
+}
+
+/// Spread of the items assigned to a center, see Kmeans::cluster_stats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterStat<F = f32> {
+    /// number of items in the cluster
+    pub count: usize,
+    /// mean distance from the items to the center, 0 if empty
+    pub mean_dist: F,
+    /// distance from the furthest item to the center, 0 if empty
+    pub max_dist: F,
+}
+
+/// Summary of a Kmeans fit
+#[derive(Debug, Clone, Copy)]
+pub struct FitReport<F = f32> {
+    /// number of iterations that were run
+    pub iterations: usize,
+    /// wall clock time of the fit
+    pub millis: u128,
+    /// sum of distances from each item to its center
+    pub inertia: F,
+    pub stop: StopReason,
+}
+
+/// Invalid input to the fits, inits and predicts of Kmeans
+#[derive(Debug, Clone, PartialEq)]
+pub enum KmeansError {
+    /// the dataset has no histograms
+    DatasetEmpty,
+    /// histogram `index` has `got` bins instead of the `expected` bins
+    /// of the centers, or of the first histogram when there are no centers yet
+    DimensionMismatch {
+        expected: usize,
+        got: usize,
+        index: usize,
+    },
+    /// k is 0 or larger than the number of histograms
+    TooManyCenters { n_centers: usize, n_data: usize },
+    /// bin `bin` of histogram `index` is NaN or infinite
+    NonFinite { index: usize, bin: usize },
+    /// the counts of model `model` given to merge have `got` entries
+    /// instead of one per center, 0 for a model that doesn't exist
+    CountsMismatch {
+        model: usize,
+        expected: usize,
+        got: usize,
+    },
 }
 
-fn print_vector(arr: &Histogram) {
-    for i in 0..arr.len() {
-        print!("{:.3} ", arr[i]);
+impl Display for KmeansError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            KmeansError::DatasetEmpty => write!(f, "kmeans needs a non empty dataset"),
+            KmeansError::DimensionMismatch {
+                expected,
+                got,
+                index,
+            } => write!(
+                f,
+                "histogram {} has {} bins, expected {}",
+                index, got, expected
+            ),
+            KmeansError::TooManyCenters { n_centers, n_data } => write!(
+                f,
+                "kmeans needs between 1 and {} centers for {} histograms, got {}",
+                n_data, n_data, n_centers
+            ),
+            KmeansError::NonFinite { index, bin } => write!(
+                f,
+                "histogram {} has a non finite value in bin {}",
+                index, bin
+            ),
+            KmeansError::CountsMismatch {
+                model,
+                expected,
+                got,
+            } => write!(
+                f,
+                "counts of model {} have {} entries, expected {}",
+                model, got, expected
+            ),
+        };
     }
-    println!("");
 }
 
-// static EPSILON: f32 = 0.01;
+impl std::error::Error for KmeansError {}
 
-pub struct Kmeans {
-    centers: Vec<Histogram>,
+pub struct Kmeans<F = f32> {
+    centers: Vec<Vec<F>>,
+    /// print progress to stdout
+    verbose: bool,
 }
 
-impl Kmeans {
+/// Kmeans over the f32 histograms of the abstraction
+pub type Kmeans32 = Kmeans<f32>;
+
+impl<F: Element> Kmeans<F> {
+    /// Creates kmeans from already known centers
+    pub fn from_centers(centers: Vec<Vec<F>>) -> Kmeans<F> {
+        Kmeans {
+            centers,
+            verbose: false,
+        }
+    }
+
+    /// Print progress of fits to stdout
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    ///
+    /// Initializes with kmeans++ from a seeded rng and fits dataset
+    /// returns the model and the cluster of each item
+    /// the same seed and dataset always give the same result: every parallel
+    /// step either computes items independently or reduces them in dataset
+    /// order, so thread scheduling never changes a sum
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` seed of the rng used by init
+    /// * `n_centers` k in k-means
+    /// * `dataset` reference to dataset
+    /// * `metric` distance between two histograms
+    ///
+    pub fn with_seed(
+        seed: u64,
+        n_centers: usize,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+    ) -> Result<(Kmeans<F>, Vec<usize>), KmeansError> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut estimator = Kmeans::init_pp(n_centers, &mut rng, metric, dataset, false)?;
+        let clusters = estimator.fit(dataset, metric)?;
+        return Ok((estimator, clusters));
+    }
+
     /// Kmeans ++ initialization
     pub fn init_pp<R: Rng>(
         n_centers: usize,
         rng: &mut R,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-        dataset: &Vec<Histogram>,
-    ) -> Self {
+        metric: DistanceMetric,
+        dataset: &Vec<Vec<F>>,
+        verbose: bool,
+    ) -> Result<Self, KmeansError> {
+        Kmeans::init_kmeans_plus_plus(n_centers, rng, metric, dataset, verbose)
+    }
+
+    ///
+    ///  Initializes the centers for k-means using k-means++ seeding
+    ///  the first center is chosen uniformly, every following center
+    ///  is sampled with probability proportional to the squared distance
+    ///  to the nearest already chosen center
+    ///
+    ///  Deterministic for a seeded rng
+    ///
+    ///  # Arguments
+    ///
+    ///  * `n_centers` k in k-means
+    ///  * `rng` seeded rng
+    ///  * `metric` distance between two histograms
+    ///  * `dataset` reference to dataset
+    ///  * `verbose` print progress to stdout
+    ///
+    pub fn init_kmeans_plus_plus<R: Rng>(
+        n_centers: usize,
+        rng: &mut R,
+        metric: DistanceMetric,
+        dataset: &Vec<Vec<F>>,
+        verbose: bool,
+    ) -> Result<Kmeans<F>, KmeansError> {
         let start = Instant::now();
 
-        println!("Initializing kmeans++ with {} centers", n_centers);
+        if verbose {
+            println!("Initializing kmeans++ with {} centers", n_centers);
+        }
 
+        check_dataset(n_centers, dataset)?;
         let n_data = dataset.len();
         // push initial center randomly
-        let mut centers: Vec<&Histogram> = Vec::with_capacity(n_centers);
+        let mut centers: Vec<&Vec<F>> = Vec::with_capacity(n_centers);
         centers.push(&dataset[rng.gen_range(0, n_data)]);
 
-        let mut min_dists = vec![f32::MAX; n_data];
+        let mut min_dists = vec![F::max_value(); n_data];
         for i in 1..n_centers {
-            print!("Center: {}/{}\r", i, n_centers);
-            io::stdout().flush().unwrap();
+            if verbose {
+                print!("Center: {}/{}\r", i, n_centers);
+                io::stdout().flush().unwrap();
+            }
 
-            update_min_dists(dist_func, &mut min_dists, dataset, &centers[i - 1]);
-            let dist = WeightedIndex::new(&min_dists).unwrap();
-            centers.push(&dataset[dist.sample(rng)]);
+            update_min_dists(metric, &mut min_dists, dataset, &centers[i - 1]);
+            let next = match WeightedIndex::new(min_dists.iter().map(|d| d.to_f64().unwrap())) {
+                Ok(dist) => dist.sample(rng),
+                // every point already lies on a center
+                Err(_) => rng.gen_range(0, n_data),
+            };
+            centers.push(&dataset[next]);
         }
 
-        println!("Done.  Took {}ms", start.elapsed().as_millis());
+        if verbose {
+            println!("Done.  Took {}ms", start.elapsed().as_millis());
+        }
 
-        Kmeans {
+        Ok(Kmeans {
             centers: centers.iter().map(|x| (*x).clone()).collect(),
+            verbose,
+        })
+    }
+
+    ///
+    ///  Initializes the centers with k-means|| (Bahmani et al., 2012)
+    ///  each round samples about `oversampling * n_centers` candidates
+    ///  in one parallel pass instead of one center per pass like kmeans++,
+    ///  the candidates weighted by the number of items closest to them
+    ///  are then reclustered down to n_centers
+    ///  close to kmeans++ quality with far fewer passes on huge datasets
+    ///
+    ///  Deterministic for a seeded rng
+    ///
+    ///  # Arguments
+    ///
+    ///  * `n_centers` k in k-means
+    ///  * `oversampling` expected candidates per round as a multiple of n_centers
+    ///  * `rounds` number of sampling rounds, ~5 is enough in practice
+    ///  * `rng` seeded rng
+    ///  * `metric` distance between two histograms
+    ///  * `dataset` reference to dataset
+    ///  * `verbose` print progress to stdout
+    ///
+    pub fn init_scalable<R: Rng>(
+        n_centers: usize,
+        oversampling: f64,
+        rounds: usize,
+        rng: &mut R,
+        metric: DistanceMetric,
+        dataset: &Vec<Vec<F>>,
+        verbose: bool,
+    ) -> Result<Kmeans<F>, KmeansError> {
+        let start = Instant::now();
+
+        if verbose {
+            println!(
+                "Initializing kmeans|| with {} centers, {} rounds",
+                n_centers, rounds
+            );
+        }
+
+        check_dataset(n_centers, dataset)?;
+        let n_data = dataset.len();
+        let mut candidates: Vec<usize> = vec![rng.gen_range(0, n_data)];
+        let mut min_dists = vec![F::max_value(); n_data];
+        update_min_dists(metric, &mut min_dists, dataset, &dataset[candidates[0]]);
+
+        let l = oversampling * n_centers as f64;
+        for r in 0..rounds {
+            let phi: f64 = min_dists.iter().map(|d| d.to_f64().unwrap()).sum();
+            // every point already lies on a candidate
+            if phi <= 0.0 {
+                break;
+            }
+            // drawn in dataset order so the sample does not depend on scheduling
+            let draws: Vec<f64> = (0..n_data).map(|_| rng.gen::<f64>()).collect();
+            let sampled: Vec<usize> = (0..n_data)
+                .filter(|i| draws[*i] < l * min_dists[*i].to_f64().unwrap() / phi)
+                .collect();
+            min_dists
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, min_dist)| {
+                    for c in sampled.iter() {
+                        let mut d = metric.compute(&dataset[i], &dataset[*c]);
+                        d = d * d;
+                        if d < *min_dist {
+                            *min_dist = d;
+                        }
+                    }
+                });
+            candidates.extend(sampled);
+            if verbose {
+                print!(
+                    "Round: {}/{}, candidates: {}\r",
+                    r + 1,
+                    rounds,
+                    candidates.len()
+                );
+                io::stdout().flush().unwrap();
+            }
+        }
+
+        // too few candidates, add the items furthest from them one at a time
+        // items at distance 0 are duplicates of a candidate and are skipped,
+        // there may be fewer distinct items than n_centers
+        let mut chosen = vec![false; n_data];
+        for c in candidates.iter() {
+            chosen[*c] = true;
+        }
+        while candidates.len() < n_centers {
+            let far = min_dists
+                .iter()
+                .enumerate()
+                .filter(|(i, d)| !chosen[*i] && **d > F::zero())
+                .fold(None, |best: Option<(usize, F)>, (i, d)| match best {
+                    Some((_, bd)) if *d <= bd => best,
+                    _ => Some((i, *d)),
+                });
+            let far = match far {
+                Some((far, _)) => far,
+                None => break,
+            };
+            chosen[far] = true;
+            candidates.push(far);
+            update_min_dists(metric, &mut min_dists, dataset, &dataset[far]);
+        }
+
+        // weight each candidate by the items closest to it
+        let pool = Kmeans::from_centers(candidates.iter().map(|c| dataset[*c].clone()).collect());
+        let nearest: Vec<usize> = dataset
+            .par_iter()
+            .map(|item| pool.nearest(item, metric).0)
+            .collect();
+        let mut weights = vec![F::zero(); candidates.len()];
+        for c in nearest.iter() {
+            weights[*c] += F::one();
+        }
+        // candidates no item is closest to are duplicates of other candidates
+        let (points, weights): (Vec<Vec<F>>, Vec<F>) = pool
+            .centers
+            .into_iter()
+            .zip(weights.into_iter())
+            .filter(|(_, w)| *w > F::zero())
+            .unzip();
+
+        let mut estimator = Kmeans::recluster(&points, &weights, n_centers, metric, rng)?;
+        estimator.verbose = verbose;
+
+        if verbose {
+            println!(
+                "Done.  Took {}ms, {} candidates",
+                start.elapsed().as_millis(),
+                points.len()
+            );
+        }
+
+        return Ok(estimator);
+    }
+
+    /// weighted kmeans++ on points followed by a weighted fit
+    /// weights are positive
+    fn recluster<R: Rng>(
+        points: &Vec<Vec<F>>,
+        weights: &[F],
+        n_centers: usize,
+        metric: DistanceMetric,
+        rng: &mut R,
+    ) -> Result<Kmeans<F>, KmeansError> {
+        let mut centers: Vec<Vec<F>> = Vec::with_capacity(n_centers);
+        let first = WeightedIndex::new(weights.iter().map(|w| w.to_f64().unwrap()))
+            .unwrap()
+            .sample(rng);
+        centers.push(points[first].clone());
+        let mut cand_dists = vec![F::max_value(); points.len()];
+        for i in 1..n_centers {
+            update_min_dists(metric, &mut cand_dists, points, &centers[i - 1]);
+            let next = match WeightedIndex::new(
+                cand_dists
+                    .iter()
+                    .zip(weights.iter())
+                    .map(|(d, w)| (*d * *w).to_f64().unwrap()),
+            ) {
+                Ok(dist) => dist.sample(rng),
+                // every candidate already lies on a center
+                Err(_) => rng.gen_range(0, points.len()),
+            };
+            centers.push(points[next].clone());
+        }
+
+        let mut estimator = Kmeans::from_centers(centers);
+        if points.len() > n_centers {
+            estimator.fit_weighted(points, weights, metric)?;
         }
+        return Ok(estimator);
+    }
+
+    ///
+    /// Merges models trained on shards of a dataset into one model of
+    /// target_k centers by clustering the pooled centers of all models,
+    /// each center weighted by the number of items of its cluster if counts are given
+    ///
+    /// every worker only has to fit its shard, but the merged centers are
+    /// means of shard centers, not of items: items near cluster borders were
+    /// assigned within their shard only, a cluster that is small in every shard
+    /// can be absorbed by a larger neighbour, and without counts a center of
+    /// 10 items pulls as hard as one of 10 million
+    /// the inertia is usually a little worse than a fit of the whole dataset,
+    /// refine the merged model with a few fit iterations when the dataset fits
+    ///
+    /// # Arguments
+    ///
+    /// * `models` models fit on the shards, with the same number of bins
+    /// * `counts` items of each center of each model, e.g. counted from the
+    ///   clusters fit returns, centers without items are dropped,
+    ///   fails with CountsMismatch unless there is one per center
+    /// * `target_k` number of centers of the merged model
+    /// * `metric` distance between two histograms
+    /// * `rng` seeded rng of the kmeans++ init
+    ///
+    pub fn merge<R: Rng>(
+        models: &[Kmeans<F>],
+        counts: Option<&[Vec<F>]>,
+        target_k: usize,
+        metric: DistanceMetric,
+        rng: &mut R,
+    ) -> Result<Kmeans<F>, KmeansError> {
+        let points: Vec<Vec<F>> = models.iter().flat_map(|m| m.centers.clone()).collect();
+        let weights: Vec<F> = match counts {
+            Some(counts) => {
+                for model in 0..counts.len().max(models.len()) {
+                    let expected = models.get(model).map_or(0, |m| m.n_centers());
+                    let got = counts.get(model).map_or(0, |c| c.len());
+                    if got != expected {
+                        return Err(KmeansError::CountsMismatch {
+                            model,
+                            expected,
+                            got,
+                        });
+                    }
+                }
+                counts.concat()
+            }
+            None => vec![F::one(); points.len()],
+        };
+        let (points, weights): (Vec<Vec<F>>, Vec<F>) = points
+            .into_iter()
+            .zip(weights)
+            .filter(|(_, w)| *w > F::zero())
+            .unzip();
+        check_dataset(target_k, &points)?;
+        return Kmeans::recluster(&points, &weights, target_k, metric, rng);
     }
 
     ///  
@@ -100,20 +679,25 @@ impl Kmeans {
     ///  * `center` k means to return
     ///  * `dataset` reference to dataset
     ///  * `rng` seeded rng
+    ///  * `verbose` print progress to stdout
     ///  
     pub fn init_random<R: Rng>(
         n_restarts: usize,
         n_centers: usize,
         rng: &mut R,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-        dataset: &Vec<Histogram>,
-    ) -> Kmeans {
+        metric: DistanceMetric,
+        dataset: &Vec<Vec<F>>,
+        verbose: bool,
+    ) -> Result<Kmeans<F>, KmeansError> {
         let start = Instant::now();
 
-        println!("Initializing Kmeans with {} random restarts", n_restarts);
+        if verbose {
+            println!("Initializing Kmeans with {} random restarts", n_restarts);
+        }
 
+        check_dataset(n_centers, dataset)?;
         // create n centers to choose from
-        let mut centers: Vec<Vec<&Histogram>> = Vec::with_capacity(n_restarts);
+        let mut centers: Vec<Vec<&Vec<F>>> = Vec::with_capacity(n_restarts);
         // init centers randomly
         for _ in 0..n_restarts {
             // random init
@@ -122,30 +706,32 @@ impl Kmeans {
 
         // calculate total dists of each restart
         let iteration = AtomicCell::new(0usize);
-        let mut cluster_dists: Vec<f32> = vec![0f32; n_restarts];
+        let mut cluster_dists: Vec<F> = vec![F::zero(); n_restarts];
         cluster_dists
             .par_iter_mut()
             .enumerate()
             .for_each(|(r, cd)| {
                 let cur_iter = iteration.fetch_add(1);
-                print!("Restart: {}/{}\r", cur_iter, n_restarts);
-                io::stdout().flush().unwrap();
+                if verbose {
+                    print!("Restart: {}/{}\r", cur_iter, n_restarts);
+                    io::stdout().flush().unwrap();
+                }
 
-                let mut sum = 0f32;
+                let mut sum = F::zero();
                 let mut count = 0usize;
-                let mut distances = vec![0f32; n_centers];
+                let mut distances = vec![F::zero(); n_centers];
                 for i in 0..n_centers {
                     for j in 0..n_centers {
                         if j == i {
                             continue;
                         }
-                        let dist = dist_func(&centers[r][i], &centers[r][j]);
+                        let dist = metric.compute(&centers[r][i], &centers[r][j]);
                         distances[i] += dist;
                         count += 1;
                     }
                     sum += distances[i];
                 }
-                *cd = sum / count as f32;
+                *cd = sum / F::from(count).unwrap();
             });
 
         // get max index
@@ -157,70 +743,293 @@ impl Kmeans {
             .map(|(i, _)| i)
             .unwrap();
 
-        println!("Done.  Took {}ms", start.elapsed().as_millis());
+        if verbose {
+            println!("Done.  Took {}ms", start.elapsed().as_millis());
+        }
 
         // clone center to return
-        Kmeans {
+        Ok(Kmeans {
             centers: centers[max_cluster].iter().map(|x| (*x).clone()).collect(),
+            verbose,
+        })
+    }
+
+    ///
+    /// Saves the centers to a binary file
+    /// layout (little endian): n_centers: u32, n_bins: u32,
+    /// followed by the centers as a flat array of F
+    ///
+    pub fn save(&self, path: &str) -> io::Result<()>
+    where
+        F: Packed,
+    {
+        let mut file = BufWriter::new(File::create(path)?);
+        let n_bins = self.centers.first().map_or(0, |c| c.len());
+        file.pack(self.centers.len() as u32)?;
+        file.pack(n_bins as u32)?;
+        for center in &self.centers {
+            file.pack_all(&center[..])?;
+        }
+        return file.flush();
+    }
+
+    /// Loads centers saved with Kmeans::save
+    /// fails with InvalidData if the file size doesn't match its header,
+    /// before allocating the centers
+    pub fn load(path: &str) -> io::Result<Kmeans<F>>
+    where
+        F: Packed,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+        let len = file.get_ref().metadata()?.len();
+        let n_centers: u32 = file.unpack()?;
+        let n_bins: u32 = file.unpack()?;
+        let size = 8 + n_centers as u128 * n_bins as u128 * std::mem::size_of::<F>() as u128;
+        if size != len as u128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has {} bytes, expected {} for {} centers of {} bins",
+                    path, len, size, n_centers, n_bins
+                ),
+            ));
         }
+        let mut centers: Vec<Vec<F>> = Vec::with_capacity(n_centers as usize);
+        for _ in 0..n_centers {
+            let mut center = vec![F::zero(); n_bins as usize];
+            file.unpack_exact(&mut center[..])?;
+            centers.push(center);
+        }
+        return Ok(Kmeans::from_centers(centers));
+    }
+
+    /// The trained centers
+    pub fn centers(&self) -> &[Vec<F>] {
+        return &self.centers;
+    }
+
+    /// k in k-means
+    pub fn n_centers(&self) -> usize {
+        return self.centers.len();
     }
 
     /**
      * Fit data to clusters
      * clusters: a mutable reference which contains the predictions
-     * returns number of clusters that have changed (used for training)
+     * returns the inertia (sum of distances from each item to its center)
+     * if two centers are equally close the lowest center index wins,
+     * the result is identical for identical inputs
+     *
+     * for metrics centers further than twice the distance to the
+     * current best center from it are skipped, they can't be closer
+     * by the triangle inequality so the result matches a full scan
+     *
+     * fails if a histogram has a different number of bins than the centers,
+     * panics if clusters doesn't have one entry per item
      */
     pub fn predict(
         &self,
-        dataset: &Vec<Histogram>,
+        dataset: &Vec<Vec<F>>,
+        clusters: &mut Vec<usize>,
+        metric: DistanceMetric,
+    ) -> Result<F, KmeansError> {
+        check_dims(dataset, self.centers[0].len())?;
+        return Ok(self.predict_unchecked(dataset, clusters, metric));
+    }
+
+    /// predict without checking the histograms, for hot paths over a
+    /// dataset that was already validated, ragged histograms give garbage
+    pub fn predict_unchecked(
+        &self,
+        dataset: &Vec<Vec<F>>,
         clusters: &mut Vec<usize>,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-    ) -> f32 {
+        metric: DistanceMetric,
+    ) -> F {
         if clusters.len() != dataset.len() {
             panic!("Cluster and dataset does not match");
         }
 
-        // number of means
-        let n_centers = self.centers.len();
-
-        // number of clusters that have changed
-        let inertia = AtomicCell::new(0f32);
+        // prefix sums of the centers are computed once per pass
+        let prepared_centers: Vec<PreparedHistogram<F>> = match metric {
+            DistanceMetric::Emd => self
+                .centers
+                .iter()
+                .map(|c| PreparedHistogram::new(c))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let center_dists = if metric.is_metric() {
+            self.center_dists(metric)
+        } else {
+            Vec::new()
+        };
 
-        clusters
+        let variances: Vec<F> = clusters
             .par_iter_mut()
             .enumerate()
-            .for_each(|(i, cluster)| {
-                let mut variance = vec![0.0; n_centers];
-                let mut min_cluster = 0;
-                variance[0] = dist_func(&dataset[i], &self.centers[0]);
-                let mut min_variance = variance[0];
-                for k in 1..n_centers {
-                    variance[k] = dist_func(&dataset[i], &self.centers[k]);
-                    if variance[k] < min_variance {
-                        min_variance = variance[k];
-                        min_cluster = k;
+            .map(|(i, cluster)| {
+                let (min_cluster, min_variance) = match metric {
+                    DistanceMetric::Emd => {
+                        let item = PreparedHistogram::new(&dataset[i]);
+                        nearest_pruned(&center_dists, |k| {
+                            prepared_emd_dist(&item, &prepared_centers[k])
+                        })
                     }
-                }
+                    _ if metric.is_metric() => nearest_pruned(&center_dists, |k| {
+                        metric.compute(&dataset[i], &self.centers[k])
+                    }),
+                    _ => self.nearest(&dataset[i], metric),
+                };
+                *cluster = min_cluster;
+                min_variance
+            })
+            .collect();
 
-                inertia.store(inertia.load() + variance[min_cluster]);
+        // sum in dataset order so the result does not depend on scheduling
+        return variances.iter().copied().sum();
+    }
 
-                *cluster = min_cluster;
-            });
+    /// Returns the index of the center closest to item
+    /// if two centers are equally close the lowest center index wins
+    pub fn predict_one(&self, item: &[F], metric: DistanceMetric) -> usize {
+        return self.nearest(item, metric).0;
+    }
+
+    ///
+    /// Builds an index over the current centers for predict_one_approx
+    /// build it once after fitting, it keeps a copy of the centers
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` distance between two histograms
+    /// * `n_directions` number of random directions the centers are sorted on,
+    ///   at least 1
+    /// * `n_candidates` centers measured per direction, more is slower
+    ///   with fewer misses, at least n_centers makes lookups exact
+    /// * `rng` seeded rng for the directions
+    ///
+    pub fn build_index<R: Rng>(
+        &self,
+        metric: DistanceMetric,
+        n_directions: usize,
+        n_candidates: usize,
+        rng: &mut R,
+    ) -> CenterIndex<F> {
+        let n_bins = self.centers[0].len();
+        let directions: Vec<Vec<F>> = (0..n_directions.max(1))
+            .map(|_| {
+                (0..n_bins)
+                    .map(|_| F::from(rng.gen_range(-1.0, 1.0)).unwrap())
+                    .collect()
+            })
+            .collect();
+        let sorted = directions
+            .iter()
+            .map(|direction| {
+                let mut projections: Vec<(F, usize)> = self
+                    .centers
+                    .iter()
+                    .enumerate()
+                    .map(|(j, c)| (project(c, direction), j))
+                    .collect();
+                projections.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Equal));
+                projections
+            })
+            .collect();
+        return CenterIndex {
+            centers: self.centers.clone(),
+            metric,
+            directions,
+            sorted,
+            n_candidates: n_candidates.max(1),
+        };
+    }
+
+    /// Returns the k centers closest to item with their distances, nearest first
+    /// for soft bucketing and for spotting items on a bucket boundary
+    /// keeps a sorted list of the k best so small k doesn't sort every center
+    /// equally close centers are ordered by index, returns every center if k is larger
+    pub fn predict_topk(&self, item: &[F], k: usize, metric: DistanceMetric) -> Vec<(usize, F)> {
+        let k = k.min(self.centers.len());
+        let mut nearest: Vec<(usize, F)> = Vec::with_capacity(k + 1);
+        if k == 0 {
+            return nearest;
+        }
+        for (j, center) in self.centers.iter().enumerate() {
+            let dist = metric.compute(item, center);
+            if nearest.len() == k && dist >= nearest[k - 1].1 {
+                continue;
+            }
+            // after any center at the same distance
+            let pos = nearest
+                .iter()
+                .position(|(_, d)| dist < *d)
+                .unwrap_or(nearest.len());
+            nearest.insert(pos, (j, dist));
+            nearest.truncate(k);
+        }
+        return nearest;
+    }
 
-        return inertia.load();
+    /// Returns the dataset indices assigned to each center
+    /// buckets[k] holds the items predict assigns to center k in dataset order
+    pub fn assign_buckets(
+        &self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+    ) -> Result<Vec<Vec<usize>>, KmeansError> {
+        let mut clusters = vec![0usize; dataset.len()];
+        self.predict(dataset, &mut clusters, metric)?;
+        let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); self.centers.len()];
+        for (i, c) in clusters.iter().enumerate() {
+            buckets[*c].push(i);
+        }
+        return Ok(buckets);
+    }
+
+    /// fails on an empty dataset or naming the first histogram whose
+    /// length differs from the centers, returns the number of bins
+    fn check_bins(&self, dataset: &Vec<Vec<F>>) -> Result<usize, KmeansError> {
+        if dataset.is_empty() {
+            return Err(KmeansError::DatasetEmpty);
+        }
+        let n_bins = self.centers[0].len();
+        check_dims(dataset, n_bins)?;
+        return Ok(n_bins);
+    }
+
+    /// distances between every pair of centers
+    fn center_dists(&self, metric: DistanceMetric) -> Vec<Vec<F>> {
+        return self
+            .centers
+            .par_iter()
+            .map(|a| self.centers.iter().map(|b| metric.compute(a, b)).collect())
+            .collect();
+    }
+
+    /// returns the closest center to item and its distance
+    fn nearest(&self, item: &[F], metric: DistanceMetric) -> (usize, F) {
+        let mut min_cluster = 0;
+        let mut min_variance = metric.compute(item, &self.centers[0]);
+        for k in 1..self.centers.len() {
+            let variance = metric.compute(item, &self.centers[k]);
+            if variance < min_variance {
+                min_variance = variance;
+                min_cluster = k;
+            }
+        }
+        return (min_cluster, min_variance);
     }
 
     fn assignment_with_bounds(
         &mut self,
-        dataset: &Vec<&Histogram>,
-        s: &Vec<f32>,
+        dataset: &Vec<&Vec<F>>,
+        s: &Vec<F>,
         clusters: &mut Vec<usize>,
-        bounds: &mut Vec<(f32, f32)>,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
+        bounds: &mut Vec<(F, F)>,
+        metric: DistanceMetric,
     ) {
         let k = s.len();
-        let clen  = clusters.len();
-        println!("{}", clen);
         clusters
             .par_iter_mut()
             .zip(bounds.par_iter_mut())
@@ -231,19 +1040,19 @@ impl Kmeans {
                 if bi.1 <= upper_comp_bound {
                     return;
                 }
-                let mut u2 = dist_func(&dataset[i], &self.centers[min_cluster]);
+                let mut u2 = metric.compute(&dataset[i], &self.centers[min_cluster]);
                 bi.1 = u2;
                 if bi.1 <= upper_comp_bound {
                     return;
                 }
                 // update lower bound by looking at all other centers
-                let mut l2 = f32::MAX;
+                let mut l2 = F::max_value();
                 for j in 0..k {
                     if j == min_cluster {
                         continue;
                     }
 
-                    let dist2 = dist_func(&dataset[i], &self.centers[j]);
+                    let dist2 = metric.compute(&dataset[i], &self.centers[j]);
 
                     if dist2 < u2 {
                         l2 = u2;
@@ -262,103 +1071,336 @@ impl Kmeans {
             });
     }
 
-    fn init_s(
-        &self,
-        s: &mut Vec<f32>,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-    ) {
+    fn init_s(&self, s: &mut Vec<F>, metric: DistanceMetric) {
         let k = s.len();
         s.par_iter_mut().enumerate().for_each(|(i, s)| {
             for j in 0..k {
                 if i == j {
                     continue;
                 }
-                let d = dist_func(&self.centers[i], &self.centers[j]);
+                let d = metric.compute(&self.centers[i], &self.centers[j]);
                 if d < *s {
                     *s = d;
                 }
             }
-            *s /= 2.0;
+            *s /= F::from(2.0).unwrap();
         });
     }
 
+    /// reassigns each item to its closest center
+    /// skipping items whose bounds prove the assignment can't change
+    /// returns the number of items that changed cluster
+    /// reassign_accumulate is used by the fits, this is kept to test it against
+    #[cfg(test)]
     fn reassign_clusters(
         &mut self,
-        dataset: &Vec<Histogram>,
-        s: &Vec<f32>,
+        dataset: &Vec<Vec<F>>,
+        s: &Vec<F>,
         clusters: &mut Vec<usize>,
-        bounds: &mut Vec<(f32, f32)>,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-    ) {
-        let k = s.len();
+        bounds: &mut Vec<(F, F)>,
+        metric: DistanceMetric,
+    ) -> usize {
         clusters
             .par_iter_mut()
             .zip(bounds.par_iter_mut())
             .enumerate()
-            .for_each(|(i, (ci, bi))| {
-                let mut min_cluster = *ci;
-                let upper_comp_bound = s[min_cluster].max(bi.0);
-                if bi.1 <= upper_comp_bound {
-                    return;
-                }
-                let mut u2 = dist_func(&dataset[i], &self.centers[min_cluster]);
-                bi.1 = u2;
-                if bi.1 <= upper_comp_bound {
-                    return;
-                }
-                // update lower bound by looking at all other centers
-                let mut l2 = f32::MAX;
-                for j in 0..k {
-                    if j == min_cluster {
-                        continue;
-                    }
-
-                    let dist2 = dist_func(&dataset[i], &self.centers[j]);
+            .map(|(i, (ci, bi))| self.reassign_one(&dataset[i], s, ci, bi, metric) as usize)
+            .sum()
+    }
 
-                    if dist2 < u2 {
-                        l2 = u2;
-                        u2 = dist2;
-                        min_cluster = j;
-                    } else if dist2 < l2 {
-                        l2 = dist2;
+    ///
+    /// reassign_clusters fused with accumulate_clusters
+    /// each item is summed into its new cluster right after it is assigned,
+    /// so an iteration reads the dataset once instead of twice
+    ///
+    /// the dataset is split into N_THREADS fixed chunks that are summed in
+    /// parallel and merged in chunk order, the sums don't depend on scheduling
+    /// returns the number of items that changed cluster, the (weighted) member
+    /// count and the (weighted) sum of the items of each cluster
+    ///
+    fn reassign_accumulate(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        weights: Option<&[F]>,
+        s: &Vec<F>,
+        clusters: &mut Vec<usize>,
+        bounds: &mut Vec<(F, F)>,
+        metric: DistanceMetric,
+    ) -> (usize, Vec<F>, Vec<Vec<F>>) {
+        let k = s.len();
+        let n_bins = dataset[0].len();
+        let chunk_size = (dataset.len() + N_THREADS - 1) / N_THREADS;
+        let this = &*self;
+        let chunks: Vec<(usize, Vec<F>, Vec<Vec<F>>)> = clusters
+            .par_chunks_mut(chunk_size)
+            .zip(bounds.par_chunks_mut(chunk_size))
+            .enumerate()
+            .map(|(c, (cs, bs))| {
+                let mut changed = 0;
+                let mut counts = vec![F::zero(); k];
+                let mut mass = vec![vec![F::zero(); n_bins]; k];
+                for (j, (ci, bi)) in cs.iter_mut().zip(bs.iter_mut()).enumerate() {
+                    let i = c * chunk_size + j;
+                    if this.reassign_one(&dataset[i], s, ci, bi, metric) {
+                        changed += 1;
+                    }
+                    let w = weights.map_or(F::one(), |w| w[i]);
+                    counts[*ci] += w;
+                    for b in 0..n_bins {
+                        mass[*ci][b] += dataset[i][b] * w;
                     }
                 }
-                bi.0 = l2;
+                (changed, counts, mass)
+            })
+            .collect();
 
-                if *ci != min_cluster {
-                    // update assignment
-                    bi.1 = u2;
-                    *ci = min_cluster;
+        let mut changed = 0;
+        let mut counts = vec![F::zero(); k];
+        let mut mass = vec![vec![F::zero(); n_bins]; k];
+        for (chunk_changed, chunk_counts, chunk_mass) in chunks {
+            changed += chunk_changed;
+            for j in 0..k {
+                counts[j] += chunk_counts[j];
+                for b in 0..n_bins {
+                    mass[j][b] += chunk_mass[j][b];
                 }
-            });
+            }
+        }
+        return (changed, counts, mass);
     }
 
-    pub fn fit_growbatch<R: Rng>(
-        &mut self,
-        rng: &mut R,
-        dist_func: &'static (dyn Fn(&Vec<f32>, &Vec<f32>) -> f32 + Sync),
-        initial_batch_size: usize,
-        dataset: &Vec<Vec<f32>>,
-    ) {
-        println!("Starting growbatch kmeans");
-        let threshold = 0.1;
-        let stop_threshold = 10000000.0;
-        let start = Instant::now();
-        let k = self.centers.len();
+    /// moves an item to its closest center unless its bounds prove
+    /// the assignment can't change, returns true if it changed cluster
+    /// an item in cluster usize::MAX isn't assigned yet and measures every center
+    fn reassign_one(
+        &self,
+        item: &[F],
+        s: &Vec<F>,
+        ci: &mut usize,
+        bi: &mut (F, F),
+        metric: DistanceMetric,
+    ) -> bool {
+        let k = s.len();
+        let mut min_cluster = *ci;
+        let mut u2;
+        if min_cluster == usize::MAX {
+            min_cluster = 0;
+            u2 = metric.compute(item, &self.centers[0]);
+        } else {
+            let upper_comp_bound = s[min_cluster].max(bi.0);
+            if bi.1 <= upper_comp_bound {
+                return false;
+            }
+            u2 = metric.compute(item, &self.centers[min_cluster]);
+            bi.1 = u2;
+            if bi.1 <= upper_comp_bound {
+                return false;
+            }
+        }
+        // update lower bound by looking at all other centers
+        let mut l2 = F::max_value();
+        for j in 0..k {
+            if j == min_cluster {
+                continue;
+            }
+
+            let dist2 = metric.compute(item, &self.centers[j]);
+
+            if dist2 < u2 {
+                l2 = u2;
+                u2 = dist2;
+                min_cluster = j;
+            } else if dist2 < l2 {
+                l2 = dist2;
+            }
+        }
+        bi.0 = l2;
+
+        if *ci != min_cluster {
+            // update assignment
+            bi.1 = u2;
+            *ci = min_cluster;
+            return true;
+        }
+        return false;
+    }
+
+    /// gives every empty cluster the item that is farthest from its center
+    /// only takes items from clusters with more than one member
+    /// returns the number of items that changed cluster
+    fn reseed_empty_clusters(
+        &self,
+        dataset: &Vec<Vec<F>>,
+        clusters: &mut Vec<usize>,
+        bounds: &mut Vec<(F, F)>,
+        metric: DistanceMetric,
+    ) -> usize {
+        let k = self.centers.len();
+        let mut counts = vec![0usize; k];
+        for c in clusters.iter() {
+            counts[*c] += 1;
+        }
+
+        let mut changed = 0;
+        for j in 0..k {
+            if counts[j] > 0 {
+                continue;
+            }
+            let farthest = (0..dataset.len())
+                .into_par_iter()
+                .filter(|i| counts[clusters[*i]] > 1)
+                .map(|i| (i, metric.compute(&dataset[i], &self.centers[clusters[i]])))
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Equal));
+            let i = match farthest {
+                Some((i, _)) => i,
+                // not enough items to fill every cluster
+                None => break,
+            };
+            counts[clusters[i]] -= 1;
+            counts[j] += 1;
+            clusters[i] = j;
+            // center j moves onto the item
+            bounds[i] = (F::zero(), F::zero());
+            changed += 1;
+        }
+
+        return changed;
+    }
+
+    ///
+    /// Fits kmeans with mini batches (Sculley, 2010)
+    /// each iteration assigns `batch_size` random items
+    /// and moves their centers toward them with a per-center
+    /// learning rate of 1 / (items seen by center)
+    /// much faster than fit on huge datasets at a small cost in accuracy
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset` reference to dataset
+    /// * `metric` distance between two histograms
+    /// * `batch_size` number of items sampled per iteration
+    /// * `n_iters` number of iterations
+    /// * `rng` seeded rng
+    ///
+    pub fn fit_minibatch<R: Rng>(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+        batch_size: usize,
+        n_iters: usize,
+        rng: &mut R,
+    ) -> Result<(), KmeansError> {
+        self.check_bins(dataset)?;
+        return self.fit_streaming(&dataset[..], metric, batch_size, n_iters, rng);
+    }
+
+    ///
+    /// Fits kmeans with mini batches read from a histogram source
+    /// only the current batch is held in memory, so the source can be
+    /// a mapped file larger than RAM, e.g. the river histograms
+    /// gives the same centers as fit_minibatch on the same items and rng
+    ///
+    /// # Arguments
+    ///
+    /// * `source` histograms to cluster
+    /// * `metric` distance between two histograms
+    /// * `batch_size` number of items read per iteration
+    /// * `n_iters` number of iterations
+    /// * `rng` seeded rng
+    ///
+    pub fn fit_streaming<S: HistogramSource<F> + ?Sized, R: Rng>(
+        &mut self,
+        source: &S,
+        metric: DistanceMetric,
+        batch_size: usize,
+        n_iters: usize,
+        rng: &mut R,
+    ) -> Result<(), KmeansError> {
+        let start = Instant::now();
+        let k = self.centers.len();
+        if source.is_empty() {
+            return Err(KmeansError::DatasetEmpty);
+        }
         let n_bins = self.centers[0].len();
+        if source.n_bins() != n_bins {
+            return Err(KmeansError::DimensionMismatch {
+                expected: n_bins,
+                got: source.n_bins(),
+                index: 0,
+            });
+        }
+        let item_dist = Uniform::from(0..source.len());
+
+        if self.verbose {
+            println!("Fitting {} centers with mini batches of {}", k, batch_size);
+        }
+
+        // number of items each center has seen
+        let mut center_counts = vec![F::zero(); k];
+        let mut indices = vec![0usize; batch_size];
+        let mut batch = vec![vec![F::zero(); n_bins]; batch_size];
+        let mut assignments = vec![0usize; batch_size];
+        for t in 0..n_iters {
+            for i in indices.iter_mut() {
+                *i = item_dist.sample(rng);
+            }
+            // read and assign batch to centers before moving any of them
+            batch
+                .par_iter_mut()
+                .zip(indices.par_iter())
+                .zip(assignments.par_iter_mut())
+                .for_each(|((h, i), a)| {
+                    source.read(*i, h);
+                    *a = self.nearest(h, metric).0;
+                });
+            // gradient step
+            for (h, c) in batch.iter().zip(assignments.iter()) {
+                center_counts[*c] += F::one();
+                let eta = F::one() / center_counts[*c];
+                for j in 0..n_bins {
+                    self.centers[*c][j] = (F::one() - eta) * self.centers[*c][j] + eta * h[j];
+                }
+            }
+            if self.verbose && t & 0xff == 0 {
+                print!("iteration: {}/{}\r", t, n_iters);
+                io::stdout().flush().unwrap();
+            }
+        }
+
+        if self.verbose {
+            println!("Done.  Took: {}ms", start.elapsed().as_millis());
+        }
+        return Ok(());
+    }
+
+    pub fn fit_growbatch<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        metric: DistanceMetric,
+        initial_batch_size: usize,
+        dataset: &Vec<Vec<F>>,
+    ) -> Result<(), KmeansError> {
+        if self.verbose {
+            println!("Starting growbatch kmeans");
+        }
+        let threshold = 0.1;
+        let stop_threshold = F::from(10000000.0).unwrap();
+        let start = Instant::now();
+        let k = self.centers.len();
+        let n_bins = self.check_bins(dataset)?;
         let n_data = dataset.len();
-        let mut shuffled_data: Vec<&Vec<f32>> = dataset.iter().collect();
+        let mut shuffled_data: Vec<&Vec<F>> = dataset.iter().collect();
         shuffled_data.shuffle(rng);
         // non growing arrays
-        let mut center_movements: Vec<f32>;
-        let mut center_counts = vec![0f32; k];
-        let mut center_sums = vec![vec![0f32; n_bins]; k];
-        let mut square_dist_sum = vec![0f32; k];
+        let mut center_movements: Vec<F>;
+        let mut center_counts = vec![F::zero(); k];
+        let mut center_sums = vec![vec![F::zero(); n_bins]; k];
+        let mut square_dist_sum = vec![F::zero(); k];
         // s is the dist between a cluster and the nearest cluster / 2
-        let mut s = vec![f32::MAX; k];
+        let mut s = vec![F::max_value(); k];
         // growing arrays
         let mut center_assignments: Vec<usize> = Vec::new();
-        let mut bounds: Vec<(f32, f32)> = Vec::new();
+        let mut bounds: Vec<(F, F)> = Vec::new();
 
         // loop till convergence
         let mut t = 0;
@@ -366,17 +1408,17 @@ impl Kmeans {
         let mut current_batch_idx = initial_batch_size;
         loop {
             // update iter-center distances
-            self.init_s(&mut s, dist_func);
+            self.init_s(&mut s, metric);
             // grow arrays
             for _ in last_batch_idx..current_batch_idx {
-                bounds.push((0f32, f32::MAX));
+                bounds.push((F::zero(), F::max_value()));
                 center_assignments.push(0);
             }
             // remove accumulation of old clusters
             for i in 0..k {
-                square_dist_sum[i] = 0.0;
-                center_counts[i] = 0.0;
-                center_sums[i] = vec![0.0; n_bins];
+                square_dist_sum[i] = F::zero();
+                center_counts[i] = F::zero();
+                center_sums[i] = vec![F::zero(); n_bins];
             }
             // assignment with bounds
             self.assignment_with_bounds(
@@ -384,29 +1426,24 @@ impl Kmeans {
                 &s,
                 &mut center_assignments,
                 &mut bounds,
-                dist_func,
+                metric,
             );
-            println!("DONE2");
-            for i in 0..100 {
-                print!("{} ", center_assignments[i]);
-            }
-            println!("");
             // accumulate all assignments
             for i in 0..current_batch_idx {
                 let new_a = center_assignments[i];
-                square_dist_sum[new_a] += bounds[i].1.powf(2.0);
-                center_counts[new_a] += 1.0;
+                square_dist_sum[new_a] += bounds[i].1.powi(2);
+                center_counts[new_a] += F::one();
                 for j in 0..n_bins {
                     center_sums[new_a][j] += shuffled_data[i][j];
                 }
             }
             // calculate new centers
-            let new_centers: Vec<Vec<f32>> = center_sums
+            let new_centers: Vec<Vec<F>> = center_sums
                 .iter_mut()
                 .enumerate()
                 .map(|(i, cs)| {
                     for j in 0..n_bins {
-                        if cs[j] > 0.0 && center_counts[i] > 0.0 {
+                        if cs[j] > F::zero() && center_counts[i] > F::zero() {
                             cs[j] /= center_counts[i];
                         }
                     }
@@ -417,7 +1454,7 @@ impl Kmeans {
             // calculate center movements
             center_movements = (0..k)
                 .into_par_iter()
-                .map(|i| dist_func(&new_centers[i], &self.centers[i]))
+                .map(|i| metric.compute(&new_centers[i], &self.centers[i]))
                 .collect();
 
             // update bounds
@@ -447,13 +1484,13 @@ impl Kmeans {
                 };
             });
             // get cluster std deviations
-            let std_dev: Vec<f32> = (0..k)
+            let std_dev: Vec<F> = (0..k)
                 .into_par_iter()
                 .map(|i| {
-                    if center_counts[i] <= 1.0 {
-                        f32::INFINITY
+                    if center_counts[i] <= F::one() {
+                        F::infinity()
                     } else {
-                        (square_dist_sum[i] / (center_counts[i] * (center_counts[i] - 1.0)))
+                        (square_dist_sum[i] / (center_counts[i] * (center_counts[i] - F::one())))
                             .abs()
                             .sqrt()
                     }
@@ -462,26 +1499,29 @@ impl Kmeans {
 
             let min_change = (0..k)
                 .into_par_iter()
-                .map(|i| std_dev[i] / (center_movements[i] + 1e-9))
+                .map(|i| std_dev[i] / (center_movements[i] + F::from(1e-9).unwrap()))
                 .min_by(|a, b| a.partial_cmp(b).unwrap())
                 .unwrap();
 
             self.centers = new_centers;
             last_batch_idx = current_batch_idx;
             // if min_change > threshold {
-            current_batch_idx = min!(n_data, current_batch_idx * 2);
+            current_batch_idx = n_data.min(current_batch_idx * 2);
             // }
-            let inertia = bounds.iter().map(|b| b.1).sum::<f32>() / current_batch_idx as f32;
+            let inertia =
+                bounds.iter().map(|b| b.1).sum::<F>() / F::from(current_batch_idx).unwrap();
             if min_change > stop_threshold {
-                println!(
-                    "Done.  took {}ms, batch size: {}, p: {:.3}, inertia: {:4}",
-                    start.elapsed().as_millis(),
-                    current_batch_idx,
-                    min_change,
-                    inertia
-                );
+                if self.verbose {
+                    println!(
+                        "Done.  took {}ms, batch size: {}, p: {:.3}, inertia: {:4}",
+                        start.elapsed().as_millis(),
+                        current_batch_idx,
+                        min_change,
+                        inertia
+                    );
+                }
                 break;
-            } else {
+            } else if self.verbose {
                 print!(
                     "iteration: {}, batch_size: {}, p: {:.3}, inertia: {:.4}\r",
                     t, current_batch_idx, min_change, inertia
@@ -491,50 +1531,289 @@ impl Kmeans {
             t += 1;
             break;
         }
+        return Ok(());
+    }
+
+    /// Fits kmeans to dataset with dist function
+    pub fn fit(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+    ) -> Result<Vec<usize>, KmeansError> {
+        return self.fit_with_callback(dataset, metric, |_, _, _| {});
+    }
+
+    ///
+    /// Fits kmeans to dataset with dist function like fit
+    /// and calls `callback` after every iteration
+    /// e.g. to save the centers every few iterations of a long fit
+    /// or to log the progress
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset` reference to dataset
+    /// * `metric` distance between two histograms
+    /// * `callback` called with the iteration, starting at 0, the updated
+    ///   centers and the fraction of items that changed cluster
+    ///
+    pub fn fit_with_callback<C: FnMut(usize, &[Vec<F>], f32)>(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+        mut callback: C,
+    ) -> Result<Vec<usize>, KmeansError> {
+        let (clusters, _) =
+            self.lloyd(dataset, None, metric, &FitOptions::default(), &mut callback)?;
+        return Ok(clusters);
+    }
+
+    ///
+    /// Continues fitting from the current centers
+    /// none of the fit functions reinitialize the centers, refit makes the
+    /// warm start explicit: after adding items to dataset or after
+    /// `fit_with_opts` stopped at `max_iters`
+    /// converges in a few iterations if dataset is similar to the last fit
+    ///
+    pub fn refit(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+    ) -> Result<Vec<usize>, KmeansError> {
+        return self.fit(dataset, metric);
+    }
+
+    ///
+    /// Fits kmeans to dataset with dist function
+    /// returns the clusters and a report with the iteration count,
+    /// wall clock time and final inertia
+    /// (sum of distances from each item to its center)
+    /// used to compare abstractions with different k or seeds
+    ///
+    pub fn fit_with_report(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+    ) -> Result<(Vec<usize>, FitReport<F>), KmeansError> {
+        return self.fit_with_opts(dataset, metric, &FitOptions::default());
+    }
+
+    /// Sum of distances from each item to its assigned center
+    /// the distances are computed in parallel but summed in dataset order,
+    /// a parallel sum would add them in an order that depends on the
+    /// scheduling and change the last digits between runs
+    /// this costs a vec of one distance per item and a sequential pass over it,
+    /// small next to computing the distances
+    pub fn inertia(
+        &self,
+        dataset: &Vec<Vec<F>>,
+        clusters: &Vec<usize>,
+        metric: DistanceMetric,
+    ) -> F {
+        if clusters.len() != dataset.len() {
+            panic!("Cluster and dataset does not match");
+        }
+        let dists: Vec<F> = dataset
+            .par_iter()
+            .zip(clusters.par_iter())
+            .map(|(item, cluster)| metric.compute(item, &self.centers[*cluster]))
+            .collect();
+        return dists.iter().copied().sum();
+    }
+
+    /// Spread of each cluster given the clusters from fit or predict
+    /// loose clusters with a large max_dist are candidates for splitting
+    pub fn cluster_stats(
+        &self,
+        dataset: &Vec<Vec<F>>,
+        clusters: &Vec<usize>,
+        metric: DistanceMetric,
+    ) -> Vec<ClusterStat<F>> {
+        if clusters.len() != dataset.len() {
+            panic!("Cluster and dataset does not match");
+        }
+        let dists: Vec<F> = dataset
+            .par_iter()
+            .zip(clusters.par_iter())
+            .map(|(item, cluster)| metric.compute(item, &self.centers[*cluster]))
+            .collect();
+        let mut stats = vec![
+            ClusterStat {
+                count: 0,
+                mean_dist: F::zero(),
+                max_dist: F::zero(),
+            };
+            self.centers.len()
+        ];
+        for (d, c) in dists.iter().zip(clusters.iter()) {
+            let stat = &mut stats[*c];
+            stat.count += 1;
+            // sum of distances until divided below
+            stat.mean_dist += *d;
+            if *d > stat.max_dist {
+                stat.max_dist = *d;
+            }
+        }
+        for stat in stats.iter_mut() {
+            if stat.count > 0 {
+                stat.mean_dist /= F::from(stat.count).unwrap();
+            }
+        }
+        return stats;
+    }
+
+    ///
+    /// Fits kmeans to dataset with dist function
+    /// stops once the fraction of items that changed cluster
+    /// in an iteration is at most `epsilon`
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset` reference to dataset
+    /// * `metric` distance between two histograms
+    /// * `epsilon` stop condition, larger converges faster
+    ///
+    pub fn fit_with_epsilon(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+        epsilon: f32,
+    ) -> Result<Vec<usize>, KmeansError> {
+        let opts = FitOptions {
+            epsilon,
+            ..FitOptions::default()
+        };
+        let (clusters, _) = self.fit_with_opts(dataset, metric, &opts)?;
+        return Ok(clusters);
     }
 
+    ///
     /// Fits kmeans to dataset with dist function
-    pub fn fit_regular(
+    /// returns the clusters and a report of the fit
+    /// `report.stop` tells if fit converged or stopped early
+    /// at `opts.max_iters` or on a stall
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset` reference to dataset
+    /// * `metric` distance between two histograms
+    /// * `opts` stop conditions
+    ///
+    pub fn fit_with_opts(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        metric: DistanceMetric,
+        opts: &FitOptions,
+    ) -> Result<(Vec<usize>, FitReport<F>), KmeansError> {
+        return self.lloyd(dataset, None, metric, opts, &mut |_, _, _| {});
+    }
+
+    ///
+    /// Fits kmeans to dataset where each item counts `weights[i]` times
+    /// centers are weighted means of their items and the stop condition is
+    /// the fraction of weight that changed cluster
+    /// use the number of combos of each isomorphic hand as its weight
+    ///
+    /// # Arguments
+    ///
+    /// * `dataset` reference to dataset
+    /// * `weights` positive weight of each item in dataset
+    /// * `metric` distance between two histograms
+    ///
+    pub fn fit_weighted(
         &mut self,
-        dataset: &Vec<Histogram>,
-        dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-    ) -> Vec<usize> {
+        dataset: &Vec<Vec<F>>,
+        weights: &[F],
+        metric: DistanceMetric,
+    ) -> Result<Vec<usize>, KmeansError> {
+        if weights.len() != dataset.len() {
+            panic!("Weights and dataset does not match");
+        }
+        let (clusters, _) = self.lloyd(
+            dataset,
+            Some(weights),
+            metric,
+            &FitOptions::default(),
+            &mut |_, _, _| {},
+        )?;
+        return Ok(clusters);
+    }
+
+    /// lloyd iterations with bounds (Hamerly, 2010) from the current centers
+    /// every item has weight 1 if weights is None
+    /// callback gets the iteration, centers and changed fraction after each iteration
+    fn lloyd(
+        &mut self,
+        dataset: &Vec<Vec<F>>,
+        weights: Option<&[F]>,
+        metric: DistanceMetric,
+        opts: &FitOptions,
+        callback: &mut dyn FnMut(usize, &[Vec<F>], f32),
+    ) -> Result<(Vec<usize>, FitReport<F>), KmeansError> {
         let start = Instant::now();
         let k = self.centers.len();
         let n_data = dataset.len();
-        let n_bins = dataset[0].len();
+        check_n_centers(k, n_data)?;
+        let n_bins = self.check_bins(dataset)?;
+        if opts.check_finite {
+            check_finite(dataset)?;
+        }
 
-        println!("Fitting {} centers to dataset", k);
+        if self.verbose {
+            println!("Fitting {} centers to dataset", k);
+        }
 
         let mut t: usize = 0;
-        // which cluster each item in dataset is in
-        let mut clusters: Vec<usize> = vec![0; n_data];
+        let mut stall = StallTracker::new();
+        let mut estimate = ConvergenceEstimate::new();
+        let stop;
+        // which cluster each item in dataset is in, none before the first
+        // iteration so every item counts as changed in it
+        let mut clusters: Vec<usize> = vec![usize::MAX; n_data];
         // s is the distance between a cluster and the closest cluster / 2
-        let mut s = vec![f32::MAX; k];
+        let mut s = vec![F::max_value(); k];
         // bounds for calculating current cluster
-        let mut bounds = vec![(0f32, f32::MAX); n_data];
+        let mut bounds = vec![(F::zero(), F::max_value()); n_data];
+        let total_weight = match weights {
+            Some(w) => w.iter().copied().sum::<F>().to_f32().unwrap(),
+            None => n_data as f32,
+        };
 
         loop {
+            let previous = match weights {
+                Some(_) => clusters.clone(),
+                None => Vec::new(),
+            };
             // calculate s
-            self.init_s(&mut s, dist_func);
-            self.reassign_clusters(dataset, &s, &mut clusters, &mut bounds, dist_func);
+            self.init_s(&mut s, metric);
+            let (mut changed, mut cluster_elem_counter, mut cluster_prob_mass) =
+                self.reassign_accumulate(dataset, weights, &s, &mut clusters, &mut bounds, metric);
+            let reseeded = self.reseed_empty_clusters(dataset, &mut clusters, &mut bounds, metric);
+            if reseeded > 0 {
+                // rare, sum again rather than move items between the sums
+                let (counter, mass) = accumulate_clusters(dataset, weights, &clusters, k);
+                cluster_elem_counter = counter;
+                cluster_prob_mass = mass;
+                changed += reseeded;
+            }
+            let changed_weight = match weights {
+                Some(w) => (0..n_data)
+                    .filter(|i| previous[*i] != clusters[*i])
+                    .map(|i| w[i])
+                    .sum::<F>()
+                    .to_f32()
+                    .unwrap(),
+                None => changed as f32,
+            };
+            let accuracy = changed_weight / total_weight;
             // update centers
             // calculate new means
-            let mut cluster_elem_counter: Vec<f32> = vec![0.0; k];
-            let mut cluster_prob_mass: Vec<Vec<f32>> = vec![vec![0.0; n_bins]; k];
-            for j in 0..n_data {
-                cluster_elem_counter[clusters[j]] += 1.0;
-                for k in 0..n_bins {
-                    cluster_prob_mass[clusters[j]][k] += dataset[j][k];
-                }
-            }
-            let new_centers: Vec<Vec<f32>> = cluster_prob_mass
+            let new_centers: Vec<Vec<F>> = cluster_prob_mass
                 .par_iter_mut()
                 .enumerate()
                 .map(|(j, cbm)| {
                     // calculate mean
                     for k in 0..n_bins {
-                        if cbm[k] > 0.0 {
+                        if cbm[k] > F::zero() {
                             cbm[k] /= cluster_elem_counter[j];
                         }
                     }
@@ -543,20 +1822,16 @@ impl Kmeans {
                 .collect();
 
             // get movement of each center
-            let center_movement: Vec<f32> = (0..k)
+            let center_movement: Vec<F> = (0..k)
                 .into_par_iter()
-                .map(|j| dist_func(&new_centers[j], &self.centers[j]))
+                .map(|j| metric.compute(&new_centers[j], &self.centers[j]))
                 .collect();
 
+            // a single center has no second longest movement
             let mut longest_idx = 0;
             let mut longest = center_movement[0];
-            let mut second_longest = center_movement[1];
-            if longest < second_longest {
-                longest = center_movement[1];
-                second_longest = center_movement[0];
-                longest_idx = 1;
-            }
-            for j in 2..k {
+            let mut second_longest = F::zero();
+            for j in 1..k {
                 if longest < center_movement[j] {
                     second_longest = longest;
                     longest = center_movement[j];
@@ -575,56 +1850,2349 @@ impl Kmeans {
                 };
             });
 
-            // stop if means stop moving
-            // pretty much zero
-            let inertia = bounds.iter().map(|b| b.1).sum::<f32>() / n_data as f32;
-            print!("iteration: {}, inertia: {:.4}\r", t, inertia);
-            io::stdout().flush().unwrap();
+            if self.verbose {
+                let inertia = bounds.iter().map(|b| b.1).sum::<F>() / F::from(n_data).unwrap();
+                // eta from the mean time of the iterations so far
+                let eta = match estimate.update(accuracy, opts.epsilon) {
+                    Some(left) => {
+                        let secs = start.elapsed().as_secs_f32() / (t + 1) as f32;
+                        format!("~{} iterations, eta {:.0}s", left, secs * left as f32)
+                    }
+                    None => String::from("eta unknown"),
+                };
+                print!(
+                    "iteration: {}, changed: {:.4}, inertia: {:.4}, {}    \r",
+                    t, accuracy, inertia, eta
+                );
+                io::stdout().flush().unwrap();
+            }
 
             self.centers = new_centers;
+            callback(t, &self.centers, accuracy);
             t += 1;
-            if t == 10 {
+            // stop once few items change cluster
+            if accuracy <= opts.epsilon {
+                stop = StopReason::Converged;
+                break;
+            }
+            if t >= opts.max_iters {
+                stop = StopReason::MaxIters;
+                break;
+            }
+            if stall.stalled(accuracy, opts) {
+                stop = StopReason::Stalled;
                 break;
             }
         }
 
-        let inertia = bounds.iter().map(|b| b.1).sum::<f32>() / n_data as f32;
-        println!(
-            "Done.  Took: {}ms, inertia: {}",
-            start.elapsed().as_millis(),
-            inertia
-        );
+        let inertia = match weights {
+            Some(w) => (0..n_data)
+                .map(|i| w[i] * metric.compute(&dataset[i], &self.centers[clusters[i]]))
+                .sum(),
+            None => self.inertia(dataset, &clusters, metric),
+        };
+        let report = FitReport {
+            iterations: t,
+            millis: start.elapsed().as_millis(),
+            inertia,
+            stop,
+        };
+        if self.verbose {
+            println!(
+                "Done.  Took: {}ms, iterations: {}, inertia: {}, stop: {:?}",
+                report.millis, report.iterations, report.inertia, report.stop
+            );
+        }
 
-        return clusters;
+        return Ok((clusters, report));
     }
 }
 
-// used for kmeans ++
-pub fn update_min_dists(
-    dist_func: &'static (dyn Fn(&Histogram, &Histogram) -> f32 + Sync),
-    min_dists: &mut Vec<f32>,
-    dataset: &Vec<Histogram>,
-    new_center: &Histogram,
-) {
-    min_dists
-        .par_iter_mut()
-        .enumerate()
-        .for_each(|(i, min_dist)| {
-            let mut d = dist_func(&dataset[i], new_center);
-            d = d * d;
-            if d < *min_dist {
-                *min_dist = d;
+/// Summarizes the model instead of dumping every bin of every center,
+/// the sum of each center is 1 for normalized histograms
+/// e.g. `Kmeans { k: 2, n_bins: 50, center_sums: [1.0, 0.99999994] }`
+impl<F: Element> Debug for Kmeans<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let center_sums: Vec<F> = self
+            .centers
+            .iter()
+            .map(|center| center.iter().copied().sum())
+            .collect();
+        return f
+            .debug_struct("Kmeans")
+            .field("k", &self.centers.len())
+            .field("n_bins", &self.centers.first().map_or(0, |c| c.len()))
+            .field("center_sums", &center_sums)
+            .finish();
+    }
+}
+
+///
+/// Fitting half precision datasets
+///
+/// an f16 histogram takes half the memory of an f32 one, e.g. 100 instead of
+/// 200 bytes for 50 bins, which makes the river dataset fit on smaller machines
+/// f16 keeps 11 significant bits, each bin is rounded by at most 0.05%
+/// which is well below the sampling noise of the histograms
+///
+/// the centers stay f32, each item is converted to f32 once per pass
+/// right before its distances are computed
+///
+#[cfg(feature = "f16")]
+impl Kmeans<f32> {
+    /// predict for a half precision dataset
+    pub fn predict_half(
+        &self,
+        dataset: &Vec<HalfHistogram>,
+        clusters: &mut Vec<usize>,
+        metric: DistanceMetric,
+    ) -> Result<f32, KmeansError> {
+        if clusters.len() != dataset.len() {
+            panic!("Cluster and dataset does not match");
+        }
+        check_dims(dataset, self.centers[0].len())?;
+        let prepared_centers: Vec<PreparedHistogram<f32>> = match metric {
+            DistanceMetric::Emd => self
+                .centers
+                .iter()
+                .map(|c| PreparedHistogram::new(c))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let variances: Vec<f32> = clusters
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, cluster)| {
+                let item = half_to_f32(&dataset[i]);
+                let (min_cluster, min_variance) = match metric {
+                    DistanceMetric::Emd => {
+                        nearest_prepared(&PreparedHistogram::new(&item), &prepared_centers)
+                    }
+                    _ => self.nearest(&item, metric),
+                };
+                *cluster = min_cluster;
+                min_variance
+            })
+            .collect();
+        return Ok(variances.iter().copied().sum());
+    }
+
+    /**
+     * Fits the centers to a half precision dataset with lloyd iterations
+     * unlike fit no per item bounds are kept, they would take more memory
+     * than the dataset, an empty cluster keeps its previous center
+     */
+    pub fn fit_half(
+        &mut self,
+        dataset: &Vec<HalfHistogram>,
+        metric: DistanceMetric,
+        opts: &FitOptions,
+    ) -> Result<(Vec<usize>, FitReport), KmeansError> {
+        let start = Instant::now();
+        let k = self.centers.len();
+        let n_data = dataset.len();
+        check_n_centers(k, n_data)?;
+        check_dims(dataset, self.centers[0].len())?;
+        if opts.check_finite {
+            for (index, histogram) in dataset.iter().enumerate() {
+                if let Some(bin) = histogram.iter().position(|v| !v.is_finite()) {
+                    return Err(KmeansError::NonFinite { index, bin });
+                }
             }
-        });
+        }
+
+        let mut clusters = vec![usize::MAX; n_data];
+        let mut next = vec![0usize; n_data];
+        let mut t = 0;
+        let mut inertia;
+        let mut stall = StallTracker::new();
+        let stop;
+        loop {
+            inertia = self.predict_half(dataset, &mut next, metric)?;
+            let changed = (0..n_data).filter(|i| clusters[*i] != next[*i]).count();
+            std::mem::swap(&mut clusters, &mut next);
+            let accuracy = changed as f32 / n_data as f32;
+            if t > 0 && accuracy <= opts.epsilon {
+                stop = StopReason::Converged;
+                break;
+            }
+            if t >= opts.max_iters {
+                stop = StopReason::MaxIters;
+                break;
+            }
+            if t > 0 && stall.stalled(accuracy, opts) {
+                stop = StopReason::Stalled;
+                break;
+            }
+            let (counts, masses) = accumulate_half(dataset, &clusters, k);
+            for (j, mass) in masses.into_iter().enumerate() {
+                if counts[j] > 0.0 {
+                    self.centers[j] = mass.iter().map(|m| m / counts[j]).collect();
+                }
+            }
+            t += 1;
+            if self.verbose {
+                print!(
+                    "iteration: {}, changed: {}, inertia: {:.4}\r",
+                    t, changed, inertia
+                );
+                io::stdout().flush().unwrap();
+            }
+        }
+        let report = FitReport {
+            iterations: t,
+            millis: start.elapsed().as_millis(),
+            inertia,
+            stop,
+        };
+        return Ok((clusters, report));
+    }
 }
 
-/// Computes the L2 norm distance between two histograms
-pub fn l2_dist(a: &Histogram, b: &Histogram) -> f32 {
-    let mut sum = 0f32;
-    let mut p_sum: f32;
-    for i in 0..a.len() {
-        p_sum = a[i] - b[i];
-        sum += p_sum * p_sum;
+#[cfg(feature = "f16")]
+fn half_to_f32(h: &[f16]) -> Vec<f32> {
+    return h.iter().map(|x| x.to_f32()).collect();
+}
+
+/// accumulate_clusters for half precision items, summed in f32
+#[cfg(feature = "f16")]
+fn accumulate_half(
+    dataset: &Vec<HalfHistogram>,
+    clusters: &Vec<usize>,
+    k: usize,
+) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let n_bins = dataset[0].len();
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); k];
+    for (i, c) in clusters.iter().enumerate() {
+        members[*c].push(i);
+    }
+    let counts = members.iter().map(|m| m.len() as f32).collect();
+    let masses = members
+        .par_iter()
+        .map(|m| {
+            let mut mass = vec![0f32; n_bins];
+            for i in m {
+                for j in 0..n_bins {
+                    mass[j] += dataset[*i][j].to_f32();
+                }
+            }
+            mass
+        })
+        .collect();
+    return (counts, masses);
+}
+
+///
+/// Kmeans over sparse histograms, e.g. transition histograms
+/// that only reach a few buckets of the next round
+///
+/// the centers stay dense, the L2, L1 and Emd distances only visit the
+/// stored bins of the items, other metrics expand each item to dense
+/// once per pass right before its distances are computed
+///
+impl Kmeans<f32> {
+    /// predict for a sparse dataset
+    pub fn predict_sparse(
+        &self,
+        dataset: &[SparseHistogram],
+        clusters: &mut Vec<usize>,
+        metric: DistanceMetric,
+    ) -> Result<f32, KmeansError> {
+        if clusters.len() != dataset.len() {
+            panic!("Cluster and dataset does not match");
+        }
+        check_sparse_dims(dataset, self.centers[0].len())?;
+        let centers: Vec<DenseCenter> = self.centers.iter().map(|c| DenseCenter::new(c)).collect();
+        let variances: Vec<f32> = clusters
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, cluster)| {
+                let (min_cluster, min_variance) = match metric {
+                    DistanceMetric::L2 | DistanceMetric::L1 | DistanceMetric::Emd => {
+                        nearest_sparse(&dataset[i], &centers, metric)
+                    }
+                    _ => self.nearest(&dataset[i].to_dense(), metric),
+                };
+                *cluster = min_cluster;
+                min_variance
+            })
+            .collect();
+        return Ok(variances.iter().copied().sum());
+    }
+
+    /**
+     * Fits the centers to a sparse dataset with lloyd iterations like fit_half,
+     * no per item bounds are kept, an empty cluster keeps its previous center
+     */
+    pub fn fit_sparse(
+        &mut self,
+        dataset: &[SparseHistogram],
+        metric: DistanceMetric,
+        opts: &FitOptions,
+    ) -> Result<(Vec<usize>, FitReport), KmeansError> {
+        let start = Instant::now();
+        let k = self.centers.len();
+        let n_data = dataset.len();
+        check_n_centers(k, n_data)?;
+        check_sparse_dims(dataset, self.centers[0].len())?;
+        if opts.check_finite {
+            for (index, histogram) in dataset.iter().enumerate() {
+                if let Some((bin, _)) = histogram.iter().find(|(_, v)| !v.is_finite()) {
+                    return Err(KmeansError::NonFinite { index, bin });
+                }
+            }
+        }
+
+        let mut clusters = vec![usize::MAX; n_data];
+        let mut next = vec![0usize; n_data];
+        let mut t = 0;
+        let mut inertia;
+        let mut stall = StallTracker::new();
+        let stop;
+        loop {
+            inertia = self.predict_sparse(dataset, &mut next, metric)?;
+            let changed = (0..n_data).filter(|i| clusters[*i] != next[*i]).count();
+            std::mem::swap(&mut clusters, &mut next);
+            let accuracy = changed as f32 / n_data as f32;
+            if t > 0 && accuracy <= opts.epsilon {
+                stop = StopReason::Converged;
+                break;
+            }
+            if t >= opts.max_iters {
+                stop = StopReason::MaxIters;
+                break;
+            }
+            if t > 0 && stall.stalled(accuracy, opts) {
+                stop = StopReason::Stalled;
+                break;
+            }
+            let (counts, masses) = accumulate_sparse(dataset, &clusters, k);
+            for (j, mass) in masses.into_iter().enumerate() {
+                if counts[j] > 0.0 {
+                    self.centers[j] = mass.iter().map(|m| m / counts[j]).collect();
+                }
+            }
+            t += 1;
+            if self.verbose {
+                print!(
+                    "iteration: {}, changed: {}, inertia: {:.4}\r",
+                    t, changed, inertia
+                );
+                io::stdout().flush().unwrap();
+            }
+        }
+        let report = FitReport {
+            iterations: t,
+            millis: start.elapsed().as_millis(),
+            inertia,
+            stop,
+        };
+        return Ok((clusters, report));
+    }
+}
+
+/// check_dims for sparse histograms
+fn check_sparse_dims(dataset: &[SparseHistogram], n_bins: usize) -> Result<(), KmeansError> {
+    return match dataset.iter().position(|h| h.len != n_bins) {
+        Some(index) => Err(KmeansError::DimensionMismatch {
+            expected: n_bins,
+            got: dataset[index].len,
+            index,
+        }),
+        None => Ok(()),
+    };
+}
+
+/// accumulate_clusters for sparse items, only their stored bins are added
+fn accumulate_sparse(
+    dataset: &[SparseHistogram],
+    clusters: &Vec<usize>,
+    k: usize,
+) -> (Vec<f32>, Vec<Vec<f32>>) {
+    let n_bins = dataset[0].len;
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); k];
+    for (i, c) in clusters.iter().enumerate() {
+        members[*c].push(i);
+    }
+    let counts = members.iter().map(|m| m.len() as f32).collect();
+    let masses = members
+        .par_iter()
+        .map(|m| {
+            let mut mass = vec![0f32; n_bins];
+            for i in m {
+                for (j, v) in dataset[*i].iter() {
+                    mass[j] += v;
+                }
+            }
+            mass
+        })
+        .collect();
+    return (counts, masses);
+}
+
+///
+/// Approximate nearest center lookup for bucketing at solve time
+/// see Kmeans::build_index
+///
+/// the centers are projected onto a few random directions and sorted,
+/// a lookup projects the item and only measures the centers whose
+/// projections are among the n_candidates closest to it on some direction,
+/// about n_directions * n_candidates distances instead of n_centers
+///
+/// the nearest center can be missed when it is close to the item but
+/// far from it on every direction, which is more likely with many bins,
+/// few directions or few candidates, the missed center is then replaced
+/// by one that is almost as close, so bucketing degrades gracefully
+/// projections preserve L2 distances best, other metrics miss more often
+/// measure the recall against predict_one on a sample before relying on it
+///
+pub struct CenterIndex<F = f32> {
+    centers: Vec<Vec<F>>,
+    metric: DistanceMetric,
+    directions: Vec<Vec<F>>,
+    /// (projection, center) sorted by projection for each direction
+    sorted: Vec<Vec<(F, usize)>>,
+    n_candidates: usize,
+}
+
+impl<F: Element> CenterIndex<F> {
+    /// Returns the index of a center close to item, usually the closest
+    /// ties between candidates go to the lowest center index like predict_one
+    pub fn predict_one_approx(&self, item: &[F]) -> usize {
+        let k = self.centers.len();
+        let n = self.n_candidates.min(k);
+        let mut candidates: Vec<usize> = Vec::with_capacity(n * self.sorted.len());
+        for (direction, sorted) in self.directions.iter().zip(self.sorted.iter()) {
+            let p = project(item, direction);
+            let pos = sorted.partition_point(|(v, _)| *v < p);
+            // the n projections around p
+            let start = pos.saturating_sub(n / 2).min(k - n);
+            candidates.extend(sorted[start..start + n].iter().map(|(_, j)| *j));
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        let mut nearest = candidates[0];
+        let mut min_dist = self.metric.compute(item, &self.centers[nearest]);
+        for j in candidates[1..].iter() {
+            let dist = self.metric.compute(item, &self.centers[*j]);
+            if dist < min_dist {
+                min_dist = dist;
+                nearest = *j;
+            }
+        }
+        return nearest;
+    }
+
+    pub fn n_centers(&self) -> usize {
+        return self.centers.len();
+    }
+}
+
+/// dot product of a histogram and a direction
+fn project<F: Element>(item: &[F], direction: &[F]) -> F {
+    return item
+        .iter()
+        .zip(direction.iter())
+        .map(|(a, b)| *a * *b)
+        .sum();
+}
+
+///
+/// A histogram with its prefix sums
+/// the EMD of two prepared histograms is the L1 distance of their prefix sums,
+/// preparing the centers once saves recomputing them for every item
+///
+pub struct PreparedHistogram<F = f32> {
+    cdf: Vec<F>,
+}
+
+impl<F: Element> PreparedHistogram<F> {
+    pub fn new(h: &[F]) -> PreparedHistogram<F> {
+        let mut sum = F::zero();
+        let cdf = h
+            .iter()
+            .map(|x| {
+                sum += *x;
+                sum
+            })
+            .collect();
+        return PreparedHistogram { cdf };
+    }
+
+    /// prefix sums of the histogram
+    pub fn cdf(&self) -> &[F] {
+        return &self.cdf;
+    }
+}
+
+/// Computes the Earth Mover's Distance between two prepared histograms
+/// equal to emd_dist of the histograms up to rounding
+pub fn prepared_emd_dist<F: Element>(a: &PreparedHistogram<F>, b: &PreparedHistogram<F>) -> F {
+    let mut sum = F::zero();
+    for i in 0..a.cdf.len() {
+        sum += (a.cdf[i] - b.cdf[i]).abs();
+    }
+    return sum;
+}
+
+/// returns the closest prepared center to item and its EMD
+/// if two centers are equally close the lowest center index wins
+#[cfg(feature = "f16")]
+fn nearest_prepared<F: Element>(
+    item: &PreparedHistogram<F>,
+    centers: &[PreparedHistogram<F>],
+) -> (usize, F) {
+    let mut min_cluster = 0;
+    let mut min_variance = prepared_emd_dist(item, &centers[0]);
+    for k in 1..centers.len() {
+        let variance = prepared_emd_dist(item, &centers[k]);
+        if variance < min_variance {
+            min_variance = variance;
+            min_cluster = k;
+        }
+    }
+    return (min_cluster, min_variance);
+}
+
+/// returns the closest dense center to a sparse item and its distance
+/// if two centers are equally close the lowest center index wins
+fn nearest_sparse(
+    item: &SparseHistogram,
+    centers: &[DenseCenter],
+    metric: DistanceMetric,
+) -> (usize, f32) {
+    let mut min_cluster = 0;
+    let mut min_variance = sparse::dist_dense(metric, item, &centers[0]);
+    for k in 1..centers.len() {
+        let variance = sparse::dist_dense(metric, item, &centers[k]);
+        if variance < min_variance {
+            min_variance = variance;
+            min_cluster = k;
+        }
+    }
+    return (min_cluster, min_variance);
+}
+
+/// returns the closest center and its distance given the distance
+/// from the item to each center, skips centers that are more than
+/// twice the current best distance away from the current best center
+/// if two centers are equally close the lowest center index wins
+fn nearest_pruned<F: Element, D: Fn(usize) -> F>(center_dists: &[Vec<F>], dist: D) -> (usize, F) {
+    let two = F::from(2.0).unwrap();
+    let mut min_cluster = 0;
+    let mut min_variance = dist(0);
+    for k in 1..center_dists.len() {
+        if center_dists[min_cluster][k] > two * min_variance {
+            continue;
+        }
+        let variance = dist(k);
+        if variance < min_variance {
+            min_variance = variance;
+            min_cluster = k;
+        }
+    }
+    return (min_cluster, min_variance);
+}
+
+/// fails unless n_centers distinct items can be picked from n_data items
+fn check_n_centers(n_centers: usize, n_data: usize) -> Result<(), KmeansError> {
+    if n_data == 0 {
+        return Err(KmeansError::DatasetEmpty);
+    }
+    if n_centers == 0 || n_centers > n_data {
+        return Err(KmeansError::TooManyCenters { n_centers, n_data });
+    }
+    return Ok(());
+}
+
+/// fails naming the first histogram that doesn't have n_bins bins
+fn check_dims<T>(dataset: &[Vec<T>], n_bins: usize) -> Result<(), KmeansError> {
+    return match dataset.iter().position(|h| h.len() != n_bins) {
+        Some(index) => Err(KmeansError::DimensionMismatch {
+            expected: n_bins,
+            got: dataset[index].len(),
+            index,
+        }),
+        None => Ok(()),
+    };
+}
+
+/// fails naming the first histogram and bin that is NaN or infinite
+/// distances to such a histogram are NaN, which compare as neither smaller
+/// nor larger, so it would be assigned to an arbitrary center without error
+pub fn check_finite<F: Float>(dataset: &[Vec<F>]) -> Result<(), KmeansError> {
+    for (index, histogram) in dataset.iter().enumerate() {
+        if let Some(bin) = histogram.iter().position(|v| !v.is_finite()) {
+            return Err(KmeansError::NonFinite { index, bin });
+        }
+    }
+    return Ok(());
+}
+
+/// check_n_centers for an init, all histograms need as many bins as the first
+fn check_dataset<T>(n_centers: usize, dataset: &[Vec<T>]) -> Result<(), KmeansError> {
+    check_n_centers(n_centers, dataset.len())?;
+    return check_dims(dataset, dataset[0].len());
+}
+
+/// sums the (weighted) items and the member weight of each cluster
+/// clusters are summed in parallel, members of a cluster in dataset order
+/// every item has weight 1 if weights is None
+fn accumulate_clusters<F: Element>(
+    dataset: &Vec<Vec<F>>,
+    weights: Option<&[F]>,
+    clusters: &Vec<usize>,
+    k: usize,
+) -> (Vec<F>, Vec<Vec<F>>) {
+    let n_bins = dataset[0].len();
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); k];
+    for (i, c) in clusters.iter().enumerate() {
+        members[*c].push(i);
+    }
+    let cluster_elem_counter = match weights {
+        Some(w) => members
+            .iter()
+            .map(|m| m.iter().map(|i| w[*i]).sum())
+            .collect(),
+        None => members.iter().map(|m| F::from(m.len()).unwrap()).collect(),
+    };
+    let cluster_prob_mass = members
+        .par_iter()
+        .map(|m| {
+            let mut mass = vec![F::zero(); n_bins];
+            for i in m {
+                let w = weights.map_or(F::one(), |w| w[*i]);
+                for j in 0..n_bins {
+                    mass[j] += dataset[*i][j] * w;
+                }
+            }
+            mass
+        })
+        .collect();
+    return (cluster_elem_counter, cluster_prob_mass);
+}
+
+///
+/// Computes the mean silhouette coefficient of a clustering
+/// close to 1 for dense, well separated clusters, close to 0 for
+/// overlapping ones, the full computation is O(n^2)
+///
+/// # Arguments
+///
+/// * `dataset` reference to dataset
+/// * `clusters` cluster of each item in dataset
+/// * `metric` distance between two histograms
+/// * `sample_size` estimate the score on this many random items
+/// * `rng` seeded rng, only used when sampling
+///
+pub fn silhouette_score<F: Element, R: Rng>(
+    dataset: &Vec<Vec<F>>,
+    clusters: &Vec<usize>,
+    metric: DistanceMetric,
+    sample_size: Option<usize>,
+    rng: &mut R,
+) -> F {
+    if clusters.len() != dataset.len() {
+        panic!("Cluster and dataset does not match");
+    }
+    let n_data = dataset.len();
+    let sample: Vec<usize> = match sample_size {
+        Some(n) if n < n_data => rand::seq::index::sample(rng, n_data, n).into_vec(),
+        _ => (0..n_data).collect(),
+    };
+    let k = match clusters.iter().max() {
+        Some(max) => max + 1,
+        None => return F::zero(),
+    };
+
+    let total = sample
+        .par_iter()
+        .map(|i| {
+            // sum of distances from item i to the items of each cluster
+            let mut dists = vec![F::zero(); k];
+            let mut counts = vec![0usize; k];
+            for j in sample.iter() {
+                if i == j {
+                    continue;
+                }
+                dists[clusters[*j]] += metric.compute(&dataset[*i], &dataset[*j]);
+                counts[clusters[*j]] += 1;
+            }
+            let own = clusters[*i];
+            // silhouette of items alone in their cluster is 0
+            if counts[own] == 0 {
+                return F::zero();
+            }
+            let a = dists[own] / F::from(counts[own]).unwrap();
+            let mut b = F::infinity();
+            for j in 0..k {
+                if j != own && counts[j] > 0 {
+                    b = b.min(dists[j] / F::from(counts[j]).unwrap());
+                }
+            }
+            // only one cluster
+            if b == F::infinity() || a.max(b) == F::zero() {
+                return F::zero();
+            }
+            (b - a) / a.max(b)
+        })
+        .sum::<F>();
+
+    return total / F::from(sample.len()).unwrap();
+}
+
+///
+/// Computes the gap statistic (Tibshirani et al., 2001) for each k
+/// the gap is how much lower log(inertia) is on dataset than on uniform
+/// random data over the bounding box of dataset, averaged over n_refs
+/// reference datasets, pick the smallest k after which the gap flattens
+/// fits k-means 1 + n_refs times per k, subsample large datasets first
+///
+/// # Arguments
+///
+/// * `dataset` reference to dataset
+/// * `metric` distance between two histograms
+/// * `k_range` numbers of centers to try
+/// * `n_refs` number of uniform reference datasets, shared by every k
+/// * `rng` seeded rng for the references and the kmeans++ inits
+///
+pub fn gap_statistic<F: Element, R: Rng, K: IntoIterator<Item = usize>>(
+    dataset: &Vec<Vec<F>>,
+    metric: DistanceMetric,
+    k_range: K,
+    n_refs: usize,
+    rng: &mut R,
+) -> Result<Vec<(usize, F)>, KmeansError> {
+    let n_bins = match dataset.first() {
+        Some(item) => item.len(),
+        None => return Err(KmeansError::DatasetEmpty),
+    };
+    check_dims(dataset, n_bins)?;
+    // bounding box of dataset
+    let mut lows = dataset[0].clone();
+    let mut highs = dataset[0].clone();
+    for item in dataset.iter() {
+        for j in 0..n_bins {
+            lows[j] = lows[j].min(item[j]);
+            highs[j] = highs[j].max(item[j]);
+        }
+    }
+    let refs: Vec<Vec<Vec<F>>> = (0..n_refs)
+        .map(|_| {
+            (0..dataset.len())
+                .map(|_| {
+                    (0..n_bins)
+                        .map(|j| {
+                            lows[j] + (highs[j] - lows[j]) * F::from(rng.gen::<f64>()).unwrap()
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut log_inertia = |data: &Vec<Vec<F>>, k: usize| -> Result<F, KmeansError> {
+        let mut estimator = Kmeans::init_pp(k, rng, metric, data, false)?;
+        let (_, report) = estimator.fit_with_report(data, metric)?;
+        return Ok(report.inertia.ln());
+    };
+    let mut gaps = Vec::new();
+    for k in k_range {
+        let log_w = log_inertia(dataset, k)?;
+        let mut ref_sum = F::zero();
+        for data in refs.iter() {
+            ref_sum += log_inertia(data, k)?;
+        }
+        gaps.push((k, ref_sum / F::from(n_refs).unwrap() - log_w));
+    }
+    return Ok(gaps);
+}
+
+///
+/// Draws k histograms of source uniformly at random without replacement
+/// in a single pass over source (reservoir sampling, algorithm R),
+/// only the k kept histograms are in memory
+/// fit on the sample, then predict the full dataset: centers of a large
+/// random sample are close to the centers of the whole dataset
+/// returns every histogram if source has at most k
+///
+/// # Arguments
+///
+/// * `source` histograms to sample, e.g. a mapped file
+/// * `k` size of the sample
+/// * `rng` seeded rng, the sample only depends on its draws
+///
+pub fn reservoir_sample<F: Element, S: HistogramSource<F> + ?Sized, R: Rng>(
+    source: &S,
+    k: usize,
+    rng: &mut R,
+) -> Vec<Vec<F>> {
+    let n_bins = source.n_bins();
+    let mut sample: Vec<Vec<F>> = Vec::with_capacity(k.min(source.len()));
+    for i in 0..source.len() {
+        let slot = if i < k {
+            sample.push(vec![F::zero(); n_bins]);
+            i
+        } else {
+            rng.gen_range(0, i + 1)
+        };
+        // histograms that are not kept are never read
+        if slot < k {
+            source.read(i, &mut sample[slot]);
+        }
+    }
+    return sample;
+}
+
+// used for kmeans ++
+pub fn update_min_dists<F: Element>(
+    metric: DistanceMetric,
+    min_dists: &mut Vec<F>,
+    dataset: &Vec<Vec<F>>,
+    new_center: &[F],
+) {
+    min_dists
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, min_dist)| {
+            let mut d = metric.compute(&dataset[i], new_center);
+            d = d * d;
+            if d < *min_dist {
+                *min_dist = d;
+            }
+        });
+}
+
+/// Computes the L2 norm distance between two histograms
+/// uses l2_dist_simd for f32 histograms when built with the simd feature
+pub fn l2_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    #[cfg(feature = "simd")]
+    {
+        if std::any::TypeId::of::<F>() == std::any::TypeId::of::<f32>() {
+            // F is f32, so the slices can be viewed as f32
+            let (a, b) = unsafe {
+                (
+                    &*(a as *const [F] as *const [f32]),
+                    &*(b as *const [F] as *const [f32]),
+                )
+            };
+            return F::from(l2_dist_simd(a, b)).unwrap();
+        }
+    }
+    return l2_dist_scalar(a, b);
+}
+
+/// l2_dist one bin at a time
+pub fn l2_dist_scalar<F: Element>(a: &[F], b: &[F]) -> F {
+    let mut sum = F::zero();
+    let mut p_sum: F;
+    for i in 0..a.len() {
+        p_sum = a[i] - b[i];
+        sum += p_sum * p_sum;
+    }
+    return sum.sqrt();
+}
+
+/// l2_dist eight f32 bins at a time, the remaining bins one at a time
+/// the sums are grouped differently than l2_dist_scalar,
+/// so results differ by float rounding
+#[cfg(feature = "simd")]
+pub fn l2_dist_simd(a: &[f32], b: &[f32]) -> f32 {
+    use std::convert::TryInto;
+    use wide::f32x8;
+
+    assert_eq!(a.len(), b.len());
+    let a_chunks = a.chunks_exact(8);
+    let b_chunks = b.chunks_exact(8);
+    let mut tail = 0f32;
+    for (x, y) in a_chunks.remainder().iter().zip(b_chunks.remainder()) {
+        tail += (x - y) * (x - y);
+    }
+    let mut sums = f32x8::ZERO;
+    for (x, y) in a_chunks.zip(b_chunks) {
+        let x: [f32; 8] = x.try_into().unwrap();
+        let y: [f32; 8] = y.try_into().unwrap();
+        let d = f32x8::from(x) - f32x8::from(y);
+        sums += d * d;
+    }
+    return (sums.reduce_add() + tail).sqrt();
+}
+
+/// Computes the L1 norm (manhattan) distance between two histograms
+pub fn l1_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let mut sum = F::zero();
+    for i in 0..a.len() {
+        sum += (a[i] - b[i]).abs();
+    }
+    return sum;
+}
+
+/// Computes the Earth Mover's Distance between two one-dimensional histograms
+/// by accumulating the running difference of their prefix sums
+pub fn emd_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let mut sum = F::zero();
+    let mut carry = F::zero();
+    for i in 0..a.len() {
+        carry += a[i] - b[i];
+        sum += carry.abs();
+    }
+    return sum;
+}
+
+/// Computes the cosine distance 1 - (a . b) / (|a| |b|) between two histograms
+/// returns 0 if either histogram is all zeros
+pub fn cosine_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let mut dot = F::zero();
+    let mut a_norm = F::zero();
+    let mut b_norm = F::zero();
+    for i in 0..a.len() {
+        dot += a[i] * b[i];
+        a_norm += a[i] * a[i];
+        b_norm += b[i] * b[i];
+    }
+    if a_norm == F::zero() || b_norm == F::zero() {
+        return F::zero();
+    }
+    return F::one() - dot / (a_norm.sqrt() * b_norm.sqrt());
+}
+
+/// mass added to every bin by kl_dist and chi_squared_dist so empty bins
+/// don't divide by or take the log of 0
+const SMOOTHING: f64 = 1e-6;
+
+/// adds SMOOTHING to every bin and returns the new total mass
+fn smoothed_sum<F: Element>(h: &[F]) -> F {
+    let eps = F::from(SMOOTHING).unwrap();
+    return h.iter().copied().sum::<F>() + eps * F::from(h.len()).unwrap();
+}
+
+/// Computes the symmetrized Kullback-Leibler divergence KL(a||b) + KL(b||a)
+/// of two histograms after additive smoothing
+pub fn kl_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let eps = F::from(SMOOTHING).unwrap();
+    let a_sum = smoothed_sum(a);
+    let b_sum = smoothed_sum(b);
+    let mut sum = F::zero();
+    for i in 0..a.len() {
+        let p = (a[i] + eps) / a_sum;
+        let q = (b[i] + eps) / b_sum;
+        sum += (p - q) * (p / q).ln();
+    }
+    return sum;
+}
+
+/// Computes the chi-squared distance 1/2 sum (a - b)^2 / (a + b)
+/// of two histograms after additive smoothing
+pub fn chi_squared_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let eps = F::from(SMOOTHING).unwrap();
+    let a_sum = smoothed_sum(a);
+    let b_sum = smoothed_sum(b);
+    let mut sum = F::zero();
+    for i in 0..a.len() {
+        let p = (a[i] + eps) / a_sum;
+        let q = (b[i] + eps) / b_sum;
+        sum += (p - q) * (p - q) / (p + q);
+    }
+    return sum / F::from(2.0).unwrap();
+}
+
+///
+/// Computes the Jensen-Shannon distance, the square root of
+/// 1/2 KL(p||m) + 1/2 KL(q||m) with m = (p + q) / 2
+/// where p and q are the histograms rescaled to sum to 1
+///
+/// suited to count histograms over discrete buckets such as the transition
+/// histograms over next round buckets: the bins are unordered, so the ground
+/// distance of EMD means nothing, and unlike L2 moving mass between two small
+/// bins counts as much as between two large ones
+/// 0 for identical shapes, sqrt(ln 2) for disjoint histograms,
+/// needs no smoothing and satisfies the triangle inequality
+///
+pub fn js_dist<F: Element>(a: &[F], b: &[F]) -> F {
+    let half = F::from(0.5).unwrap();
+    let a_sum = a.iter().copied().sum::<F>();
+    let b_sum = b.iter().copied().sum::<F>();
+    let mut sum = F::zero();
+    for i in 0..a.len() {
+        let p = if a_sum > F::zero() {
+            a[i] / a_sum
+        } else {
+            F::zero()
+        };
+        let q = if b_sum > F::zero() {
+            b[i] / b_sum
+        } else {
+            F::zero()
+        };
+        let m = (p + q) * half;
+        // 0 ln 0 = 0
+        if p > F::zero() {
+            sum += p * (p / m).ln();
+        }
+        if q > F::zero() {
+            sum += q * (q / m).ln();
+        }
+    }
+    return (sum * half).max(F::zero()).sqrt();
+}
+
+/// Rescales a histogram so its bins sum to 1
+/// histograms that are all zeros are left unchanged
+pub fn normalize<F: Element>(h: &mut [F]) {
+    let sum = h.iter().copied().sum::<F>();
+    if sum == F::zero() {
+        return;
+    }
+    for x in h.iter_mut() {
+        *x /= sum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERROR: f32 = 1e-6;
+
+    #[cfg(feature = "f16")]
+    #[test]
+    fn test_fit_half() {
+        let dataset: Vec<Histogram> = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.9, 0.1, 0.0],
+            vec![0.0, 0.1, 0.9],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let half: Vec<HalfHistogram> = dataset
+            .iter()
+            .map(|h| h.iter().map(|x| f16::from_f32(*x)).collect())
+            .collect();
+        for metric in [DistanceMetric::L2, DistanceMetric::Emd].iter() {
+            let mut estimator = Kmeans::from_centers(vec![dataset[1].clone(), dataset[2].clone()]);
+            let (clusters, report) = estimator
+                .fit_half(&half, *metric, &FitOptions::default())
+                .unwrap();
+            assert_eq!(clusters, vec![0, 0, 1, 1]);
+            assert!(report.iterations >= 1);
+            // centers match the f32 means up to the f16 rounding
+            let expected = [[0.95, 0.05, 0.0], [0.0, 0.05, 0.95]];
+            for (center, expected) in estimator.centers().iter().zip(expected.iter()) {
+                for (c, e) in center.iter().zip(expected.iter()) {
+                    assert!((c - e).abs() < 1e-3, "{:?}", center);
+                }
+            }
+            let mut f32_clusters = vec![0; dataset.len()];
+            let inertia = estimator
+                .predict(&dataset, &mut f32_clusters, *metric)
+                .unwrap();
+            let mut half_clusters = vec![0; dataset.len()];
+            let half_inertia = estimator
+                .predict_half(&half, &mut half_clusters, *metric)
+                .unwrap();
+            assert_eq!(half_clusters, f32_clusters);
+            assert!((inertia - half_inertia).abs() < 1e-3);
+        }
+    }
+
+    #[cfg(feature = "f16")]
+    #[test]
+    fn test_fit_half_check_finite() {
+        let mut half: Vec<HalfHistogram> = vec![
+            vec![f16::from_f32(1.0), f16::from_f32(0.0)],
+            vec![f16::from_f32(0.0), f16::from_f32(1.0)],
+        ];
+        half[1][0] = f16::NAN;
+        let opts = FitOptions {
+            check_finite: true,
+            ..FitOptions::default()
+        };
+        let mut estimator = Kmeans::from_centers(vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        let result = estimator.fit_half(&half, DistanceMetric::L2, &opts);
+        assert_eq!(
+            result.err(),
+            Some(KmeansError::NonFinite { index: 1, bin: 0 })
+        );
+        half[1][0] = f16::INFINITY;
+        let result = estimator.fit_half(&half, DistanceMetric::L2, &opts);
+        assert_eq!(
+            result.err(),
+            Some(KmeansError::NonFinite { index: 1, bin: 0 })
+        );
+    }
+
+    #[test]
+    fn test_fit_sparse() {
+        let dataset: Vec<Histogram> = vec![
+            vec![1.0, 0.0, 0.0, 0.0],
+            vec![0.9, 0.1, 0.0, 0.0],
+            vec![0.0, 0.0, 0.1, 0.9],
+            vec![0.0, 0.0, 0.0, 1.0],
+        ];
+        let sparse: Vec<SparseHistogram> = dataset
+            .iter()
+            .map(|h| SparseHistogram::from_dense(h))
+            .collect();
+        let metrics = [
+            DistanceMetric::L2,
+            DistanceMetric::L1,
+            DistanceMetric::Emd,
+            DistanceMetric::Cosine,
+        ];
+        for metric in metrics.iter() {
+            let mut estimator = Kmeans::from_centers(vec![dataset[1].clone(), dataset[2].clone()]);
+            let mut dense = Kmeans::from_centers(vec![dataset[1].clone(), dataset[2].clone()]);
+            let (clusters, report) = estimator
+                .fit_sparse(&sparse, *metric, &FitOptions::default())
+                .unwrap();
+            let dense_clusters = dense.fit(&dataset, *metric).unwrap();
+            assert_eq!(clusters, dense_clusters);
+            assert_eq!(clusters, vec![0, 0, 1, 1]);
+            for (center, expected) in estimator.centers().iter().zip(dense.centers()) {
+                for (c, e) in center.iter().zip(expected.iter()) {
+                    assert!((c - e).abs() < ERROR, "{:?} {:?}", center, expected);
+                }
+            }
+            let inertia = dense.inertia(&dataset, &clusters, *metric);
+            assert!((report.inertia - inertia).abs() < ERROR);
+        }
+
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0; 3]]);
+        assert_eq!(
+            estimator
+                .fit_sparse(&sparse, DistanceMetric::L2, &FitOptions::default())
+                .err(),
+            Some(KmeansError::DimensionMismatch {
+                expected: 3,
+                got: 4,
+                index: 0
+            })
+        );
+        let mut nan = sparse.clone();
+        nan[2].values[1] = f32::NAN;
+        let opts = FitOptions {
+            check_finite: true,
+            ..FitOptions::default()
+        };
+        let mut estimator = Kmeans::from_centers(vec![dataset[1].clone(), dataset[2].clone()]);
+        assert_eq!(
+            estimator.fit_sparse(&nan, DistanceMetric::L2, &opts).err(),
+            Some(KmeansError::NonFinite { index: 2, bin: 3 })
+        );
+    }
+
+    #[test]
+    fn test_emd_dist_same() {
+        let hist = vec![0.1, 0.4, 0.2, 0.3];
+        assert_eq!(emd_dist(&hist, &hist), 0.0);
+    }
+
+    #[test]
+    fn test_emd_dist_small() {
+        // move all mass two bins
+        let a = vec![1.0, 0.0, 0.0];
+        let b = vec![0.0, 0.0, 1.0];
+        assert!((emd_dist(&a, &b) - 2.0).abs() < ERROR);
+        assert!((emd_dist(&b, &a) - 2.0).abs() < ERROR);
+
+        // move 0.5 one bin twice
+        let a = vec![0.5, 0.5, 0.0];
+        let b = vec![0.0, 0.5, 0.5];
+        assert!((emd_dist(&a, &b) - 1.0).abs() < ERROR);
+
+        // move 0.25 three bins
+        let a = vec![0.25, 0.25, 0.25, 0.25];
+        let b = vec![0.0, 0.25, 0.25, 0.5];
+        assert!((emd_dist(&a, &b) - 0.75).abs() < ERROR);
+    }
+
+    #[test]
+    fn test_prepared_emd_dist() {
+        let a = vec![0.25, 0.25, 0.25, 0.25];
+        let b = vec![0.0, 0.25, 0.25, 0.5];
+        let prepared_a = PreparedHistogram::new(&a);
+        assert_eq!(prepared_a.cdf(), &[0.25, 0.5, 0.75, 1.0]);
+        let d = prepared_emd_dist(&prepared_a, &PreparedHistogram::new(&b));
+        assert!((d - emd_dist(&a, &b)).abs() < ERROR);
+    }
+
+    #[test]
+    fn test_predict_emd_prepared() {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..2000)
+            .map(|_| (0..10).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let estimator = Kmeans::from_centers(dataset[..50].to_vec());
+        let mut clusters = vec![0usize; dataset.len()];
+        let inertia = estimator
+            .predict(&dataset, &mut clusters, DistanceMetric::Emd)
+            .unwrap();
+        for (item, c) in dataset.iter().zip(clusters.iter()) {
+            let (expected, _) = estimator.nearest(item, DistanceMetric::Emd);
+            let d = emd_dist(item, &estimator.centers[*c]);
+            let expected_d = emd_dist(item, &estimator.centers[expected]);
+            assert!((d - expected_d).abs() < 1e-4);
+        }
+        let expected_inertia = estimator.inertia(&dataset, &clusters, DistanceMetric::Emd);
+        assert!((inertia - expected_inertia).abs() / expected_inertia < 1e-4);
+    }
+
+    #[test]
+    fn test_predict_pruned() {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let dataset: Vec<Histogram> = (0..2000)
+            .map(|_| {
+                let mut h: Histogram = (0..10).map(|_| rng.gen::<f32>()).collect();
+                normalize(&mut h);
+                h
+            })
+            .collect();
+        let estimator = Kmeans::from_centers(dataset[..100].to_vec());
+        let mut clusters = vec![0usize; dataset.len()];
+        for metric in [DistanceMetric::L2, DistanceMetric::L1, DistanceMetric::Emd].iter() {
+            estimator.predict(&dataset, &mut clusters, *metric).unwrap();
+            for (item, c) in dataset.iter().zip(clusters.iter()) {
+                assert_eq!(*c, estimator.nearest(item, *metric).0);
+            }
+        }
+    }
+
+    #[bench]
+    fn bench_predict_emd(b: &mut test::Bencher) {
+        // centers prepared once per pass
+        let (estimator, dataset) = bench_predict_setup();
+        let mut clusters = vec![0usize; dataset.len()];
+        b.iter(|| {
+            estimator
+                .predict(&dataset, &mut clusters, DistanceMetric::Emd)
+                .unwrap()
+        });
+    }
+
+    #[bench]
+    fn bench_predict_emd_unprepared(b: &mut test::Bencher) {
+        let (estimator, dataset) = bench_predict_setup();
+        b.iter(|| {
+            dataset
+                .par_iter()
+                .map(|item| estimator.nearest(item, DistanceMetric::Emd).1)
+                .sum::<f32>()
+        });
+    }
+
+    fn bench_predict_setup() -> (Kmeans32, Vec<Histogram>) {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..10_000)
+            .map(|_| (0..30).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let estimator = Kmeans::from_centers(dataset[..200].to_vec());
+        return (estimator, dataset);
+    }
+
+    #[test]
+    fn test_l1_dist() {
+        let a = vec![0.5, 0.25, 0.25, 0.0];
+        let b = vec![0.0, 0.25, 0.5, 0.25];
+        assert!((l1_dist(&a, &b) - 1.0).abs() < ERROR);
+        assert_eq!(l1_dist(&a, &a), 0.0);
+    }
+
+    #[test]
+    fn test_cosine_dist() {
+        // orthogonal
+        let a = vec![1.0, 0.0, 0.0];
+        let b = vec![0.0, 0.5, 0.5];
+        assert!((cosine_dist(&a, &b) - 1.0).abs() < ERROR);
+        // identical up to magnitude
+        let a = vec![0.2, 0.3, 0.5];
+        let b = vec![2.0, 3.0, 5.0];
+        assert!(cosine_dist(&a, &a).abs() < ERROR);
+        assert!(cosine_dist(&a, &b).abs() < ERROR);
+        // all zeros
+        let zero = vec![0.0, 0.0, 0.0];
+        assert_eq!(cosine_dist(&a, &zero), 0.0);
+        assert_eq!(cosine_dist(&zero, &zero), 0.0);
+    }
+
+    #[test]
+    fn test_kl_dist() {
+        let a = vec![0.5, 0.5];
+        let b = vec![0.9, 0.1];
+        // (0.5 - 0.9) ln(0.5 / 0.9) + (0.5 - 0.1) ln(0.5 / 0.1)
+        assert!((kl_dist(&a, &b) - 0.878890).abs() < 1e-5);
+        assert_eq!(kl_dist(&a, &b), kl_dist(&b, &a));
+        assert!(kl_dist(&a, &a).abs() < ERROR);
+        // empty bins stay finite
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 1.0];
+        assert!(kl_dist(&a, &b).is_finite());
+        assert!(kl_dist(&a, &b) > kl_dist(&a, &vec![0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_js_dist() {
+        let a = vec![3.0, 1.0, 0.0];
+        // counts are compared by their shape
+        assert!(js_dist(&a, &vec![6.0, 2.0, 0.0]).abs() < ERROR);
+        assert!(js_dist(&a, &a).abs() < ERROR);
+        // disjoint histograms are at the maximum distance
+        let b = vec![0.0, 0.0, 5.0];
+        let max = 2f32.ln().sqrt();
+        assert!((js_dist(&a, &b) - max).abs() < 1e-6);
+        assert_eq!(js_dist(&a, &b), js_dist(&b, &a));
+        let c = vec![1.0, 1.0, 2.0];
+        assert!(js_dist(&a, &c) > 0.0 && js_dist(&a, &c) < max);
+        assert!(js_dist(&a, &c) <= js_dist(&a, &b) + js_dist(&b, &c));
+        assert_eq!(
+            DistanceMetric::JensenShannon.compute(&a, &c),
+            js_dist(&a, &c)
+        );
+    }
+
+    #[test]
+    fn test_chi_squared_dist() {
+        let a = vec![0.5, 0.5];
+        let b = vec![0.9, 0.1];
+        // (0.16 / 1.4 + 0.16 / 0.6) / 2
+        assert!((chi_squared_dist(&a, &b) - 0.190476).abs() < 1e-5);
+        assert!((chi_squared_dist(&a, &b) - chi_squared_dist(&b, &a)).abs() < ERROR);
+        assert!(chi_squared_dist(&a, &a).abs() < ERROR);
+        // disjoint histograms are at the maximum distance of 1
+        let a = vec![1.0, 0.0, 0.0];
+        let b = vec![0.0, 0.0, 1.0];
+        assert!((chi_squared_dist(&a, &b) - 1.0).abs() < 1e-5);
+        let zero = vec![0.0, 0.0, 0.0];
+        assert!(chi_squared_dist(&a, &zero).is_finite());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut h: Histogram = vec![1.0, 3.0, 0.0, 4.0];
+        normalize(&mut h);
+        assert_eq!(h, vec![0.125, 0.375, 0.0, 0.5]);
+        let mut zero: Histogram = vec![0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_silhouette_score() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..1000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![
+                    c + rng.gen_range(-0.05, 0.05),
+                    c + rng.gen_range(-0.05, 0.05),
+                ]
+            })
+            .collect();
+        let clusters: Vec<usize> = (0..1000).map(|i| i % 2).collect();
+
+        let full = silhouette_score(&dataset, &clusters, DistanceMetric::L2, None, &mut rng);
+        assert!(full > 0.9, "well separated blobs scored {}", full);
+        let sampled =
+            silhouette_score(&dataset, &clusters, DistanceMetric::L2, Some(200), &mut rng);
+        assert!((full - sampled).abs() < 0.02);
+
+        // random assignment is close to 0
+        let random: Vec<usize> = (0..1000).map(|_| rng.gen_range(0, 2)).collect();
+        let score = silhouette_score(&dataset, &random, DistanceMetric::L2, None, &mut rng);
+        assert!(score.abs() < 0.1, "random clusters scored {}", score);
+    }
+
+    #[test]
+    fn test_cluster_stats() {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..1000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![
+                    c + rng.gen_range(-0.05, 0.05),
+                    c + rng.gen_range(-0.05, 0.05),
+                ]
+            })
+            .collect();
+        let estimator = Kmeans::from_centers(vec![vec![0.2, 0.2], vec![0.8, 0.8], vec![0.5, 0.5]]);
+        let mut clusters = vec![0usize; dataset.len()];
+        estimator
+            .predict(&dataset, &mut clusters, DistanceMetric::L2)
+            .unwrap();
+        let stats = estimator.cluster_stats(&dataset, &clusters, DistanceMetric::L2);
+        assert_eq!(stats.len(), 3);
+        for stat in stats[..2].iter() {
+            assert_eq!(stat.count, 500);
+            assert!(stat.mean_dist > 0.0 && stat.mean_dist < 0.05);
+            // furthest corner of the blob
+            assert!(stat.max_dist < 0.0708);
+        }
+        assert_eq!(
+            stats[2],
+            ClusterStat {
+                count: 0,
+                mean_dist: 0.0,
+                max_dist: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_inertia_deterministic() {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..10_000)
+            .map(|_| vec![rng.gen::<f32>(), rng.gen::<f32>()])
+            .collect();
+        let estimator = Kmeans::from_centers(vec![vec![0.2, 0.2], vec![0.8, 0.8]]);
+        let mut clusters = vec![0usize; dataset.len()];
+        estimator
+            .predict(&dataset, &mut clusters, DistanceMetric::L2)
+            .unwrap();
+        let expected: f32 = (0..dataset.len())
+            .map(|i| DistanceMetric::L2.compute(&dataset[i], &estimator.centers[clusters[i]]))
+            .sum();
+        // same bits whatever the number of threads
+        for n_threads in [1, 3, 8].iter() {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(*n_threads)
+                .build()
+                .unwrap();
+            let inertia =
+                pool.install(|| estimator.inertia(&dataset, &clusters, DistanceMetric::L2));
+            assert_eq!(inertia.to_bits(), expected.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_accumulate_clusters() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let n_data = 20_000;
+        let n_bins = 50;
+        let k = 30;
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..n_data)
+            .map(|_| (0..n_bins).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let clusters: Vec<usize> = (0..n_data).map(|_| rng.gen_range(0, k)).collect();
+
+        let (counts, mass) = accumulate_clusters(&dataset, None, &clusters, k);
+
+        // sequential reference
+        let mut seq_counts = vec![0f32; k];
+        let mut seq_mass = vec![vec![0f32; n_bins]; k];
+        for i in 0..n_data {
+            seq_counts[clusters[i]] += 1.0;
+            for j in 0..n_bins {
+                seq_mass[clusters[i]][j] += dataset[i][j];
+            }
+        }
+
+        assert_eq!(counts, seq_counts);
+        assert_eq!(mass, seq_mass);
+    }
+
+    #[bench]
+    fn bench_accumulate_clusters(b: &mut test::Bencher) {
+        use rand::rngs::SmallRng;
+
+        let (n_data, n_bins, k) = (20_000, 50, 30);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..n_data)
+            .map(|_| (0..n_bins).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let clusters: Vec<usize> = (0..n_data).map(|_| rng.gen_range(0, k)).collect();
+        b.iter(|| accumulate_clusters(&dataset, None, &clusters, k));
+    }
+
+    #[test]
+    fn test_reassign_accumulate() {
+        use rand::rngs::SmallRng;
+
+        // multiples of 1/64 are summed exactly in any order
+        let n_data = 20_000;
+        let n_bins = 10;
+        let k = 20;
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..n_data)
+            .map(|_| {
+                (0..n_bins)
+                    .map(|_| rng.gen_range(0, 64) as f32 / 64.0)
+                    .collect()
+            })
+            .collect();
+        let weights: Vec<f32> = (0..n_data).map(|_| rng.gen_range(1, 4) as f32).collect();
+        let centers: Vec<Histogram> = dataset[..k].to_vec();
+
+        for w in [None, Some(&weights[..])].iter() {
+            let mut two_pass = Kmeans::from_centers(centers.clone());
+            let mut fused = Kmeans::from_centers(centers.clone());
+            let mut s = vec![f32::MAX; k];
+            two_pass.init_s(&mut s, DistanceMetric::L2);
+            let mut clusters = vec![0usize; n_data];
+            let mut bounds = vec![(0.0, f32::MAX); n_data];
+            let mut fused_clusters = clusters.clone();
+            let mut fused_bounds = bounds.clone();
+
+            let changed = two_pass.reassign_clusters(
+                &dataset,
+                &s,
+                &mut clusters,
+                &mut bounds,
+                DistanceMetric::L2,
+            );
+            let (counts, mass) = accumulate_clusters(&dataset, *w, &clusters, k);
+            let (fused_changed, fused_counts, fused_mass) = fused.reassign_accumulate(
+                &dataset,
+                *w,
+                &s,
+                &mut fused_clusters,
+                &mut fused_bounds,
+                DistanceMetric::L2,
+            );
+            assert_eq!(changed, fused_changed);
+            assert_eq!(clusters, fused_clusters);
+            assert_eq!(bounds, fused_bounds);
+            assert_eq!(counts, fused_counts);
+            assert_eq!(mass, fused_mass);
+        }
+    }
+
+    #[test]
+    fn test_fit_minibatch() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        // two blobs around (0.2, 0.2) and (0.8, 0.8)
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..2000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![
+                    c + rng.gen_range(-0.05, 0.05),
+                    c + rng.gen_range(-0.05, 0.05),
+                ]
+            })
+            .collect();
+        let init = vec![vec![0.3, 0.1], vec![0.7, 0.9]];
+
+        let mut full = Kmeans::from_centers(init.clone());
+        full.fit(&dataset, DistanceMetric::L2).unwrap();
+        let mut minibatch = Kmeans::from_centers(init);
+        minibatch
+            .fit_minibatch(&dataset, DistanceMetric::L2, 50, 200, &mut rng)
+            .unwrap();
+
+        for j in 0..2 {
+            let d = l2_dist(&full.centers[j], &minibatch.centers[j]);
+            assert!(d < 0.02, "center {} is {} away", j, d);
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn test_l2_dist_simd() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        // lengths with and without a scalar tail
+        for n_bins in [0, 3, 8, 30, 50, 64].iter() {
+            let a: Histogram = (0..*n_bins).map(|_| rng.gen::<f32>()).collect();
+            let b: Histogram = (0..*n_bins).map(|_| rng.gen::<f32>()).collect();
+            let scalar = l2_dist_scalar(&a, &b);
+            assert!((l2_dist_simd(&a, &b) - scalar).abs() <= 1e-5 * scalar.max(1.0));
+            assert_eq!(l2_dist(&a, &b), l2_dist_simd(&a, &b));
+        }
+        let a: Vec<f64> = vec![0.0, 3.0];
+        assert_eq!(l2_dist(&a, &[4.0, 0.0]), 5.0);
+    }
+
+    #[test]
+    fn test_fit_with_callback() {
+        let dataset: Vec<Histogram> = (0..1000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![c + (i % 7) as f32 * 1e-3, c]
+            })
+            .collect();
+        let init = vec![vec![0.0, 0.0], vec![0.5, 0.5]];
+        let mut iterations = Vec::new();
+        let mut last_centers = Vec::new();
+        let mut estimator = Kmeans::from_centers(init.clone());
+        let clusters = estimator
+            .fit_with_callback(&dataset, DistanceMetric::L2, |t, centers, changed| {
+                iterations.push((t, changed));
+                last_centers = centers.to_vec();
+            })
+            .unwrap();
+
+        let mut reference = Kmeans::from_centers(init);
+        let (expected, report) = reference
+            .fit_with_report(&dataset, DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(clusters, expected);
+        assert_eq!(iterations.len(), report.iterations);
+        for (i, (t, _)) in iterations.iter().enumerate() {
+            assert_eq!(*t, i);
+        }
+        // the first iteration assigns every item, the last converged
+        assert_eq!(iterations[0].1, 1.0);
+        assert!(iterations.last().unwrap().1 <= EPSILON);
+        assert_eq!(last_centers, estimator.centers);
+    }
+
+    #[test]
+    fn test_gap_statistic() {
+        // three tight blobs
+        let mut rng = StdRng::seed_from_u64(0);
+        let blobs = [[0.1, 0.1], [0.9, 0.2], [0.5, 0.9]];
+        let dataset: Vec<Histogram> = (0..300)
+            .map(|i| {
+                let c = blobs[i % 3];
+                vec![
+                    c[0] + rng.gen_range(-0.02, 0.02),
+                    c[1] + rng.gen_range(-0.02, 0.02),
+                ]
+            })
+            .collect();
+        let gaps = gap_statistic(&dataset, DistanceMetric::L2, 1..6, 3, &mut rng).unwrap();
+        assert_eq!(
+            gaps.iter().map(|(k, _)| *k).collect::<Vec<usize>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+        // the gap jumps up to k = 3 and flattens after
+        assert!(gaps[1].1 > gaps[0].1);
+        assert!(gaps[2].1 > gaps[1].1 + 0.5);
+        assert!(gaps[3].1 < gaps[2].1 + 0.1);
+
+        let empty: Vec<Histogram> = Vec::new();
+        assert_eq!(
+            gap_statistic(&empty, DistanceMetric::L2, 1..3, 1, &mut rng),
+            Err(KmeansError::DatasetEmpty)
+        );
+        assert!(gap_statistic(&dataset, DistanceMetric::L2, 301..302, 1, &mut rng).is_err());
+    }
+
+    #[test]
+    fn test_predict_one_approx() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let centers: Vec<Histogram> = (0..200)
+            .map(|_| (0..10).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let estimator = Kmeans::from_centers(centers.clone());
+        // items near a center
+        let items: Vec<Histogram> = (0..500)
+            .map(|i| {
+                centers[i % 200]
+                    .iter()
+                    .map(|x| x + rng.gen_range(-0.05, 0.05))
+                    .collect()
+            })
+            .collect();
+
+        let exact = estimator.build_index(DistanceMetric::L2, 1, 200, &mut rng);
+        assert_eq!(exact.n_centers(), 200);
+        for item in items.iter() {
+            assert_eq!(
+                exact.predict_one_approx(item),
+                estimator.predict_one(item, DistanceMetric::L2)
+            );
+        }
+
+        // 8 * 16 of 200 centers measured
+        let index = estimator.build_index(DistanceMetric::L2, 8, 16, &mut rng);
+        let hits = items
+            .iter()
+            .filter(|item| {
+                index.predict_one_approx(item) == estimator.predict_one(item, DistanceMetric::L2)
+            })
+            .count();
+        assert!(hits >= 450, "recall {}/500", hits);
+
+        // 0 directions is 1, with every center a candidate the lookup is exact
+        let index = estimator.build_index(DistanceMetric::L2, 0, 200, &mut rng);
+        assert_eq!(
+            index.predict_one_approx(&items[0]),
+            estimator.predict_one(&items[0], DistanceMetric::L2)
+        );
+    }
+
+    #[test]
+    fn test_predict_topk() {
+        let estimator = Kmeans::from_centers(vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![0.0, 0.5],
+            vec![3.0, 0.0],
+            vec![0.0, 1.0],
+        ]);
+        let item = [0.0, 0.0];
+        assert_eq!(
+            estimator.predict_topk(&item, 3, DistanceMetric::L1),
+            vec![(0, 0.0), (2, 0.5), (1, 1.0)]
+        );
+        // ties keep index order, k past the number of centers returns all
+        let all = estimator.predict_topk(&item, 10, DistanceMetric::L1);
+        assert_eq!(
+            all.iter().map(|(j, _)| *j).collect::<Vec<_>>(),
+            vec![0, 2, 1, 4, 3]
+        );
+        assert!(estimator
+            .predict_topk(&item, 0, DistanceMetric::L1)
+            .is_empty());
+        let top = estimator.predict_topk(&[0.9, 0.1], 1, DistanceMetric::L2);
+        assert_eq!(
+            top[0].0,
+            estimator.predict_one(&[0.9, 0.1], DistanceMetric::L2)
+        );
+    }
+
+    #[test]
+    fn test_debug() {
+        let estimator = Kmeans::from_centers(vec![vec![0.5f32, 0.5, 0.0], vec![0.0, 0.25, 0.5]]);
+        assert_eq!(
+            format!("{:?}", estimator),
+            "Kmeans { k: 2, n_bins: 3, center_sums: [1.0, 0.75] }"
+        );
+        let empty: Kmeans<f64> = Kmeans::from_centers(Vec::new());
+        assert_eq!(
+            format!("{:?}", empty),
+            "Kmeans { k: 0, n_bins: 0, center_sums: [] }"
+        );
+    }
+
+    /// reads histograms from a flat array like a mapped file would
+    struct FlatSource {
+        data: Vec<f32>,
+        n_bins: usize,
+    }
+
+    impl HistogramSource<f32> for FlatSource {
+        fn len(&self) -> usize {
+            return self.data.len() / self.n_bins;
+        }
+
+        fn n_bins(&self) -> usize {
+            return self.n_bins;
+        }
+
+        fn read(&self, i: usize, out: &mut [f32]) {
+            out.copy_from_slice(&self.data[i * self.n_bins..(i + 1) * self.n_bins]);
+        }
+    }
+
+    #[test]
+    fn test_fit_streaming() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let dataset: Vec<Histogram> = (0..2000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![
+                    c + rng.gen_range(-0.05, 0.05),
+                    c + rng.gen_range(-0.05, 0.05),
+                ]
+            })
+            .collect();
+        let source = FlatSource {
+            data: dataset.concat(),
+            n_bins: 2,
+        };
+        let init = vec![vec![0.3, 0.1], vec![0.7, 0.9]];
+
+        let mut streaming = Kmeans::from_centers(init.clone());
+        let mut rng = SmallRng::seed_from_u64(0);
+        streaming
+            .fit_streaming(&source, DistanceMetric::L2, 50, 200, &mut rng)
+            .unwrap();
+        // same items and rng as fit_minibatch
+        let mut minibatch = Kmeans::from_centers(init.clone());
+        let mut rng = SmallRng::seed_from_u64(0);
+        minibatch
+            .fit_minibatch(&dataset, DistanceMetric::L2, 50, 200, &mut rng)
+            .unwrap();
+        assert_eq!(streaming.centers, minibatch.centers);
+        assert!(l2_dist(&streaming.centers[0], &[0.2, 0.2]) < 0.02);
+        assert!(l2_dist(&streaming.centers[1], &[0.8, 0.8]) < 0.02);
+
+        let narrow = FlatSource {
+            data: vec![0.5; 9],
+            n_bins: 3,
+        };
+        assert_eq!(
+            Kmeans::from_centers(init)
+                .fit_streaming(&narrow, DistanceMetric::L2, 50, 1, &mut rng)
+                .err(),
+            Some(KmeansError::DimensionMismatch {
+                expected: 2,
+                got: 3,
+                index: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_reservoir_sample() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let source = FlatSource {
+            data: (0..20).map(|x| x as f32).collect(),
+            n_bins: 2,
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+        // every histogram is kept with probability k / n
+        let mut counts = [0usize; 10];
+        for _ in 0..10000 {
+            let sample = reservoir_sample(&source, 3, &mut rng);
+            assert_eq!(sample.len(), 3);
+            for h in sample.iter() {
+                assert_eq!(h[1], h[0] + 1.0);
+                counts[h[0] as usize / 2] += 1;
+            }
+        }
+        for count in counts.iter() {
+            assert!((*count as f32 / 10000.0 - 0.3).abs() < 0.02, "{:?}", counts);
+        }
+
+        // small sources are returned whole
+        let dataset: Vec<Histogram> = vec![vec![0.5, 0.5], vec![1.0, 0.0]];
+        assert_eq!(reservoir_sample(&dataset[..], 5, &mut rng), dataset);
+        assert!(reservoir_sample(&dataset[..], 0, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_fit_f64() {
+        let dataset: Vec<Vec<f64>> = (0..1000)
+            .map(|i| {
+                let c = if i % 2 == 0 { 0.2 } else { 0.8 };
+                vec![c + (i % 7) as f64 * 1e-3, c]
+            })
+            .collect();
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let clusters = estimator.fit(&dataset, DistanceMetric::Emd).unwrap();
+        // even items in the first cluster, odd in the second
+        for (i, c) in clusters.iter().enumerate() {
+            assert_eq!(*c, i % 2);
+        }
+        assert!((estimator.centers()[0][1] - 0.2).abs() < 1e-12);
+        assert!((estimator.centers()[1][1] - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_predict_deterministic() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut dataset: Vec<Histogram> = (0..10_000)
+            .map(|_| (0..10).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        // equidistant to both centers of tied below
+        dataset.push(vec![0.5; 10]);
+        let estimator = Kmeans::from_centers(dataset[..20].to_vec());
+
+        let mut first = vec![0usize; dataset.len()];
+        let mut second = vec![0usize; dataset.len()];
+        let first_inertia = estimator
+            .predict(&dataset, &mut first, DistanceMetric::L2)
+            .unwrap();
+        let second_inertia = estimator
+            .predict(&dataset, &mut second, DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first_inertia.to_bits(), second_inertia.to_bits());
+
+        // lowest center index wins ties
+        let tied = Kmeans::from_centers(vec![vec![0.0; 10], vec![1.0; 10]]);
+        assert_eq!(tied.predict_one(&dataset[10_000], DistanceMetric::L2), 0);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let estimator = Kmeans::from_centers(vec![vec![0.1, 0.2, 0.7], vec![1.0, 0.0, 0.0]]);
+        let path = std::env::temp_dir().join("kmeans_test_save_load.dat");
+        let path = path.to_str().unwrap();
+        estimator.save(path).unwrap();
+        let loaded: Kmeans32 = Kmeans::load(path).unwrap();
+        assert_eq!(estimator.centers, loaded.centers);
+
+        // truncated, and a corrupt header asking for 2^32 - 1 centers
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(8 + 5 * 4).unwrap();
+        drop(file);
+        let err = Kmeans::<f32>::load(path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(path, &bytes).unwrap();
+        let err = Kmeans::<f32>::load(path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_fit_no_empty_clusters() {
+        let dataset = vec![
+            vec![10.0, 10.0],
+            vec![10.0, 10.1],
+            vec![11.0, 11.0],
+            vec![11.0, 10.9],
+            vec![13.0, 13.0],
+        ];
+        // second center is too far away to attract any item
+        let mut estimator =
+            Kmeans::from_centers(vec![vec![10.0, 10.0], vec![100.0, 100.0], vec![11.0, 11.0]]);
+        let clusters = estimator.fit(&dataset, DistanceMetric::L2).unwrap();
+        for j in 0..3 {
+            assert!(clusters.iter().any(|c| *c == j), "cluster {} is empty", j);
+        }
+    }
+
+    #[test]
+    fn test_fit_report() {
+        let dataset = vec![
+            vec![0.0, 0.0],
+            vec![0.0, 0.1],
+            vec![1.0, 1.0],
+            vec![1.0, 0.9],
+        ];
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![0.0, 0.1]]);
+        let opts = FitOptions {
+            max_iters: 1,
+            ..FitOptions::default()
+        };
+        let (clusters, report) = estimator
+            .fit_with_opts(&dataset, DistanceMetric::L2, &opts)
+            .unwrap();
+        assert_eq!(report.iterations, 1);
+        assert_eq!(report.stop, StopReason::MaxIters);
+        let inertia = estimator.inertia(&dataset, &clusters, DistanceMetric::L2);
+        assert!((report.inertia - inertia).abs() < ERROR);
+
+        let (_, report) = estimator
+            .fit_with_report(&dataset, DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(report.stop, StopReason::Converged);
+    }
+
+    #[test]
+    fn test_fit_stalled() {
+        // a grid that needs a few iterations to settle
+        let dataset: Vec<Histogram> = (0..1000)
+            .map(|i| vec![(i % 10) as f32 / 10.0, ((i * 7) % 10) as f32 / 10.0])
+            .collect();
+        let opts = FitOptions {
+            epsilon: 0.0,
+            patience: 1,
+            min_improvement: 1.0,
+            ..FitOptions::default()
+        };
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let (_, report) = estimator
+            .fit_with_opts(&dataset, DistanceMetric::L2, &opts)
+            .unwrap();
+        // the first iteration sets the lowest fraction, the second can't beat it by 1
+        assert_eq!(report.iterations, 2);
+        assert_eq!(report.stop, StopReason::Stalled);
+
+        let opts = FitOptions {
+            patience: usize::MAX,
+            ..opts
+        };
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let (_, report) = estimator
+            .fit_with_opts(&dataset, DistanceMetric::L2, &opts)
+            .unwrap();
+        assert_eq!(report.stop, StopReason::Converged);
+    }
+
+    #[test]
+    fn test_convergence_estimate() {
+        let mut estimate = ConvergenceEstimate::new();
+        assert_eq!(estimate.update(0.5, 0.005), None);
+        // halves every iteration, 0.25 -> 0.005 takes log2(50) iterations
+        assert_eq!(estimate.update(0.25, 0.005), Some(6));
+        assert_eq!(estimate.update(0.125, 0.005), Some(5));
+        assert_eq!(estimate.update(0.004, 0.005), Some(0));
+        // not decreasing on average
+        let mut estimate = ConvergenceEstimate::new();
+        estimate.update(0.1, 0.005);
+        assert_eq!(estimate.update(0.2, 0.005), None);
+    }
+
+    #[test]
+    fn test_check_finite() {
+        let mut dataset = vec![vec![0.0, 0.0], vec![0.0, 0.1], vec![1.0, 1.0]];
+        assert_eq!(check_finite(&dataset), Ok(()));
+        dataset[1][1] = f32::NAN;
+        dataset[2][0] = f32::INFINITY;
+        let err = KmeansError::NonFinite { index: 1, bin: 1 };
+        assert_eq!(check_finite(&dataset), Err(err.clone()));
+        assert_eq!(
+            err.to_string(),
+            "histogram 1 has a non finite value in bin 1"
+        );
+
+        let opts = FitOptions {
+            check_finite: true,
+            ..FitOptions::default()
+        };
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let result = estimator.fit_with_opts(&dataset, DistanceMetric::L2, &opts);
+        assert_eq!(result.err(), Some(err));
+        // the check is opt in
+        assert!(estimator
+            .fit_with_opts(&dataset, DistanceMetric::L2, &FitOptions::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_fit_first_iteration_changed() {
+        // 3 of 4 items are nearest center 0, they were still never assigned
+        let dataset = vec![
+            vec![0.0, 0.0],
+            vec![0.0, 0.1],
+            vec![0.1, 0.0],
+            vec![1.0, 1.0],
+        ];
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let mut changed = Vec::new();
+        let clusters = estimator
+            .fit_with_callback(&dataset, DistanceMetric::L2, |_, _, c| changed.push(c))
+            .unwrap();
+        assert_eq!(clusters, vec![0, 0, 0, 1]);
+        assert_eq!(changed, vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_refit() {
+        let dataset = vec![
+            vec![0.0, 0.0],
+            vec![0.0, 0.1],
+            vec![1.0, 1.0],
+            vec![1.0, 0.9],
+        ];
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let clusters = estimator.fit(&dataset, DistanceMetric::L2).unwrap();
+        let converged = estimator.centers().to_vec();
+        assert_eq!(converged, vec![vec![0.0, 0.05], vec![1.0, 0.95]]);
+
+        let refit_clusters = estimator.refit(&dataset, DistanceMetric::L2).unwrap();
+        assert_eq!(clusters, refit_clusters);
+        assert_eq!(estimator.centers(), &converged[..]);
+    }
+
+    #[test]
+    fn test_assign_buckets() {
+        let dataset = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 1.0],
+            vec![0.0, 0.1],
+            vec![1.0, 0.9],
+        ];
+        let estimator = Kmeans::from_centers(vec![vec![1.0, 1.0], vec![0.0, 0.0], vec![5.0, 5.0]]);
+        let buckets = estimator
+            .assign_buckets(&dataset, DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(buckets, vec![vec![1, 3], vec![0, 2], vec![]]);
+    }
+
+    #[test]
+    fn test_fit_weighted() {
+        let dataset = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![10.0, 10.0],
+            vec![11.0, 10.0],
+        ];
+        let weights = vec![3.0, 1.0, 1.0, 1.0];
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![10.0, 10.0]]);
+        let clusters = estimator
+            .fit_weighted(&dataset, &weights, DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(clusters, vec![0, 0, 1, 1]);
+        // (3 * 0 + 1 * 1) / 4
+        assert!((estimator.centers()[0][0] - 0.25).abs() < ERROR);
+        assert!((estimator.centers()[1][0] - 10.5).abs() < ERROR);
+
+        // unit weights match fit
+        let mut unweighted = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![10.0, 10.0]]);
+        unweighted.fit(&dataset, DistanceMetric::L2).unwrap();
+        let mut weighted = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![10.0, 10.0]]);
+        weighted
+            .fit_weighted(&dataset, &[1.0; 4], DistanceMetric::L2)
+            .unwrap();
+        assert_eq!(unweighted.centers(), weighted.centers());
+    }
+
+    #[test]
+    fn test_fit_ragged() {
+        let dataset = vec![vec![0.0, 0.0], vec![0.0, 0.1], vec![1.0], vec![1.0, 0.9]];
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        let err = estimator.fit(&dataset, DistanceMetric::L2).unwrap_err();
+        assert_eq!(
+            err,
+            KmeansError::DimensionMismatch {
+                expected: 2,
+                got: 1,
+                index: 2
+            }
+        );
+        assert_eq!(err.to_string(), "histogram 2 has 1 bins, expected 2");
+        let mut clusters = vec![0; dataset.len()];
+        assert_eq!(
+            estimator.predict(&dataset, &mut clusters, DistanceMetric::L2),
+            Err(err.clone())
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let init = Kmeans::init_pp(2, &mut rng, DistanceMetric::L2, &dataset, false);
+        assert_eq!(init.err(), Some(err));
+    }
+
+    #[test]
+    fn test_init_empty() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let dataset: Vec<Histogram> = Vec::new();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let init = Kmeans::init_random(1, 2, &mut rng, DistanceMetric::L2, &dataset, false);
+        assert_eq!(init.err(), Some(KmeansError::DatasetEmpty));
+    }
+
+    #[test]
+    fn test_init_too_many_centers() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let dataset: Vec<Histogram> = vec![vec![0.0, 1.0], vec![1.0, 0.0]];
+        let mut rng = SmallRng::seed_from_u64(0);
+        let err = Kmeans::init_pp(3, &mut rng, DistanceMetric::L2, &dataset, false)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            KmeansError::TooManyCenters {
+                n_centers: 3,
+                n_data: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "kmeans needs between 1 and 2 centers for 2 histograms, got 3"
+        );
+        let init = Kmeans::init_scalable(0, 2.0, 5, &mut rng, DistanceMetric::L2, &dataset, false);
+        assert!(init.is_err());
+    }
+
+    #[test]
+    fn test_fit_empty() {
+        let dataset: Vec<Histogram> = Vec::new();
+        let mut estimator = Kmeans::from_centers(vec![vec![0.0, 0.0]]);
+        assert_eq!(
+            estimator.fit(&dataset, DistanceMetric::L2),
+            Err(KmeansError::DatasetEmpty)
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            estimator.fit_minibatch(&dataset, DistanceMetric::L2, 10, 10, &mut rng),
+            Err(KmeansError::DatasetEmpty)
+        );
+    }
+
+    #[test]
+    fn test_init_scalable_duplicates() {
+        use rand::rngs::SmallRng;
+
+        // no sampling rounds, the centers are the furthest items
+        let dataset = vec![
+            vec![0.0, 1.0],
+            vec![0.0, 1.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![1.0, 0.0],
+            vec![0.5, 0.5],
+        ];
+        for seed in 0..10 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let estimator =
+                Kmeans::init_scalable(3, 2.0, 0, &mut rng, DistanceMetric::L2, &dataset, false)
+                    .unwrap();
+            let mut centers = estimator.centers().to_vec();
+            centers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(
+                centers,
+                vec![dataset[0].clone(), dataset[5].clone(), dataset[3].clone()]
+            );
+        }
+    }
+
+    #[test]
+    fn test_init_scalable() {
+        use rand::rngs::SmallRng;
+
+        // 10 blobs in 5 dimensions
+        let mut rng = SmallRng::seed_from_u64(0);
+        let blobs: Vec<Histogram> = (0..10)
+            .map(|_| (0..5).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let dataset: Vec<Histogram> = (0..5000)
+            .map(|i| {
+                blobs[i % 10]
+                    .iter()
+                    .map(|c| c + rng.gen_range(-0.02, 0.02))
+                    .collect()
+            })
+            .collect();
+
+        let mut pp = Kmeans::init_pp(10, &mut rng, DistanceMetric::L2, &dataset, false).unwrap();
+        let (_, pp_report) = pp.fit_with_report(&dataset, DistanceMetric::L2).unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut scalable =
+            Kmeans::init_scalable(10, 2.0, 5, &mut rng, DistanceMetric::L2, &dataset, false)
+                .unwrap();
+        assert_eq!(scalable.n_centers(), 10);
+        let (_, report) = scalable
+            .fit_with_report(&dataset, DistanceMetric::L2)
+            .unwrap();
+        assert!(
+            report.inertia < 1.1 * pp_report.inertia,
+            "kmeans|| inertia {} kmeans++ inertia {}",
+            report.inertia,
+            pp_report.inertia
+        );
+
+        // same seed gives the same centers
+        let mut rng = SmallRng::seed_from_u64(1);
+        let again =
+            Kmeans::init_scalable(10, 2.0, 5, &mut rng, DistanceMetric::L2, &dataset, false)
+                .unwrap();
+        let mut rng = SmallRng::seed_from_u64(1);
+        let first =
+            Kmeans::init_scalable(10, 2.0, 5, &mut rng, DistanceMetric::L2, &dataset, false)
+                .unwrap();
+        assert_eq!(first.centers(), again.centers());
+    }
+
+    #[test]
+    fn test_merge() {
+        use rand::rngs::SmallRng;
+
+        // 4 blobs split into two shards
+        let mut rng = SmallRng::seed_from_u64(0);
+        let blobs = [[0.1, 0.1], [0.9, 0.1], [0.1, 0.9], [0.9, 0.9]];
+        let dataset: Vec<Histogram> = (0..2000)
+            .map(|i| {
+                let blob = blobs[i % 4];
+                vec![
+                    blob[0] + rng.gen_range(-0.05, 0.05),
+                    blob[1] + rng.gen_range(-0.05, 0.05),
+                ]
+            })
+            .collect();
+        let (left, right) = dataset.split_at(1000);
+        let mut models = Vec::new();
+        let mut counts = Vec::new();
+        for shard in [left.to_vec(), right.to_vec()].iter() {
+            let mut model = Kmeans::init_pp(8, &mut rng, DistanceMetric::L2, shard, false).unwrap();
+            let clusters = model.fit(shard, DistanceMetric::L2).unwrap();
+            let mut count = vec![0f32; 8];
+            for c in clusters.iter() {
+                count[*c] += 1.0;
+            }
+            models.push(model);
+            counts.push(count);
+        }
+
+        let merged =
+            Kmeans::merge(&models, Some(&counts), 4, DistanceMetric::L2, &mut rng).unwrap();
+        assert_eq!(merged.n_centers(), 4);
+        for blob in blobs.iter() {
+            let (c, _) = merged.nearest(blob, DistanceMetric::L2);
+            assert!(
+                l2_dist(&merged.centers[c], blob) < 0.02,
+                "{:?}",
+                merged.centers
+            );
+        }
+
+        // counts weigh the centers
+        let models = vec![
+            Kmeans::from_centers(vec![vec![0.0f32, 0.0]]),
+            Kmeans::from_centers(vec![vec![1.0, 1.0], vec![0.5, 0.5]]),
+        ];
+        let counts = vec![vec![9.0], vec![1.0, 0.0]];
+        let merged =
+            Kmeans::merge(&models, Some(&counts), 1, DistanceMetric::L2, &mut rng).unwrap();
+        assert!(l2_dist(&merged.centers[0], &[0.1, 0.1]) < ERROR);
+        let merged = Kmeans::merge(&models, None, 1, DistanceMetric::L2, &mut rng).unwrap();
+        assert!(l2_dist(&merged.centers[0], &[0.5, 0.5]) < ERROR);
+        // the empty center is dropped
+        assert_eq!(
+            Kmeans::merge(&models, Some(&counts), 3, DistanceMetric::L2, &mut rng).err(),
+            Some(KmeansError::TooManyCenters {
+                n_centers: 3,
+                n_data: 2
+            })
+        );
+        // counts need one entry per center of each model
+        let missing = vec![vec![9.0], vec![1.0]];
+        let err = Kmeans::merge(&models, Some(&missing), 1, DistanceMetric::L2, &mut rng).err();
+        assert_eq!(
+            err,
+            Some(KmeansError::CountsMismatch {
+                model: 1,
+                expected: 2,
+                got: 1
+            })
+        );
+        assert_eq!(
+            err.unwrap().to_string(),
+            "counts of model 1 have 1 entries, expected 2"
+        );
+        let extra = vec![vec![9.0], vec![1.0, 0.0], vec![1.0]];
+        assert_eq!(
+            Kmeans::merge(&models, Some(&extra), 1, DistanceMetric::L2, &mut rng).err(),
+            Some(KmeansError::CountsMismatch {
+                model: 2,
+                expected: 0,
+                got: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_with_seed() {
+        use rand::rngs::SmallRng;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let dataset: Vec<Histogram> = (0..5000)
+            .map(|_| (0..5).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let (first, first_clusters) =
+            Kmeans::with_seed(7, 20, &dataset, DistanceMetric::Emd).unwrap();
+        let (second, second_clusters) =
+            Kmeans::with_seed(7, 20, &dataset, DistanceMetric::Emd).unwrap();
+        assert_eq!(first.centers(), second.centers());
+        assert_eq!(first_clusters, second_clusters);
+        let (other, _) = Kmeans::with_seed(8, 20, &dataset, DistanceMetric::Emd).unwrap();
+        assert_ne!(first.centers(), other.centers());
     }
-    return sum.sqrt();
 }
@@ -0,0 +1,17 @@
+//! Equity features and clustering of the card abstraction
+//! gen_abstraction is built on these, they can also be used to build
+//! other pipelines, e.g. equity::equity_histogram and kmeans::Kmeans
+
+// the modules carry their own #[bench] tests
+#![feature(test)]
+
+extern crate rand;
+extern crate test;
+
+pub mod bucket_format;
+pub mod ehs;
+pub mod ehs_format;
+pub mod emd;
+pub mod equity;
+pub mod kmeans;
+pub mod sparse;
@@ -9,18 +9,17 @@ extern crate rayon;
 extern crate rust_poker;
 extern crate test;
 
-mod ehs;
-mod emd;
-mod kmeans;
-
+use std::env;
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::process;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use crossbeam::atomic::AtomicCell;
 
-use bytepack::LEPacker;
+use bytepack::{LEPacker, LEUnpacker};
 use std::fs::OpenOptions;
 
 use rand::distributions::Uniform;
@@ -31,16 +30,24 @@ use rand::{thread_rng, Rng, SeedableRng};
 use rayon::prelude::*;
 
 use hand_indexer::HandIndexer;
+use memmap2::Mmap;
 use rust_poker::equity_calculator::approx_equity;
 use rust_poker::hand_range::{char_to_rank, Combo, HandRange};
 
+use rust_solver::bucket_format;
+use rust_solver::emd;
+use rust_solver::equity::get_bin;
+use rust_solver::kmeans;
 // use kmeans::Kmeans;
+use kmeans::DistanceMetric;
 
-use ehs::EHS;
+use rust_solver::ehs::EHS;
 
 const N_THREADS: usize = 16;
 
-pub type Histogram = Vec<f32>;
+#[cfg(feature = "f16")]
+pub use kmeans::HalfHistogram;
+pub use kmeans::Histogram;
 
 /**
  * Create histograms for each combo
@@ -50,25 +57,6 @@ pub type Histogram = Vec<f32>;
  * Evaluate the resulting hand and push probability to histogram
  */
 
-/**
- * Get index of bin in histogram
- * @param bins: number of bins in histogram
- * @param value: the probability
- */
-fn get_bin(value: f32, bins: usize) -> usize {
-    let interval = 1f32 / bins as f32;
-    let mut bin = bins - 1;
-    let mut threshold = 1f32 - interval;
-    while bin > 0 {
-        if value > threshold {
-            return bin;
-        }
-        bin -= 1;
-        threshold -= interval;
-    }
-    return 0;
-}
-
 /**
  * Generates histograms based on EHS vs random probability distributions
  *
@@ -158,6 +146,656 @@ fn generate_histograms(samples: usize, round: usize, bins: usize) -> Vec<Histogr
     return dataset;
 }
 
+///
+/// Saves histograms to a binary file
+/// layout (little endian): n_histograms: u32, n_bins: u32,
+/// followed by the histograms as a flat f32 array
+///
+fn save_histograms(dataset: &Vec<Histogram>, path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let n_bins = dataset.first().map_or(0, |h| h.len());
+    file.pack(dataset.len() as u32)?;
+    file.pack(n_bins as u32)?;
+    for histogram in dataset {
+        file.pack_all(&histogram[..])?;
+    }
+    return file.flush();
+}
+
+/// Loads histograms saved with save_histograms
+fn load_histograms(path: &str) -> io::Result<Vec<Histogram>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let n_histograms: u32 = file.unpack()?;
+    let n_bins: u32 = file.unpack()?;
+    let mut dataset: Vec<Histogram> = Vec::with_capacity(n_histograms as usize);
+    for _ in 0..n_histograms {
+        let mut histogram = vec![0f32; n_bins as usize];
+        file.unpack_exact(&mut histogram[..])?;
+        dataset.push(histogram);
+    }
+    return Ok(dataset);
+}
+
+/// Histograms saved with save_histograms mapped into memory
+/// they are read on demand, see Kmeans::fit_streaming
+struct MappedHistograms {
+    map: Mmap,
+    len: usize,
+    n_bins: usize,
+}
+
+/// size in bytes of the save_histograms header
+const HISTOGRAMS_HEADER_SIZE: usize = 8;
+
+impl kmeans::HistogramSource<f32> for MappedHistograms {
+    fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn n_bins(&self) -> usize {
+        return self.n_bins;
+    }
+
+    fn read(&self, i: usize, out: &mut [f32]) {
+        let start = HISTOGRAMS_HEADER_SIZE + i * self.n_bins * 4;
+        let bytes = &self.map[start..start + self.n_bins * 4];
+        for (v, b) in out.iter_mut().zip(bytes.chunks_exact(4)) {
+            *v = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+    }
+}
+
+/// Maps histograms saved with save_histograms, fails if the file is truncated
+fn map_histograms(path: &str) -> io::Result<MappedHistograms> {
+    let mut file = File::open(path)?;
+    let n_histograms: u32 = file.unpack()?;
+    let n_bins: u32 = file.unpack()?;
+    let (len, n_bins) = (n_histograms as usize, n_bins as usize);
+    let end = HISTOGRAMS_HEADER_SIZE + len * n_bins * 4;
+    if (file.metadata()?.len() as usize) < end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is shorter than its {} histograms", path, len),
+        ));
+    }
+    let map = unsafe { Mmap::map(&file)? };
+    return Ok(MappedHistograms { map, len, n_bins });
+}
+
+/// Reads histograms from a csv file, one histogram of n_bins values per line
+fn read_histograms_csv(path: &str, n_bins: usize) -> io::Result<Vec<Histogram>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut dataset: Vec<Histogram> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let histogram = line
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Histogram, _>>()
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path, i + 1, e),
+                )
+            })?;
+        if histogram.len() != n_bins {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} line {}: {} values, expected {}",
+                    path,
+                    i + 1,
+                    histogram.len(),
+                    n_bins
+                ),
+            ));
+        }
+        dataset.push(histogram);
+    }
+    return Ok(dataset);
+}
+
+/// Reads histograms of n_bins from a flat array of little endian f32
+/// files written by save_histograms are read past their header
+fn read_histograms_bin(path: &str, n_bins: usize) -> io::Result<Vec<Histogram>> {
+    return read_flat_histograms(path, n_bins, |h| h);
+}
+
+/// Reads histograms like read_histograms_bin and stores them in half precision
+/// converts one histogram at a time so the f32 dataset is never in memory
+#[cfg(feature = "f16")]
+fn read_histograms_half(path: &str, n_bins: usize) -> io::Result<Vec<HalfHistogram>> {
+    return read_flat_histograms(path, n_bins, |h| {
+        h.iter().map(|x| half::f16::from_f32(*x)).collect()
+    });
+}
+
+/// true if file starts with a save_histograms header whose size matches len,
+/// the header is then consumed
+/// a headerless file would need its first two f32 to be the bits of
+/// the histogram count and a small n_bins to match
+/// fails if the header doesn't have n_bins
+fn has_histograms_header<R: io::Read>(
+    file: &mut R,
+    len: u64,
+    n_bins: usize,
+    path: &str,
+) -> io::Result<bool> {
+    if len < HISTOGRAMS_HEADER_SIZE as u64 {
+        return Ok(false);
+    }
+    let n_histograms: u32 = file.unpack()?;
+    let header_bins: u32 = file.unpack()?;
+    let size = HISTOGRAMS_HEADER_SIZE as u64 + u64::from(n_histograms) * u64::from(header_bins) * 4;
+    if header_bins == 0 || size != len {
+        return Ok(false);
+    }
+    if header_bins as usize != n_bins {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has histograms of {} bins, expected {}",
+                path, header_bins, n_bins
+            ),
+        ));
+    }
+    return Ok(true);
+}
+
+fn read_flat_histograms<T, C: Fn(Histogram) -> T>(
+    path: &str,
+    n_bins: usize,
+    convert: C,
+) -> io::Result<Vec<T>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut len = file.get_ref().metadata()?.len();
+    let histogram_size = (n_bins * std::mem::size_of::<f32>()) as u64;
+    if has_histograms_header(&mut file, len, n_bins, path)? {
+        len -= HISTOGRAMS_HEADER_SIZE as u64;
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+    }
+    if n_bins == 0 || len % histogram_size != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has {} bytes, not a multiple of {} bins of f32",
+                path, len, n_bins
+            ),
+        ));
+    }
+    let n_histograms = len / histogram_size;
+    let mut dataset: Vec<T> = Vec::with_capacity(n_histograms as usize);
+    for _ in 0..n_histograms {
+        let mut histogram = vec![0f32; n_bins];
+        file.unpack_exact(&mut histogram[..])?;
+        dataset.push(convert(histogram));
+    }
+    return Ok(dataset);
+}
+
+const HISTOGRAMS_USAGE: &str =
+    "usage: gen_abstraction histograms --round <0-2> [--bins <n>] [--samples <n>] [--out <path>]";
+
+/// options of the histograms mode
+#[derive(Debug, PartialEq)]
+struct HistogramArgs {
+    /// betting round (0 -> preflop, 2 -> turn)
+    round: usize,
+    /// number of bins per histogram
+    bins: usize,
+    /// number of board rollouts per histogram
+    samples: usize,
+    /// path to write the histograms to
+    out: String,
+}
+
+/// parses the arguments of the histograms mode
+fn parse_histogram_args<I: Iterator<Item = String>>(mut args: I) -> Result<HistogramArgs, String> {
+    let mut round = None;
+    let mut bins = 30;
+    let mut samples = 1000;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", arg)),
+        };
+        let number = value.parse::<usize>();
+        match (arg.as_str(), number) {
+            // river histograms only contain a single value
+            ("--round", Ok(n)) if n <= 2 => round = Some(n),
+            ("--bins", Ok(n)) if n > 0 => bins = n,
+            ("--samples", Ok(n)) if n > 0 => samples = n,
+            ("--out", _) => out = Some(value),
+            ("--round", _) | ("--bins", _) | ("--samples", _) => {
+                return Err(format!("invalid value for {}: {}", arg, value));
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    let round = match round {
+        Some(round) => round,
+        None => return Err(String::from("--round is required")),
+    };
+    return Ok(HistogramArgs {
+        round,
+        bins,
+        samples,
+        out: out.unwrap_or_else(|| format!("histograms_round_{}.dat", round)),
+    });
+}
+
+/// generates histograms for every hand of a round and saves them to file
+fn gen_histograms(args: &HistogramArgs) {
+    let dataset = generate_histograms(args.samples, args.round, args.bins);
+    save_histograms(&dataset, &args.out).unwrap();
+    println!("Wrote {} histograms to {}", dataset.len(), args.out);
+}
+
+/**
+ * OCHS features of a single hand
+ *
+ * cards: hole cards followed by the board
+ * opp_ranges: range string of each opponent cluster
+ * returns the equity of the hand against each cluster
+ */
+fn ochs_features(cards: &[u8], opp_ranges: &[String]) -> Histogram {
+    let hand_str = Combo(cards[0], cards[1], 100).to_string();
+    let mut board_mask = 0u64;
+    for i in 2..cards.len() {
+        board_mask |= 1u64 << cards[i];
+    }
+    let dead_mask = board_mask | (1u64 << cards[0]) | (1u64 << cards[1]);
+    return opp_ranges
+        .iter()
+        .map(|range| {
+            let mut hand_ranges =
+                HandRange::from_strings([hand_str.clone(), range.clone()].to_vec());
+            hand_ranges[1].remove_conflicting_combos(dead_mask);
+            // the cards block every hand of the cluster
+            if hand_ranges[1].hands.is_empty() {
+                return 0.5;
+            }
+            return approx_equity(&hand_ranges, board_mask, 1, 0.01).unwrap()[0] as f32;
+        })
+        .collect();
+}
+
+/**
+ * Generates OCHS histograms for every hand of a round
+ *
+ * round: betting round (0 -> preflop, 3 -> river)
+ * opp_ranges: range string of each opponent cluster, one bin per cluster
+ */
+fn generate_ochs_histograms(round: usize, opp_ranges: &[String]) -> Vec<Histogram> {
+    let start_time = Instant::now();
+    let cards_per_round = [2, 5, 6, 7];
+    let hand_indexer = match round {
+        0 => HandIndexer::init(1, vec![2]),
+        1 => HandIndexer::init(2, vec![2, 3]),
+        2 => HandIndexer::init(2, vec![2, 4]),
+        3 => HandIndexer::init(2, vec![2, 5]),
+        _ => panic!("invalid round"),
+    };
+    let round_size = hand_indexer.size(if round == 0 { 0 } else { 1 }) as usize;
+
+    println!(
+        "Generating {} OCHS histograms for round {} against {} clusters",
+        round_size,
+        round,
+        opp_ranges.len()
+    );
+
+    let mut dataset = vec![Histogram::new(); round_size];
+    let acc = AtomicCell::new(0usize);
+    dataset.par_iter_mut().enumerate().for_each_init(
+        || vec![0u8; cards_per_round[round]],
+        |cards, (i, hist)| {
+            let iteration = acc.fetch_add(1);
+            if iteration % 1000 == 0 {
+                print!("{:.3}% \r", (100 * iteration) as f32 / round_size as f32);
+                io::stdout().flush().unwrap();
+            }
+            hand_indexer.get_hand(if round == 0 { 0 } else { 1 }, i as u64, cards);
+            *hist = ochs_features(cards, opp_ranges);
+        },
+    );
+
+    let duration = start_time.elapsed().as_millis();
+    println!("Done.  Took {}ms", duration);
+
+    return dataset;
+}
+
+/// Loads opponent cluster ranges, one range string per line
+fn load_ranges(path: &str) -> io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    return Ok(contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect());
+}
+
+const OCHS_USAGE: &str =
+    "usage: gen_abstraction ochs --round <0-3> [--ranges <path>] [--out <path>]";
+
+/// options of the ochs mode
+#[derive(Debug, PartialEq)]
+struct OchsArgs {
+    /// betting round (0 -> preflop, 3 -> river)
+    round: usize,
+    /// file with one opponent cluster range per line,
+    /// clusters are generated from ehs.dat if not set
+    ranges: Option<String>,
+    /// path to write the histograms to
+    out: String,
+}
+
+/// parses the arguments of the ochs mode
+fn parse_ochs_args<I: Iterator<Item = String>>(mut args: I) -> Result<OchsArgs, String> {
+    let mut round = None;
+    let mut ranges = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", arg)),
+        };
+        match (arg.as_str(), value.parse::<usize>()) {
+            ("--round", Ok(n)) if n <= 3 => round = Some(n),
+            ("--round", _) => return Err(format!("invalid value for {}: {}", arg, value)),
+            ("--ranges", _) => ranges = Some(value),
+            ("--out", _) => out = Some(value),
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    let round = match round {
+        Some(round) => round,
+        None => return Err(String::from("--round is required")),
+    };
+    return Ok(OchsArgs {
+        round,
+        ranges,
+        out: out.unwrap_or_else(|| format!("ochs_round_{}.dat", round)),
+    });
+}
+
+/// generates OCHS histograms for every hand of a round and saves them to file
+fn gen_ochs_histograms(args: &OchsArgs) {
+    let opp_ranges = match &args.ranges {
+        Some(path) => load_ranges(path).unwrap(),
+        None => generate_opponent_clusters(8),
+    };
+    if opp_ranges.is_empty() {
+        eprintln!("no opponent ranges");
+        process::exit(1);
+    }
+    let dataset = generate_ochs_histograms(args.round, &opp_ranges);
+    save_histograms(&dataset, &args.out).unwrap();
+    println!("Wrote {} histograms to {}", dataset.len(), args.out);
+}
+
+/**
+ * Potential-aware features of a single hand
+ *
+ * cards: hole cards followed by the board
+ * next_means: bucketing of the next round
+ * next_features: features of a next round hand, as clustered by next_means
+ * returns the fraction of next round cards that land in each bucket
+ */
+fn transition_histogram<G: Fn(&[u8]) -> Histogram>(
+    cards: &[u8],
+    next_means: &kmeans::Kmeans32,
+    metric: DistanceMetric,
+    next_features: G,
+) -> Histogram {
+    let mut used_mask = 0u64;
+    for c in cards {
+        used_mask |= 1u64 << c;
+    }
+    let mut next_cards = cards.to_vec();
+    next_cards.push(0);
+    let mut hist = vec![0f32; next_means.n_centers()];
+    let mut n_cards = 0f32;
+    for c in 0u8..52 {
+        if (used_mask & (1u64 << c)) != 0 {
+            continue;
+        }
+        next_cards[cards.len()] = c;
+        let bucket = next_means.predict_one(&next_features(&next_cards), metric);
+        hist[bucket] += 1f32;
+        n_cards += 1f32;
+    }
+    for h in hist.iter_mut() {
+        *h /= n_cards;
+    }
+    return hist;
+}
+
+/**
+ * Generates potential-aware histograms for every hand of a round
+ *
+ * round: betting round (1 -> flop, 2 -> turn)
+ * next_means: bucketing of round + 1, one bin per bucket
+ * metric: distance next_means was trained with
+ * next_features: features of a round + 1 hand
+ */
+fn generate_transition_histograms<G: Fn(&[u8]) -> Histogram + Sync>(
+    round: usize,
+    next_means: &kmeans::Kmeans32,
+    metric: DistanceMetric,
+    next_features: G,
+) -> Vec<Histogram> {
+    let start_time = Instant::now();
+    let hand_indexer = match round {
+        1 => HandIndexer::init(2, vec![2, 3]),
+        2 => HandIndexer::init(2, vec![2, 4]),
+        _ => panic!("invalid round"),
+    };
+    let round_size = hand_indexer.size(1) as usize;
+
+    println!(
+        "Generating {} transition histograms for round {} over {} buckets",
+        round_size,
+        round,
+        next_means.n_centers()
+    );
+
+    let mut dataset = vec![Histogram::new(); round_size];
+    let acc = AtomicCell::new(0usize);
+    dataset.par_iter_mut().enumerate().for_each_init(
+        || vec![0u8; round + 4],
+        |cards, (i, hist)| {
+            let iteration = acc.fetch_add(1);
+            if iteration % 1000 == 0 {
+                print!("{:.3}% \r", (100 * iteration) as f32 / round_size as f32);
+                io::stdout().flush().unwrap();
+            }
+            hand_indexer.get_hand(1, i as u64, cards);
+            *hist = transition_histogram(cards, next_means, metric, &next_features);
+        },
+    );
+
+    let duration = start_time.elapsed().as_millis();
+    println!("Done.  Took {}ms", duration);
+
+    return dataset;
+}
+
+const TRANSITIONS_USAGE: &str =
+    "usage: gen_abstraction transitions --means <path> --ranges <path> [--out <path>]";
+
+/// options of the transitions mode
+#[derive(Debug, PartialEq)]
+struct TransitionArgs {
+    /// river Kmeans saved with Kmeans::save, trained with l2 on ochs histograms
+    means: String,
+    /// opponent cluster ranges the river ochs histograms were generated with
+    ranges: String,
+    /// path to write the histograms to
+    out: String,
+}
+
+/// parses the arguments of the transitions mode
+fn parse_transition_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<TransitionArgs, String> {
+    let mut means = None;
+    let mut ranges = None;
+    let mut out = String::from("transitions_round_2.dat");
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", arg)),
+        };
+        match arg.as_str() {
+            "--means" => means = Some(value),
+            "--ranges" => ranges = Some(value),
+            "--out" => out = value,
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    return match (means, ranges) {
+        (Some(means), Some(ranges)) => Ok(TransitionArgs { means, ranges, out }),
+        _ => Err(String::from("--means and --ranges are required")),
+    };
+}
+
+/// generates turn histograms over the river buckets and saves them to file
+fn gen_transition_histograms(args: &TransitionArgs) {
+    let river_means = kmeans::Kmeans32::load(&args.means).unwrap();
+    let opp_ranges = load_ranges(&args.ranges).unwrap();
+    if opp_ranges.len() != river_means.centers()[0].len() {
+        eprintln!(
+            "{} has {} bins but {} has {} ranges",
+            args.means,
+            river_means.centers()[0].len(),
+            args.ranges,
+            opp_ranges.len()
+        );
+        process::exit(1);
+    }
+    let dataset = generate_transition_histograms(2, &river_means, DistanceMetric::L2, |cards| {
+        ochs_features(cards, &opp_ranges)
+    });
+    save_histograms(&dataset, &args.out).unwrap();
+    println!("Wrote {} histograms to {}", dataset.len(), args.out);
+}
+
+const BUCKETS_USAGE: &str = "usage: gen_abstraction buckets --round <0-3> --means <path> \
+                             --histograms <path> [--metric <l2|l1|emd>] [--out <path>]";
+
+/// options of the buckets mode
+#[derive(Debug, PartialEq)]
+struct BucketArgs {
+    /// betting round (0 -> preflop, 3 -> river)
+    round: usize,
+    /// Kmeans saved with Kmeans::save
+    means: String,
+    /// histograms of every hand of round saved with save_histograms
+    histograms: String,
+    /// distance means was trained with
+    metric: DistanceMetric,
+    /// path to write the bucket table to
+    out: String,
+}
+
+/// parses the arguments of the buckets mode
+fn parse_bucket_args<I: Iterator<Item = String>>(mut args: I) -> Result<BucketArgs, String> {
+    let mut round = None;
+    let mut means = None;
+    let mut histograms = None;
+    let mut metric = DistanceMetric::L2;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", arg)),
+        };
+        match (arg.as_str(), value.parse::<usize>()) {
+            ("--round", Ok(n)) if n <= 3 => round = Some(n),
+            ("--round", _) => return Err(format!("invalid value for {}: {}", arg, value)),
+            ("--means", _) => means = Some(value),
+            ("--histograms", _) => histograms = Some(value),
+            ("--metric", _) => {
+                metric = match value.as_str() {
+                    "l2" => DistanceMetric::L2,
+                    "l1" => DistanceMetric::L1,
+                    "emd" => DistanceMetric::Emd,
+                    _ => return Err(format!("invalid value for {}: {}", arg, value)),
+                }
+            }
+            ("--out", _) => out = Some(value),
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    return match (round, means, histograms) {
+        (Some(round), Some(means), Some(histograms)) => Ok(BucketArgs {
+            round,
+            means,
+            histograms,
+            metric,
+            out: out.unwrap_or_else(|| format!("buckets_round_{}.dat", round)),
+        }),
+        _ => Err(String::from(
+            "--round, --means and --histograms are required",
+        )),
+    };
+}
+
+/// assigns every hand of a round to its bucket and writes the bucket table
+/// the solver maps the table to look up the bucket of a hand index
+fn gen_buckets(args: &BucketArgs) {
+    let means = match kmeans::Kmeans32::load(&args.means) {
+        Ok(means) => means,
+        Err(err) => {
+            eprintln!("{}: {}", args.means, err);
+            process::exit(1);
+        }
+    };
+    let dataset = load_histograms(&args.histograms).unwrap();
+    let hand_indexer = match args.round {
+        0 => HandIndexer::init(1, vec![2]),
+        1 => HandIndexer::init(2, vec![2, 3]),
+        2 => HandIndexer::init(2, vec![2, 4]),
+        _ => HandIndexer::init(2, vec![2, 5]),
+    };
+    let round_size = hand_indexer.size(if args.round == 0 { 0 } else { 1 }) as usize;
+    if dataset.len() != round_size {
+        eprintln!(
+            "{} has {} histograms, round {} has {} hands",
+            args.histograms,
+            dataset.len(),
+            args.round,
+            round_size
+        );
+        process::exit(1);
+    }
+    if means.n_centers() > u32::MAX as usize {
+        eprintln!("{} has too many centers", args.means);
+        process::exit(1);
+    }
+    let mut clusters = vec![0usize; dataset.len()];
+    if let Err(err) = means.predict(&dataset, &mut clusters, args.metric) {
+        eprintln!("{}: {}", args.histograms, err);
+        process::exit(1);
+    }
+    let buckets: Vec<u32> = clusters.iter().map(|c| *c as u32).collect();
+    let n_buckets = means.n_centers() as u32;
+    bucket_format::write_buckets(&args.out, args.round as u32, n_buckets, &buckets).unwrap();
+    println!(
+        "Wrote {} buckets of {} hands to {}",
+        means.n_centers(),
+        buckets.len(),
+        args.out
+    );
+}
+
 fn generate_opponent_clusters(n_opp_clusters: usize) -> Vec<String> {
     let mut thread_rng = thread_rng();
     let n_samples = 10000usize;
@@ -178,16 +816,26 @@ fn generate_opponent_clusters(n_opp_clusters: usize) -> Vec<String> {
     //     &emd::emd_1d,
     //     &opp_features,
     // );
-    let mut estimator =
-        kmeans::Kmeans::init_pp(n_opp_clusters, &mut thread_rng, &emd::emd_1d, &opp_features);
+    let mut estimator = kmeans::Kmeans::init_pp(
+        n_opp_clusters,
+        &mut thread_rng,
+        DistanceMetric::EmdApprox,
+        &opp_features,
+        true,
+    )
+    .unwrap();
     // println!("Running Kmeans");
 
     // estimator.growbatch_rho(&mut thread_rng, &emd::emd_1d, 10, &opp_features);
-    estimator.fit_regular(&opp_features, &emd::emd_1d);
+    estimator
+        .fit(&opp_features, DistanceMetric::EmdApprox)
+        .unwrap();
     // estimator.fit_growbatch(&mut thread_rng, &emd::emd_1d, 50, &opp_features);
 
     let mut opp_clusters = vec![0usize; opp_features.len()];
-    let inertia = estimator.predict(&opp_features, &mut opp_clusters, &emd::emd_1d);
+    let inertia = estimator
+        .predict(&opp_features, &mut opp_clusters, DistanceMetric::EmdApprox)
+        .unwrap();
     println!("{}", inertia / n_opp_clusters as f32);
 
     // transform clusters into range string representation
@@ -314,7 +962,7 @@ fn generate_opponent_clusters(n_opp_clusters: usize) -> Vec<String> {
 //         &mut rng, &kmeans::l2_dist, &train_data);
 
 //     // train kmeans
-//     estimator.fit_regular(&train_data, &kmeans::l2_dist);
+//     estimator.fit(&train_data, &kmeans::l2_dist);
 
 //     estimator.predict(&features, &mut clusters, &kmeans::l2_dist);
 
@@ -361,13 +1009,24 @@ fn gen_emd(round: u8, n_clusters: usize, n_samples: usize, n_bins: usize) {
     let features = generate_histograms(n_samples, round.into(), n_bins);
     let mut clusters = vec![0usize; round_size as usize];
     // let mut estimator = kmeans::Kmeans::init_pp(n_clusters, &mut rng, &emd::emd_1d, &features);
-    let mut estimator =
-        kmeans::Kmeans::init_random(n_restarts, n_clusters, &mut rng, &emd::emd_1d, &features);
+    let mut estimator = kmeans::Kmeans::init_random(
+        n_restarts,
+        n_clusters,
+        &mut rng,
+        DistanceMetric::EmdApprox,
+        &features,
+        true,
+    )
+    .unwrap();
 
     // use mini batches
-    estimator.fit_growbatch(&mut rng, &emd::emd_1d, 10000, &features);
+    estimator
+        .fit_growbatch(&mut rng, DistanceMetric::EmdApprox, 10000, &features)
+        .unwrap();
 
-    estimator.predict(&features, &mut clusters, &emd::emd_1d);
+    estimator
+        .predict(&features, &mut clusters, DistanceMetric::EmdApprox)
+        .unwrap();
 
     let mut file = OpenOptions::new()
         .write(true)
@@ -381,6 +1040,33 @@ fn gen_emd(round: u8, n_clusters: usize, n_samples: usize, n_bins: usize) {
 }
 
 fn main() {
+    let mut args = env::args().skip(1);
+    if let Some(mode) = args.next() {
+        let result = match mode.as_str() {
+            "histograms" => parse_histogram_args(args)
+                .map(|args| gen_histograms(&args))
+                .map_err(|e| format!("{}\n{}", e, HISTOGRAMS_USAGE)),
+            "ochs" => parse_ochs_args(args)
+                .map(|args| gen_ochs_histograms(&args))
+                .map_err(|e| format!("{}\n{}", e, OCHS_USAGE)),
+            "transitions" => parse_transition_args(args)
+                .map(|args| gen_transition_histograms(&args))
+                .map_err(|e| format!("{}\n{}", e, TRANSITIONS_USAGE)),
+            "buckets" => parse_bucket_args(args)
+                .map(|args| gen_buckets(&args))
+                .map_err(|e| format!("{}\n{}", e, BUCKETS_USAGE)),
+            _ => Err(format!(
+                "unknown mode: {}\n{}\n{}\n{}\n{}",
+                mode, HISTOGRAMS_USAGE, OCHS_USAGE, TRANSITIONS_USAGE, BUCKETS_USAGE
+            )),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     // round, n means, n samples, 40 bins
     gen_emd(1, 500, 250, 20);
     // flop
@@ -402,4 +1088,228 @@ mod tests {
     // fn bench_gen_round_0(b: &mut Bencher) {
     //     b.iter(|| generate_round(0));
     // }
+
+    #[test]
+    fn test_save_load_histograms() {
+        let dataset: Vec<Histogram> = vec![vec![0.5, 0.25, 0.25], vec![0.0, 1.0, 0.0]];
+        let path = std::env::temp_dir().join("test_save_load_histograms.dat");
+        let path = path.to_str().unwrap();
+        save_histograms(&dataset, path).unwrap();
+        let loaded = load_histograms(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(dataset, loaded);
+    }
+
+    #[test]
+    fn test_map_histograms() {
+        use kmeans::HistogramSource;
+
+        let dataset: Vec<Histogram> = vec![vec![0.5, 0.25, 0.25], vec![0.0, 1.0, 0.0]];
+        let path = std::env::temp_dir().join("test_map_histograms.dat");
+        let path = path.to_str().unwrap();
+        save_histograms(&dataset, path).unwrap();
+        let mapped = map_histograms(path).unwrap();
+        assert_eq!(mapped.len(), 2);
+        assert_eq!(mapped.n_bins(), 3);
+        let mut histogram = vec![0f32; 3];
+        for (i, h) in dataset.iter().enumerate() {
+            mapped.read(i, &mut histogram);
+            assert_eq!(&histogram, h);
+        }
+        drop(mapped);
+
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(8 + 5 * 4).unwrap();
+        drop(file);
+        let err = map_histograms(path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_histograms_csv() {
+        let path = std::env::temp_dir().join("test_read_histograms.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "0.5,0.25,0.25\n\n0, 1, 0\n").unwrap();
+        let dataset = read_histograms_csv(path, 3).unwrap();
+        assert_eq!(dataset, vec![vec![0.5, 0.25, 0.25], vec![0.0, 1.0, 0.0]]);
+        assert!(read_histograms_csv(path, 2).is_err());
+        std::fs::write(path, "0.5,x,0.25\n").unwrap();
+        assert!(read_histograms_csv(path, 3).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_histograms_bin() {
+        let path = std::env::temp_dir().join("test_read_histograms.bin");
+        let path = path.to_str().unwrap();
+        let mut file = File::create(path).unwrap();
+        file.pack_all(&[0.5f32, 0.5, 0.0, 1.0, 0.25, 0.75][..])
+            .unwrap();
+        drop(file);
+        let dataset = read_histograms_bin(path, 2).unwrap();
+        assert_eq!(
+            dataset,
+            vec![vec![0.5, 0.5], vec![0.0, 1.0], vec![0.25, 0.75]]
+        );
+        assert_eq!(read_histograms_bin(path, 3).unwrap().len(), 2);
+        let err = read_histograms_bin(path, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // the output of save_histograms
+        let saved: Vec<Histogram> = vec![vec![0.5, 0.25, 0.25], vec![0.0, 1.0, 0.0]];
+        save_histograms(&saved, path).unwrap();
+        assert_eq!(read_histograms_bin(path, 3).unwrap(), saved);
+        let err = read_histograms_bin(path, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "f16")]
+    #[test]
+    fn test_read_histograms_half() {
+        let path = std::env::temp_dir().join("test_read_histograms_half.bin");
+        let path = path.to_str().unwrap();
+        let mut file = File::create(path).unwrap();
+        file.pack_all(&[0.5f32, 0.5, 0.1, 0.9][..]).unwrap();
+        drop(file);
+        let dataset = read_histograms_half(path, 2).unwrap();
+        assert_eq!(dataset.len(), 2);
+        assert_eq!(dataset[0][0].to_f32(), 0.5);
+        assert!((dataset[1][0].to_f32() - 0.1).abs() < 1e-4);
+        assert!(read_histograms_half(path, 3).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_histogram_args() {
+        let parse = |args: &[&str]| parse_histogram_args(args.iter().map(|a| a.to_string()));
+        assert_eq!(
+            parse(&["--round", "1"]),
+            Ok(HistogramArgs {
+                round: 1,
+                bins: 30,
+                samples: 1000,
+                out: String::from("histograms_round_1.dat"),
+            })
+        );
+        let args = parse(&[
+            "--round",
+            "2",
+            "--bins",
+            "50",
+            "--samples",
+            "200",
+            "--out",
+            "a.dat",
+        ]);
+        assert_eq!(args.unwrap().out, "a.dat");
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--round", "3"]).is_err());
+        assert!(parse(&["--round", "1", "--bins", "0"]).is_err());
+        assert!(parse(&["--round"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_ochs_args() {
+        let parse = |args: &[&str]| parse_ochs_args(args.iter().map(|a| a.to_string()));
+        assert_eq!(
+            parse(&["--round", "3"]),
+            Ok(OchsArgs {
+                round: 3,
+                ranges: None,
+                out: String::from("ochs_round_3.dat"),
+            })
+        );
+        let args = parse(&["--round", "1", "--ranges", "r.txt", "--out", "a.dat"]).unwrap();
+        assert_eq!(args.ranges, Some(String::from("r.txt")));
+        assert_eq!(args.out, "a.dat");
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--round", "4"]).is_err());
+        assert!(parse(&["--round", "1", "--bins", "8"]).is_err());
+    }
+
+    #[test]
+    fn test_load_ranges() {
+        let path = std::env::temp_dir().join("test_load_ranges.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "AA,KK\n\n  22+ \n").unwrap();
+        let ranges = load_ranges(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ranges, vec!["AA,KK", "22+"]);
+    }
+
+    #[test]
+    fn test_ochs_features() {
+        // AsAh
+        let ranges = vec![String::from("KK"), String::from("72o"), String::from("AA")];
+        let features = ochs_features(&[51, 50], &ranges);
+        assert_eq!(features.len(), 3);
+        assert!(features[0] > 0.75 && features[0] < 0.9);
+        assert!(features[1] > 0.85);
+        // only AdAc is left for the opponent
+        assert!((features[2] - 0.5).abs() < 0.05);
+        // the board blocks every hand of the last cluster
+        let features = ochs_features(&[51, 50, 49, 48, 0], &ranges);
+        assert_eq!(features[2], 0.5);
+    }
+
+    #[test]
+    fn test_parse_transition_args() {
+        let parse = |args: &[&str]| parse_transition_args(args.iter().map(|a| a.to_string()));
+        assert_eq!(
+            parse(&["--means", "river.dat", "--ranges", "r.txt"]),
+            Ok(TransitionArgs {
+                means: String::from("river.dat"),
+                ranges: String::from("r.txt"),
+                out: String::from("transitions_round_2.dat"),
+            })
+        );
+        assert!(parse(&["--means", "river.dat"]).is_err());
+        assert!(parse(&["--means"]).is_err());
+        assert!(parse(&["--bins", "8"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_bucket_args() {
+        let parse = |args: &[&str]| parse_bucket_args(args.iter().map(|a| a.to_string()));
+        assert_eq!(
+            parse(&["--round", "3", "--means", "m.dat", "--histograms", "h.dat"]),
+            Ok(BucketArgs {
+                round: 3,
+                means: String::from("m.dat"),
+                histograms: String::from("h.dat"),
+                metric: DistanceMetric::L2,
+                out: String::from("buckets_round_3.dat"),
+            })
+        );
+        let args = parse(&[
+            "--round",
+            "1",
+            "--means",
+            "m.dat",
+            "--histograms",
+            "h.dat",
+            "--metric",
+            "emd",
+        ]);
+        assert_eq!(args.unwrap().metric, DistanceMetric::Emd);
+        assert!(parse(&["--round", "1", "--means", "m.dat"]).is_err());
+        assert!(parse(&["--round", "4", "--means", "m.dat", "--histograms", "h.dat"]).is_err());
+        assert!(parse(&["--metric", "cos"]).is_err());
+    }
+
+    #[test]
+    fn test_transition_histogram() {
+        // bucket river cards by rank, low cards -> 0, high cards -> 1
+        let means = kmeans::Kmeans32::from_centers(vec![vec![0.0], vec![1.0]]);
+        let rank = |cards: &[u8]| vec![(cards[6] >> 2) as f32 / 12.0];
+        // two aces, deuces and treys are used, 46 river cards left
+        let cards = [51u8, 50, 0, 1, 4, 5];
+        let hist = transition_histogram(&cards, &means, DistanceMetric::L2, rank);
+        assert_eq!(hist.len(), 2);
+        // 2..8 go to bucket 0 (8 is a tie), 9..A to bucket 1
+        assert!((hist[0] - 24.0 / 46.0).abs() < 1e-6);
+        assert!((hist[1] - 22.0 / 46.0).abs() < 1e-6);
+    }
 }
@@ -0,0 +1,312 @@
+/**
+ * Sparse histograms and their distances
+ *
+ * the transition histograms of a turn hand over the river buckets are mostly
+ * empty bins, storing only the nonzero bins saves memory and the distances
+ * below only visit them, see Kmeans::fit_sparse
+ */
+use crate::kmeans::{DistanceMetric, Histogram, HistogramSource};
+
+/// Histogram of `len` bins where only the bins at `indices` can be nonzero
+/// indices are strictly increasing and below len, values[i] is bin indices[i]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseHistogram {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+    pub len: usize,
+}
+
+impl SparseHistogram {
+    /// panics if indices and values differ in length,
+    /// or indices aren't strictly increasing and below len
+    pub fn new(indices: Vec<u32>, values: Vec<f32>, len: usize) -> SparseHistogram {
+        if indices.len() != values.len() {
+            panic!(
+                "sparse histogram has {} indices and {} values",
+                indices.len(),
+                values.len()
+            );
+        }
+        if indices.windows(2).any(|w| w[0] >= w[1]) {
+            panic!("sparse histogram indices are not strictly increasing");
+        }
+        if let Some(last) = indices.last() {
+            if *last as usize >= len {
+                panic!("sparse histogram index {} is not below {}", last, len);
+            }
+        }
+        return SparseHistogram {
+            indices,
+            values,
+            len,
+        };
+    }
+
+    /// keeps the nonzero bins of a dense histogram
+    pub fn from_dense(dense: &[f32]) -> SparseHistogram {
+        let mut indices = Vec::new();
+        let mut values = Vec::new();
+        for (i, v) in dense.iter().enumerate() {
+            if *v != 0.0 {
+                indices.push(i as u32);
+                values.push(*v);
+            }
+        }
+        return SparseHistogram {
+            indices,
+            values,
+            len: dense.len(),
+        };
+    }
+
+    pub fn to_dense(&self) -> Histogram {
+        let mut dense = vec![0f32; self.len];
+        self.write_dense(&mut dense);
+        return dense;
+    }
+
+    /// writes every bin to out, which has len elements
+    pub fn write_dense(&self, out: &mut [f32]) {
+        for v in out.iter_mut() {
+            *v = 0.0;
+        }
+        for (i, v) in self.iter() {
+            out[i] = v;
+        }
+    }
+
+    /// number of stored bins
+    pub fn nnz(&self) -> usize {
+        return self.indices.len();
+    }
+
+    /// stored bins and their values in increasing order of bin
+    pub fn iter(&self) -> impl Iterator<Item = (usize, f32)> + '_ {
+        return self
+            .indices
+            .iter()
+            .zip(self.values.iter())
+            .map(|(i, v)| (*i as usize, *v));
+    }
+}
+
+/// the histograms are expanded to dense one at a time on read
+impl HistogramSource<f32> for [SparseHistogram] {
+    fn len(&self) -> usize {
+        return <[SparseHistogram]>::len(self);
+    }
+
+    fn n_bins(&self) -> usize {
+        return self.first().map_or(0, |h| h.len);
+    }
+
+    fn read(&self, i: usize, out: &mut [f32]) {
+        self[i].write_dense(out);
+    }
+}
+
+/// calls f with every bin stored in a or b in increasing order of bin
+/// and its value in a and b, 0 if it isn't stored
+fn merge<G: FnMut(usize, f32, f32)>(a: &SparseHistogram, b: &SparseHistogram, mut f: G) {
+    let (mut i, mut j) = (0, 0);
+    while i < a.nnz() || j < b.nnz() {
+        let ai = a.indices.get(i).map_or(u32::MAX, |x| *x);
+        let bj = b.indices.get(j).map_or(u32::MAX, |x| *x);
+        if ai == bj {
+            f(ai as usize, a.values[i], b.values[j]);
+            i += 1;
+            j += 1;
+        } else if ai < bj {
+            f(ai as usize, a.values[i], 0.0);
+            i += 1;
+        } else {
+            f(bj as usize, 0.0, b.values[j]);
+            j += 1;
+        }
+    }
+}
+
+/// l2_dist of two sparse histograms of the same length
+pub fn l2_dist(a: &SparseHistogram, b: &SparseHistogram) -> f32 {
+    let mut sum = 0f32;
+    merge(a, b, |_, x, y| sum += (x - y) * (x - y));
+    return sum.sqrt();
+}
+
+/// l1_dist of two sparse histograms of the same length
+pub fn l1_dist(a: &SparseHistogram, b: &SparseHistogram) -> f32 {
+    let mut sum = 0f32;
+    merge(a, b, |_, x, y| sum += (x - y).abs());
+    return sum;
+}
+
+/// emd_dist of two sparse histograms of the same length
+/// the running difference only changes at stored bins and counts
+/// once for every bin until the next one
+pub fn emd_dist(a: &SparseHistogram, b: &SparseHistogram) -> f32 {
+    let mut sum = 0f32;
+    let mut carry = 0f32;
+    let mut previous = 0;
+    merge(a, b, |i, x, y| {
+        sum += carry.abs() * (i - previous) as f32;
+        carry += x - y;
+        previous = i;
+    });
+    return sum + carry.abs() * (a.len - previous) as f32;
+}
+
+/// A dense center with the sums the distances from sparse histograms
+/// to it need, computed once per pass over the dataset
+pub struct DenseCenter<'a> {
+    pub values: &'a [f32],
+    /// sum of the squares of the bins
+    sq_sum: f32,
+    /// sum of the absolute values of the bins
+    abs_sum: f32,
+}
+
+impl<'a> DenseCenter<'a> {
+    pub fn new(values: &'a [f32]) -> DenseCenter<'a> {
+        return DenseCenter {
+            values,
+            sq_sum: values.iter().map(|v| v * v).sum(),
+            abs_sum: values.iter().map(|v| v.abs()).sum(),
+        };
+    }
+}
+
+/// l2_dist from a sparse histogram to a dense center
+/// starts from the distance to an empty histogram and corrects the stored bins,
+/// so rounding differs slightly from l2_dist on the dense histogram
+pub fn l2_dist_dense(a: &SparseHistogram, b: &DenseCenter) -> f32 {
+    let mut sum = b.sq_sum;
+    for (i, x) in a.iter() {
+        let y = b.values[i];
+        sum += (x - y) * (x - y) - y * y;
+    }
+    return sum.max(0.0).sqrt();
+}
+
+/// l1_dist from a sparse histogram to a dense center, like l2_dist_dense
+pub fn l1_dist_dense(a: &SparseHistogram, b: &DenseCenter) -> f32 {
+    let mut sum = b.abs_sum;
+    for (i, x) in a.iter() {
+        let y = b.values[i];
+        sum += (x - y).abs() - y.abs();
+    }
+    return sum.max(0.0);
+}
+
+/// emd_dist from a sparse histogram to a dense histogram
+/// the prefix sums of the dense histogram change at every bin,
+/// so unlike l2_dist_dense every bin is visited
+pub fn emd_dist_dense(a: &SparseHistogram, b: &[f32]) -> f32 {
+    let mut sum = 0f32;
+    let mut carry = 0f32;
+    // next stored bin of a
+    let mut j = 0;
+    for (i, y) in b.iter().enumerate() {
+        if j < a.nnz() && a.indices[j] as usize == i {
+            carry += a.values[j];
+            j += 1;
+        }
+        carry -= y;
+        sum += carry.abs();
+    }
+    return sum;
+}
+
+/// distance from a sparse histogram to a dense center with metric
+/// l2, l1 and emd use the sparse distances, other metrics the dense
+/// distance on the expanded histogram
+pub fn dist_dense(metric: DistanceMetric, a: &SparseHistogram, b: &DenseCenter) -> f32 {
+    return match metric {
+        DistanceMetric::L2 => l2_dist_dense(a, b),
+        DistanceMetric::L1 => l1_dist_dense(a, b),
+        DistanceMetric::Emd => emd_dist_dense(a, b.values),
+        _ => metric.compute(&a.to_dense(), b.values),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmeans;
+
+    const ERROR: f32 = 1e-5;
+
+    fn histograms() -> Vec<Histogram> {
+        return vec![
+            vec![0.0, 0.5, 0.0, 0.0, 0.5, 0.0],
+            vec![0.25, 0.0, 0.0, 0.75, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            vec![0.0; 6],
+            vec![0.1, 0.2, 0.1, 0.2, 0.3, 0.1],
+        ];
+    }
+
+    #[test]
+    fn test_sparse_histogram() {
+        let dense = vec![0.0, 0.5, 0.0, 0.0, 0.5, 0.0];
+        let sparse = SparseHistogram::from_dense(&dense);
+        assert_eq!(sparse, SparseHistogram::new(vec![1, 4], vec![0.5, 0.5], 6));
+        assert_eq!(sparse.nnz(), 2);
+        assert_eq!(sparse.to_dense(), dense);
+
+        let source = vec![sparse.clone(), SparseHistogram::new(vec![], vec![], 6)];
+        assert_eq!(HistogramSource::n_bins(&source[..]), 6);
+        let mut out = vec![1f32; 6];
+        source[..].read(1, &mut out);
+        assert_eq!(out, vec![0.0; 6]);
+    }
+
+    #[test]
+    fn test_distances() {
+        let dense = histograms();
+        let sparse: Vec<SparseHistogram> = dense
+            .iter()
+            .map(|h| SparseHistogram::from_dense(h))
+            .collect();
+        for (a, sa) in dense.iter().zip(&sparse) {
+            for (b, sb) in dense.iter().zip(&sparse) {
+                let center = DenseCenter::new(b);
+                let expected = [
+                    (
+                        kmeans::l2_dist(a, b),
+                        l2_dist(sa, sb),
+                        l2_dist_dense(sa, &center),
+                    ),
+                    (
+                        kmeans::l1_dist(a, b),
+                        l1_dist(sa, sb),
+                        l1_dist_dense(sa, &center),
+                    ),
+                    (
+                        kmeans::emd_dist(a, b),
+                        emd_dist(sa, sb),
+                        emd_dist_dense(sa, b),
+                    ),
+                ];
+                for (d, s, sd) in expected.iter() {
+                    assert!((d - s).abs() < ERROR, "{} {} {:?} {:?}", d, s, a, b);
+                    assert!((d - sd).abs() < ERROR, "{} {} {:?} {:?}", d, sd, a, b);
+                }
+                let d = DistanceMetric::Cosine.compute(a, b);
+                let sd = dist_dense(DistanceMetric::Cosine, sa, &center);
+                assert!((d - sd).abs() < ERROR);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not strictly increasing")]
+    fn test_new_unsorted() {
+        SparseHistogram::new(vec![2, 1], vec![0.5, 0.5], 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "index 6 is not below 6")]
+    fn test_new_out_of_range() {
+        SparseHistogram::new(vec![1, 6], vec![0.5, 0.5], 6);
+    }
+}
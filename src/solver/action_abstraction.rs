@@ -1,6 +1,7 @@
 use crate::state::{GameState};
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum Action {
     Bet(f64),
     Raise(f64),
@@ -0,0 +1,853 @@
+use rust_poker::hand_evaluator::{evaluate, Hand, CARDS};
+use std::fmt;
+
+use crate::action_abstraction::Action;
+use crate::bucket_map::BucketMap;
+use crate::tree::{NodeId, Tree};
+
+/**
+ * Betting options of a single street between two players
+ * player 0 acts first
+ */
+#[derive(Debug, Clone)]
+pub struct TreeConfig {
+    /// chips behind for each player
+    pub stack: f32,
+    /// chips in the pot before the street, put in evenly by both players
+    pub pot: f32,
+    /// bet and raise sizes as fractions of the pot
+    pub bet_sizes: Vec<f32>,
+    /// whether a bet can be raised
+    pub allow_raise: bool,
+    /// max number of raises on the street
+    pub max_raises: u8,
+}
+
+/**
+ * A node of the game tree
+ * the children of a decision node are in the order of its actions,
+ * the children of a chance node in the order of its outcomes
+ */
+#[derive(Debug)]
+pub enum GameNode {
+    Decision {
+        /// player to act
+        player: u8,
+        /// index of the information set, nodes the player can't tell apart share it
+        infoset: usize,
+        actions: Vec<Action>,
+    },
+    Chance {
+        /// probability of each child
+        outcomes: Vec<f32>,
+    },
+    Terminal {
+        /// chips won by each player
+        payoff: [f32; 2],
+    },
+}
+
+impl GameNode {
+    pub fn decision(player: u8, infoset: usize, actions: Vec<Action>) -> GameNode {
+        return GameNode::Decision {
+            player,
+            infoset,
+            actions,
+        };
+    }
+    pub fn chance(outcomes: Vec<f32>) -> GameNode {
+        return GameNode::Chance { outcomes };
+    }
+    pub fn terminal(payoff: [f32; 2]) -> GameNode {
+        return GameNode::Terminal { payoff };
+    }
+    pub fn is_terminal(&self) -> bool {
+        return matches!(self, GameNode::Terminal { .. });
+    }
+    /// player to act at decision nodes
+    pub fn player(&self) -> Option<u8> {
+        match self {
+            GameNode::Decision { player, .. } => return Some(*player),
+            _ => return None,
+        }
+    }
+    /// infoset of decision nodes
+    pub fn infoset(&self) -> Option<usize> {
+        match self {
+            GameNode::Decision { infoset, .. } => return Some(*infoset),
+            _ => return None,
+        }
+    }
+    /// actions of decision nodes, empty otherwise
+    pub fn actions(&self) -> &[Action] {
+        match self {
+            GameNode::Decision { actions, .. } => return actions,
+            _ => return &[],
+        }
+    }
+    /// payoff of terminal nodes
+    pub fn payoff(&self) -> Option<[f32; 2]> {
+        match self {
+            GameNode::Terminal { payoff } => return Some(*payoff),
+            _ => return None,
+        }
+    }
+}
+
+/// e.g. "P0 [Check, Bet 0.5]", "Chance [0.5, 0.5]" or "Terminal [1.0, -1.0]"
+impl fmt::Display for GameNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameNode::Decision {
+                player, actions, ..
+            } => {
+                let actions: Vec<String> = actions.iter().map(|a| a.to_string()).collect();
+                return write!(f, "P{} [{}]", player, actions.join(", "));
+            }
+            GameNode::Chance { outcomes } => return write!(f, "Chance {:?}", outcomes),
+            GameNode::Terminal { payoff } => return write!(f, "Terminal {:?}", payoff),
+        }
+    }
+}
+
+/**
+ * Renders the tree under root like Tree::format_tree,
+ * each child is labeled with the action leading to it
+ * e.g. "Bet 0.5 -> P1 [Call, Fold]"
+ */
+pub fn format_game_tree(tree: &Tree<GameNode>, root: NodeId) -> String {
+    let mut out = String::new();
+    let mut stack: Vec<(NodeId, usize, Option<&Action>)> = vec![(root, 0, None)];
+    while let Some((id, depth, action)) = stack.pop() {
+        out.push_str(&"  ".repeat(depth));
+        if let Some(action) = action {
+            out.push_str(&format!("{} -> ", action.to_string()));
+        }
+        out.push_str(&format!("{}\n", tree.data(id)));
+        let children = &tree.get_node(id).children;
+        let actions = tree.data(id).actions();
+        for (i, child) in children.iter().enumerate().rev() {
+            stack.push((*child, depth + 1, actions.get(i)));
+        }
+    }
+    return out;
+}
+
+/// betting state while building the tree
+#[derive(Debug, Clone, Copy)]
+struct BetState {
+    player: u8,
+    wagers: [f32; 2],
+    stacks: [f32; 2],
+    raises: u8,
+}
+
+impl BetState {
+    fn pot(&self) -> f32 {
+        return self.wagers[0] + self.wagers[1];
+    }
+    fn call_size(&self) -> f32 {
+        let p = usize::from(self.player);
+        return self.wagers[1 - p] - self.wagers[p];
+    }
+    /// chips the player puts in for action, capped at their stack
+    fn chips(&self, action: &Action) -> f32 {
+        let p = usize::from(self.player);
+        let chips = match action {
+            Action::Check | Action::Fold => 0.0,
+            Action::Call => self.call_size(),
+            Action::Bet(size) => *size as f32 * self.pot(),
+            // call, then bet size of the pot after the call
+            Action::Raise(size) => {
+                self.call_size() + *size as f32 * (self.pot() + self.call_size())
+            }
+        };
+        return chips.min(self.stacks[p]);
+    }
+    fn apply(&self, action: &Action) -> BetState {
+        let p = usize::from(self.player);
+        let chips = self.chips(action);
+        let mut next = *self;
+        next.wagers[p] += chips;
+        next.stacks[p] -= chips;
+        if let Action::Raise(_) = action {
+            next.raises += 1;
+        }
+        next.player = 1 - self.player;
+        return next;
+    }
+    /// actions in the order of GameState::valid_actions
+    fn valid_actions(&self, config: &TreeConfig) -> Vec<Action> {
+        let p = usize::from(self.player);
+        let can_bet = self.stacks[p] > 0.0 && self.stacks[1 - p] > 0.0;
+        let mut actions = Vec::new();
+        if self.call_size() == 0.0 {
+            actions.push(Action::Check);
+            if can_bet {
+                self.push_sizes(&mut actions, config, Action::Bet);
+            }
+        } else {
+            actions.push(Action::Call);
+            actions.push(Action::Fold);
+            let can_raise = config.allow_raise
+                && self.raises < config.max_raises
+                && self.stacks[p] > self.call_size();
+            if can_bet && can_raise {
+                self.push_sizes(&mut actions, config, Action::Raise);
+            }
+        }
+        return actions;
+    }
+    /// pushes a bet or raise for each size, stops after the first all in
+    fn push_sizes<A: Fn(f64) -> Action>(
+        &self,
+        actions: &mut Vec<Action>,
+        config: &TreeConfig,
+        action: A,
+    ) {
+        for size in &config.bet_sizes {
+            let a = action(*size as f64);
+            let all_in = self.chips(&a) >= self.stacks[usize::from(self.player)];
+            actions.push(a);
+            if all_in {
+                break;
+            }
+        }
+    }
+}
+
+/**
+ * Builds the betting tree of a single street
+ * showdown returns the payoff for the chips each player put in when nobody folded,
+ * decision nodes get sequential infosets starting at 0
+ * returns the tree and its root
+ */
+pub fn build_tree(
+    config: &TreeConfig,
+    showdown: &dyn Fn(&[f32; 2]) -> [f32; 2],
+) -> (Tree<GameNode>, NodeId) {
+    let mut tree = Tree::new();
+    let mut n_infosets = 0;
+    let root = build_node(
+        &mut tree,
+        None,
+        config,
+        &showdown_terminal(showdown),
+        &initial_state(config),
+        &mut Vec::new(),
+        &mut n_infosets,
+    );
+    return (tree, root);
+}
+
+/**
+ * Builds the betting tree of a single street as a child of parent
+ * the infosets of the subtree start at 0 like in build_tree
+ * returns the root of the subtree
+ */
+pub fn build_subtree(
+    tree: &mut Tree<GameNode>,
+    parent: NodeId,
+    config: &TreeConfig,
+    showdown: &dyn Fn(&[f32; 2]) -> [f32; 2],
+) -> NodeId {
+    let mut n_infosets = 0;
+    return build_node(
+        tree,
+        Some(parent),
+        config,
+        &showdown_terminal(showdown),
+        &initial_state(config),
+        &mut Vec::new(),
+        &mut n_infosets,
+    );
+}
+
+/// terminal payoffs of build_node where the folding player loses
+/// what they put in and showdown pays otherwise
+fn showdown_terminal<'a>(
+    showdown: &'a dyn Fn(&[f32; 2]) -> [f32; 2],
+) -> impl Fn(&[Action], &[f32; 2]) -> [f32; 2] + 'a {
+    return move |history: &[Action], wagers: &[f32; 2]| match history.last() {
+        Some(Action::Fold) => return fold_payoff(history, wagers),
+        _ => return showdown(wagers),
+    };
+}
+
+/// payoff when the last action of history is a fold,
+/// players alternate starting with player 0
+fn fold_payoff(history: &[Action], wagers: &[f32; 2]) -> [f32; 2] {
+    // folding player loses what they put in
+    let p = (history.len() - 1) % 2;
+    let mut payoff = [0.0; 2];
+    payoff[p] = -wagers[p];
+    payoff[1 - p] = wagers[p];
+    return payoff;
+}
+
+/// chips each player put in after history from the start of the street
+pub fn history_wagers(config: &TreeConfig, history: &[Action]) -> [f32; 2] {
+    let mut state = initial_state(config);
+    for action in history {
+        state = state.apply(action);
+    }
+    return state.wagers;
+}
+
+/**
+ * Payoff of the terminal nodes of a bucketed betting tree
+ * decouples the rules that pay the players from the tree and CFR,
+ * which only read the payoffs stored in the terminal nodes
+ */
+pub trait PayoffFn {
+    /**
+     * Chips won by player 0 (hero) at the terminal node reached by history,
+     * player 1 (villain) wins the negation
+     * history holds the actions from the start of the street,
+     * players alternate starting with player 0
+     */
+    fn payoff(&self, history: &[Action], hero_bucket: usize, villain_bucket: usize) -> f32;
+}
+
+/**
+ * Pays folds from the pot and showdowns from the mean EHS of the buckets
+ * hero wins a showdown with probability
+ * a (1 - b) / (a (1 - b) + b (1 - a)) for hero EHS a and villain EHS b,
+ * 0.5 for equal buckets and 1 against a bucket of EHS 0,
+ * only an approximation since EHS is the equity against a random hand
+ */
+#[derive(Debug, Clone)]
+pub struct EhsPayoff {
+    config: TreeConfig,
+    /// mean EHS of each bucket
+    bucket_ehs: Vec<f32>,
+}
+
+impl EhsPayoff {
+    pub fn new(config: &TreeConfig, bucket_ehs: Vec<f32>) -> EhsPayoff {
+        return EhsPayoff {
+            config: config.clone(),
+            bucket_ehs,
+        };
+    }
+
+    /**
+     * Averages the EHS of the hands of each bucket
+     * ehs holds the EHS of every hand index of the round of bucket_map,
+     * e.g. a round of the table gen_ehs writes
+     * panics if there is not one EHS per hand index
+     */
+    pub fn from_table(config: &TreeConfig, bucket_map: &BucketMap, ehs: &[f64]) -> EhsPayoff {
+        if ehs.len() != bucket_map.n_hands() {
+            panic!(
+                "EHS table has {} hands, the buckets {}",
+                ehs.len(),
+                bucket_map.n_hands()
+            );
+        }
+        let mut sums = vec![0f64; bucket_map.n_buckets()];
+        let mut counts = vec![0usize; bucket_map.n_buckets()];
+        for (index, e) in ehs.iter().enumerate() {
+            let bucket = bucket_map.index_bucket(index);
+            sums[bucket] += e;
+            counts[bucket] += 1;
+        }
+        let bucket_ehs = sums
+            .iter()
+            .zip(counts.iter())
+            .map(|(sum, count)| (sum / (*count).max(1) as f64) as f32)
+            .collect();
+        return EhsPayoff::new(config, bucket_ehs);
+    }
+
+    /// probability that hero_bucket wins a showdown against villain_bucket
+    pub fn win_probability(&self, hero_bucket: usize, villain_bucket: usize) -> f32 {
+        let a = self.bucket_ehs[hero_bucket];
+        let b = self.bucket_ehs[villain_bucket];
+        let (win, lose) = (a * (1.0 - b), b * (1.0 - a));
+        if win + lose == 0.0 {
+            return 0.5;
+        }
+        return win / (win + lose);
+    }
+}
+
+impl PayoffFn for EhsPayoff {
+    fn payoff(&self, history: &[Action], hero_bucket: usize, villain_bucket: usize) -> f32 {
+        let wagers = history_wagers(&self.config, history);
+        if let Some(Action::Fold) = history.last() {
+            return fold_payoff(history, &wagers)[0];
+        }
+        let p = self.win_probability(hero_bucket, villain_bucket);
+        return p * wagers[1] - (1.0 - p) * wagers[0];
+    }
+}
+
+/// adds offsets[player] to the infosets of the decision nodes of player under root
+pub fn offset_infosets(tree: &mut Tree<GameNode>, root: NodeId, offsets: [usize; 2]) {
+    tree.for_each_mut(root, |_, node| {
+        if let GameNode::Decision {
+            player, infoset, ..
+        } = node
+        {
+            *infoset += offsets[usize::from(*player)];
+        }
+    });
+}
+
+/// hole cards of both players and the board
+#[derive(Debug, Clone)]
+pub struct Deal {
+    pub hands: [[u8; 2]; 2],
+    pub board: Vec<u8>,
+}
+
+impl Deal {
+    /// hole cards of player followed by the board
+    pub fn cards(&self, player: u8) -> Vec<u8> {
+        let mut cards = self.hands[usize::from(player)].to_vec();
+        cards.extend(&self.board);
+        return cards;
+    }
+    fn rank(&self, player: u8) -> u16 {
+        let mut hand = Hand::default();
+        for c in self.cards(player) {
+            hand += CARDS[usize::from(c)];
+        }
+        return evaluate(&hand);
+    }
+}
+
+/**
+ * Builds a tree that deals one of deals with the same probability,
+ * followed by the betting tree of config where the best hand wins the showdown
+ * the infoset of a decision node is the bucket of the hand of the acting player
+ * times the number of decisions in the betting tree plus the index of the node
+ * in the betting tree, so hands in a bucket with the same action history
+ * share their regrets
+ */
+pub fn build_bucketed_tree(
+    config: &TreeConfig,
+    deals: &[Deal],
+    bucket_map: &BucketMap,
+) -> (Tree<GameNode>, NodeId) {
+    return build_dealt_tree(config, deals, bucket_map, &|deal, _, history, wagers| {
+        if let Some(Action::Fold) = history.last() {
+            return fold_payoff(history, wagers);
+        }
+        let ranks = [deal.rank(0), deal.rank(1)];
+        if ranks[0] > ranks[1] {
+            return [wagers[1], -wagers[1]];
+        } else if ranks[0] < ranks[1] {
+            return [-wagers[0], wagers[0]];
+        }
+        return [0.0, 0.0];
+    });
+}
+
+/**
+ * Like build_bucketed_tree but every terminal node is paid by payoff_fn
+ * from the buckets of the deal instead of the best hand,
+ * e.g. EhsPayoff for showdowns of buckets by EHS
+ */
+pub fn build_payoff_tree(
+    config: &TreeConfig,
+    deals: &[Deal],
+    bucket_map: &BucketMap,
+    payoff_fn: &dyn PayoffFn,
+) -> (Tree<GameNode>, NodeId) {
+    return build_dealt_tree(config, deals, bucket_map, &|_, buckets, history, _| {
+        let payoff = payoff_fn.payoff(history, buckets[0], buckets[1]);
+        return [payoff, -payoff];
+    });
+}
+
+/// chance node over deals followed by a betting tree per deal with bucketed infosets,
+/// terminal pays the terminal node reached by history in the deal given
+/// the buckets of both players and the chips they put in
+fn build_dealt_tree(
+    config: &TreeConfig,
+    deals: &[Deal],
+    bucket_map: &BucketMap,
+    terminal: &dyn Fn(&Deal, [usize; 2], &[Action], &[f32; 2]) -> [f32; 2],
+) -> (Tree<GameNode>, NodeId) {
+    let mut tree = Tree::new();
+    let probability = 1.0 / deals.len() as f32;
+    let root = tree.create_node(None, GameNode::chance(vec![probability; deals.len()]));
+    let mut n_decisions = 0;
+    for deal in deals {
+        let buckets = [
+            bucket_map.bucket(&deal.cards(0)),
+            bucket_map.bucket(&deal.cards(1)),
+        ];
+        let deal_terminal =
+            |history: &[Action], wagers: &[f32; 2]| terminal(deal, buckets, history, wagers);
+        let subtree = build_node(
+            &mut tree,
+            Some(root),
+            config,
+            &deal_terminal,
+            &initial_state(config),
+            &mut Vec::new(),
+            &mut 0,
+        );
+        if n_decisions == 0 {
+            n_decisions = tree
+                .iter_dfs(subtree)
+                .filter(|node| node.infoset().is_some())
+                .count();
+        }
+        offset_infosets(
+            &mut tree,
+            subtree,
+            [buckets[0] * n_decisions, buckets[1] * n_decisions],
+        );
+    }
+    return (tree, root);
+}
+
+fn initial_state(config: &TreeConfig) -> BetState {
+    return BetState {
+        player: 0,
+        wagers: [config.pot / 2.0; 2],
+        stacks: [config.stack; 2],
+        raises: 0,
+    };
+}
+
+/// creates the decision node of state and its subtree
+fn build_node(
+    tree: &mut Tree<GameNode>,
+    parent: Option<NodeId>,
+    config: &TreeConfig,
+    terminal: &dyn Fn(&[Action], &[f32; 2]) -> [f32; 2],
+    state: &BetState,
+    history: &mut Vec<Action>,
+    n_infosets: &mut usize,
+) -> NodeId {
+    let actions = state.valid_actions(config);
+    let data = GameNode::decision(state.player, *n_infosets, actions.clone());
+    *n_infosets += 1;
+    let node = match parent {
+        Some(parent) => tree.create_child(parent, data),
+        None => tree.create_node(None, data),
+    };
+    for action in actions {
+        let next = state.apply(&action);
+        history.push(action);
+        match action {
+            Action::Fold | Action::Call => {
+                let payoff = terminal(history, &next.wagers);
+                tree.create_child(node, GameNode::terminal(payoff));
+            }
+            // player 1 checks back
+            Action::Check if state.player == 1 => {
+                let payoff = terminal(history, &next.wagers);
+                tree.create_child(node, GameNode::terminal(payoff));
+            }
+            _ => {
+                build_node(
+                    tree,
+                    Some(node),
+                    config,
+                    terminal,
+                    &next,
+                    history,
+                    n_infosets,
+                );
+            }
+        }
+        history.pop();
+    }
+    return node;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::BettingRound;
+
+    fn kuhn_config() -> TreeConfig {
+        return TreeConfig {
+            stack: 1.0,
+            pot: 2.0,
+            bet_sizes: vec![0.5],
+            allow_raise: false,
+            max_raises: 0,
+        };
+    }
+
+    /// player 0 always wins the showdown
+    fn p0_wins(wagers: &[f32; 2]) -> [f32; 2] {
+        return [wagers[1], -wagers[1]];
+    }
+
+    fn actions(tree: &Tree<GameNode>, node: NodeId) -> Vec<String> {
+        return tree
+            .data(node)
+            .actions()
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+    }
+
+    #[test]
+    fn test_game_node() {
+        let node = GameNode::decision(1, 3, vec![Action::Check]);
+        assert_eq!(node.player(), Some(1));
+        assert_eq!(node.infoset(), Some(3));
+        assert_eq!(node.actions().len(), 1);
+        assert!(!node.is_terminal());
+        let node = GameNode::chance(vec![0.5, 0.5]);
+        assert_eq!(node.player(), None);
+        assert!(node.actions().is_empty());
+        let node = GameNode::terminal([1.0, -1.0]);
+        assert!(node.is_terminal());
+        assert_eq!(node.payoff(), Some([1.0, -1.0]));
+    }
+
+    #[test]
+    fn test_display() {
+        let node = GameNode::decision(1, 0, vec![Action::Call, Action::Fold]);
+        assert_eq!(node.to_string(), "P1 [Call, Fold]");
+        assert_eq!(
+            GameNode::chance(vec![0.5, 0.5]).to_string(),
+            "Chance [0.5, 0.5]"
+        );
+        assert_eq!(
+            GameNode::terminal([1.0, -1.0]).to_string(),
+            "Terminal [1.0, -1.0]"
+        );
+    }
+
+    #[test]
+    fn test_format_game_tree() {
+        let (tree, root) = build_tree(&kuhn_config(), &p0_wins);
+        let expected = [
+            "P0 [Check, Bet 0.5]",
+            "  Check -> P1 [Check, Bet 0.5]",
+            "    Check -> Terminal [1.0, -1.0]",
+            "    Bet 0.5 -> P0 [Call, Fold]",
+            "      Call -> Terminal [2.0, -2.0]",
+            "      Fold -> Terminal [-1.0, 1.0]",
+            "  Bet 0.5 -> P1 [Call, Fold]",
+            "    Call -> Terminal [2.0, -2.0]",
+            "    Fold -> Terminal [1.0, -1.0]",
+            "",
+        ];
+        assert_eq!(format_game_tree(&tree, root), expected.join("\n"));
+        assert_eq!(
+            tree.format_tree(root, |n| n.to_string()).lines().nth(1),
+            Some("  P1 [Check, Bet 0.5]")
+        );
+    }
+
+    #[test]
+    fn test_build_kuhn_betting() {
+        let (tree, root) = build_tree(&kuhn_config(), &p0_wins);
+        // 4 decisions, 5 terminals
+        assert_eq!(tree.len(), 9);
+        assert_eq!(tree.leaves(root).len(), 5);
+        assert_eq!(actions(&tree, root), vec!["Check", "Bet 0.5"]);
+        assert_eq!(tree.data(root).player(), Some(0));
+
+        let check = tree.get_node(root).children[0];
+        assert_eq!(tree.data(check).player(), Some(1));
+        assert_eq!(actions(&tree, check), vec!["Check", "Bet 0.5"]);
+        let check_check = tree.get_node(check).children[0];
+        assert_eq!(tree.data(check_check).payoff(), Some([1.0, -1.0]));
+        let check_bet = tree.get_node(check).children[1];
+        assert_eq!(tree.data(check_bet).player(), Some(0));
+        assert_eq!(actions(&tree, check_bet), vec!["Call", "Fold"]);
+
+        let bet = tree.get_node(root).children[1];
+        let call = tree.get_node(bet).children[0];
+        assert_eq!(tree.data(call).payoff(), Some([2.0, -2.0]));
+        // player 1 folds and loses the ante
+        let fold = tree.get_node(bet).children[1];
+        assert_eq!(tree.data(fold).payoff(), Some([1.0, -1.0]));
+        // player 0 checks, then folds to the bet and loses the ante
+        let fold = tree.get_node(check_bet).children[1];
+        assert_eq!(tree.data(fold).payoff(), Some([-1.0, 1.0]));
+
+        let mut infosets: Vec<usize> = tree
+            .iter_dfs(root)
+            .filter_map(|node| node.infoset())
+            .collect();
+        infosets.sort();
+        assert_eq!(infosets, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_build_subtree() {
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, GameNode::chance(vec![0.5, 0.5]));
+        build_subtree(&mut tree, root, &kuhn_config(), &p0_wins);
+        let child = build_subtree(&mut tree, root, &kuhn_config(), &p0_wins);
+        assert_eq!(tree.len(), 19);
+        assert_eq!(tree.get_node(root).children[1], child);
+        assert_eq!(tree.parent(child), Some(root));
+        assert_eq!(tree.data(child).infoset(), Some(0));
+        assert_eq!(actions(&tree, child), vec!["Check", "Bet 0.5"]);
+    }
+
+    #[test]
+    fn test_build_bucketed_tree() {
+        let size = hand_indexer::HandIndexer::init(2, vec![2, 3]).size(1);
+        // a single bucket, all hands share their strategy
+        let bucket_map = BucketMap::new(BettingRound::Flop, vec![0; size as usize]);
+        let deals = vec![
+            // aces against kings, then kings against aces
+            Deal {
+                hands: [[51, 50], [47, 46]],
+                board: vec![0, 5, 10],
+            },
+            Deal {
+                hands: [[47, 46], [51, 50]],
+                board: vec![0, 5, 10],
+            },
+        ];
+        let (tree, root) = build_bucketed_tree(&kuhn_config(), &deals, &bucket_map);
+        assert_eq!(tree.len(), 19);
+        let children = &tree.get_node(root).children;
+        assert_eq!(tree.data(root).payoff(), None);
+        for deal in children {
+            assert_eq!(tree.data(*deal).infoset(), Some(0));
+        }
+        let call = tree
+            .get_node(tree.get_node(children[0]).children[1])
+            .children[0];
+        assert_eq!(tree.data(call).payoff(), Some([2.0, -2.0]));
+        let call = tree
+            .get_node(tree.get_node(children[1]).children[1])
+            .children[0];
+        assert_eq!(tree.data(call).payoff(), Some([-2.0, 2.0]));
+
+        // a bucket per player 0 hand, 4 decisions in the betting tree
+        let mut buckets = vec![0; size as usize];
+        buckets[bucket_map.hand_index(&deals[1].cards(0))] = 1;
+        let bucket_map = BucketMap::new(BettingRound::Flop, buckets);
+        let (tree, root) = build_bucketed_tree(&kuhn_config(), &deals, &bucket_map);
+        let children = &tree.get_node(root).children;
+        assert_eq!(tree.data(children[0]).infoset(), Some(0));
+        assert_eq!(tree.data(children[1]).infoset(), Some(4));
+        let check = tree.get_node(children[1]).children[0];
+        assert_eq!(tree.data(check).infoset(), Some(1));
+    }
+
+    #[test]
+    fn test_ehs_payoff() {
+        let config = kuhn_config();
+        let payoff = EhsPayoff::new(&config, vec![0.2, 0.8, 0.5]);
+        let p = payoff.win_probability(1, 0);
+        assert!((p - 0.64 / 0.68).abs() < 1e-6);
+        assert!((p + payoff.win_probability(0, 1) - 1.0).abs() < 1e-6);
+        assert_eq!(payoff.win_probability(2, 2), 0.5);
+
+        let bet = Action::Bet(0.5);
+        assert_eq!(history_wagers(&config, &[bet, Action::Call]), [2.0, 2.0]);
+        assert_eq!(payoff.payoff(&[Action::Check, Action::Check], 2, 2), 0.0);
+        assert!((payoff.payoff(&[bet, Action::Call], 1, 0) - (4.0 * p - 2.0)).abs() < 1e-6);
+        // folds don't depend on the buckets
+        assert_eq!(payoff.payoff(&[bet, Action::Fold], 0, 1), 1.0);
+        assert_eq!(
+            payoff.payoff(&[Action::Check, bet, Action::Fold], 1, 0),
+            -1.0
+        );
+    }
+
+    #[test]
+    fn test_ehs_payoff_from_table() {
+        let size = hand_indexer::HandIndexer::init(2, vec![2, 3]).size(1) as usize;
+        let bucket_map = BucketMap::new(
+            BettingRound::Flop,
+            (0..size).map(|i| (i % 2) as u32).collect(),
+        );
+        let ehs: Vec<f64> = (0..size)
+            .map(|i| if i % 2 == 0 { 0.25 } else { 0.75 })
+            .collect();
+        let payoff = EhsPayoff::from_table(&kuhn_config(), &bucket_map, &ehs);
+        assert_eq!(payoff.bucket_ehs, vec![0.25, 0.75]);
+    }
+
+    /// hero wins the difference of the buckets at every terminal node
+    struct BucketDifference;
+
+    impl PayoffFn for BucketDifference {
+        fn payoff(&self, _: &[Action], hero_bucket: usize, villain_bucket: usize) -> f32 {
+            return hero_bucket as f32 - villain_bucket as f32;
+        }
+    }
+
+    #[test]
+    fn test_build_payoff_tree() {
+        let size = hand_indexer::HandIndexer::init(2, vec![2, 3]).size(1);
+        let deals = vec![
+            Deal {
+                hands: [[51, 50], [47, 46]],
+                board: vec![0, 5, 10],
+            },
+            Deal {
+                hands: [[47, 46], [51, 50]],
+                board: vec![0, 5, 10],
+            },
+        ];
+        // aces in bucket 2, everything else in bucket 0
+        let mut buckets = vec![0; size as usize];
+        let bucket_map = BucketMap::new(BettingRound::Flop, buckets.clone());
+        buckets[bucket_map.hand_index(&deals[0].cards(0))] = 2;
+        let bucket_map = BucketMap::new(BettingRound::Flop, buckets);
+        let (tree, root) =
+            build_payoff_tree(&kuhn_config(), &deals, &bucket_map, &BucketDifference);
+        // same shape and infosets as the tree with showdowns by hand rank
+        let (ranked, ranked_root) = build_bucketed_tree(&kuhn_config(), &deals, &bucket_map);
+        assert_eq!(tree.len(), ranked.len());
+        let infosets = |tree: &Tree<GameNode>, root| -> Vec<Option<usize>> {
+            return tree.iter_dfs(root).map(|n| n.infoset()).collect();
+        };
+        assert_eq!(infosets(&tree, root), infosets(&ranked, ranked_root));
+
+        let children = &tree.get_node(root).children;
+        for (deal, payoff) in children.iter().zip([2.0, -2.0].iter()) {
+            for leaf in tree.leaves(*deal) {
+                assert_eq!(tree.data(leaf).payoff(), Some([*payoff, -*payoff]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_raises() {
+        let config = TreeConfig {
+            stack: 10.0,
+            pot: 2.0,
+            bet_sizes: vec![0.5, 1.0, 10.0],
+            allow_raise: true,
+            max_raises: 1,
+        };
+        let (tree, root) = build_tree(&config, &p0_wins);
+        // the pot sized bet is not all in, the third size is capped at the stack
+        assert_eq!(
+            actions(&tree, root),
+            vec!["Check", "Bet 0.5", "Bet 1", "Bet 10"]
+        );
+        let bet = tree.get_node(root).children[1];
+        assert_eq!(
+            actions(&tree, bet),
+            vec!["Call", "Fold", "Raise 0.5", "Raise 1", "Raise 10"]
+        );
+        // raise 0.5: call 1, then half of the pot of 4, player 0 folds their 2 chips
+        let raise = tree.get_node(bet).children[2];
+        // max_raises is reached, no reraise
+        assert_eq!(actions(&tree, raise), vec!["Call", "Fold"]);
+        let fold = tree.get_node(raise).children[1];
+        assert_eq!(tree.data(fold).payoff(), Some([-2.0, 2.0]));
+        // nothing is left to bet after calling an all in
+        let all_in = tree.get_node(root).children[3];
+        assert_eq!(actions(&tree, all_in), vec!["Call", "Fold"]);
+        let call = tree.get_node(all_in).children[0];
+        assert_eq!(tree.data(call).payoff(), Some([11.0, -11.0]));
+        for leaf in tree.leaves(root) {
+            assert!(tree.data(leaf).is_terminal());
+        }
+    }
+}
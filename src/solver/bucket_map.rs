@@ -0,0 +1,228 @@
+use bytepack::LEUnpacker;
+use hand_indexer::HandIndexer;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result};
+
+use crate::state::BettingRound;
+use rust_solver::bucket_format::{self, BucketTable};
+
+/// one u32 bucket per hand index, read into memory or mapped
+#[derive(Debug)]
+enum Buckets {
+    Owned(Vec<u32>),
+    Mapped(BucketTable),
+}
+
+impl Buckets {
+    fn as_slice(&self) -> &[u32] {
+        match self {
+            Buckets::Owned(buckets) => return buckets,
+            Buckets::Mapped(table) => return table.buckets(),
+        }
+    }
+}
+
+/**
+ * Maps the hands of a round to the buckets of a card abstraction
+ * the table holds one u32 bucket per hand index like the
+ * round_{}_emd.dat files written by gen_abstraction
+ * or the bucket tables written by gen_abstraction buckets
+ */
+#[derive(Debug)]
+pub struct BucketMap {
+    hand_indexer: HandIndexer,
+    /// number of hole and board cards of the round
+    n_cards: usize,
+    buckets: Buckets,
+    n_buckets: usize,
+}
+
+impl BucketMap {
+    /// panics if buckets doesn't have one bucket per hand index of round
+    pub fn new(round: BettingRound, buckets: Vec<u32>) -> BucketMap {
+        let (n_cards, hand_indexer) = round_indexer(round);
+        let size = hand_indexer.size(1);
+        if buckets.len() as u64 != size {
+            panic!(
+                "bucket table has {} hands, expected {} for the {:?}",
+                buckets.len(),
+                size,
+                round
+            );
+        }
+        let n_buckets = buckets.iter().max().map_or(0, |b| *b as usize + 1);
+        return BucketMap {
+            hand_indexer,
+            n_cards,
+            buckets: Buckets::Owned(buckets),
+            n_buckets,
+        };
+    }
+
+    /// maps a bucket table written by gen_abstraction buckets
+    /// the buckets are read from the page cache on lookup instead of loaded
+    /// fails if a bucket isn't below the n_buckets of the header,
+    /// which reads the table once
+    pub fn map(round: BettingRound, path: &str) -> Result<BucketMap> {
+        let table = bucket_format::map_buckets(path)?;
+        let (n_cards, hand_indexer) = round_indexer(round);
+        let header = *table.header();
+        let size = hand_indexer.size(1);
+        // gen_abstraction counts the preflop as round 0
+        if header.round as usize != round.to_usize() + 1 || header.n_hands != size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has {} hands of round {}, expected {} for the {:?}",
+                    path, header.n_hands, header.round, size, round
+                ),
+            ));
+        }
+        let buckets = table.buckets();
+        if let Some(index) = buckets.iter().position(|b| *b >= header.n_buckets) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has bucket {} for hand {}, expected below {}",
+                    path, buckets[index], index, header.n_buckets
+                ),
+            ));
+        }
+        return Ok(BucketMap {
+            hand_indexer,
+            n_cards,
+            buckets: Buckets::Mapped(table),
+            n_buckets: header.n_buckets as usize,
+        });
+    }
+
+    /// reads a bucket table of round from path
+    pub fn load(round: BettingRound, path: &str) -> Result<BucketMap> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut buckets: Vec<u32> = Vec::new();
+        reader.unpack_to_end(&mut buckets)?;
+        let size = round_indexer(round).1.size(1);
+        if buckets.len() as u64 != size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has {} hands, expected {} for the {:?}",
+                    path,
+                    buckets.len(),
+                    size,
+                    round
+                ),
+            ));
+        }
+        return Ok(BucketMap::new(round, buckets));
+    }
+
+    /// bucket of the hole cards followed by the board, extra board cards are ignored
+    pub fn bucket(&self, cards: &[u8]) -> usize {
+        return self.buckets.as_slice()[self.hand_index(cards)] as usize;
+    }
+
+    pub fn hand_index(&self, cards: &[u8]) -> usize {
+        return self.hand_indexer.get_index(&cards[..self.n_cards]) as usize;
+    }
+
+    /// bucket of a hand index of the round
+    pub fn index_bucket(&self, index: usize) -> usize {
+        return self.buckets.as_slice()[index] as usize;
+    }
+
+    /// number of hand indices of the round
+    pub fn n_hands(&self) -> usize {
+        return self.buckets.as_slice().len();
+    }
+
+    pub fn n_buckets(&self) -> usize {
+        return self.n_buckets;
+    }
+}
+
+fn round_indexer(round: BettingRound) -> (usize, HandIndexer) {
+    match round {
+        BettingRound::Flop => return (5, HandIndexer::init(2, vec![2, 3])),
+        BettingRound::Turn => return (6, HandIndexer::init(2, vec![2, 4])),
+        BettingRound::River => return (7, HandIndexer::init(2, vec![2, 5])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytepack::LEPacker;
+
+    /// flop buckets of hand index % 3
+    fn flop_buckets() -> Vec<u32> {
+        let size = HandIndexer::init(2, vec![2, 3]).size(1);
+        return (0..size).map(|i| (i % 3) as u32).collect();
+    }
+
+    #[test]
+    fn test_bucket() {
+        let map = BucketMap::new(BettingRound::Flop, flop_buckets());
+        assert_eq!(map.n_buckets(), 3);
+        assert_eq!(map.n_hands(), flop_buckets().len());
+        assert_eq!(map.index_bucket(4), 1);
+        let cards = [51u8, 47, 0, 1, 2];
+        let indexer = HandIndexer::init(2, vec![2, 3]);
+        let expected = (indexer.get_index(&cards) % 3) as usize;
+        assert_eq!(map.bucket(&cards), expected);
+        // suit isomorphic hands share a bucket, the turn card is ignored
+        assert_eq!(
+            map.bucket(&[50u8, 46, 3, 0, 1, 20]),
+            map.bucket(&[51, 47, 0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("test_load_buckets.dat");
+        let path = path.to_str().unwrap();
+        let buckets = flop_buckets();
+        let mut file = File::create(path).unwrap();
+        file.pack_all(&buckets[..]).unwrap();
+        drop(file);
+        let map = BucketMap::load(BettingRound::Flop, path).unwrap();
+        assert_eq!(map.n_buckets(), 3);
+
+        let err = BucketMap::load(BettingRound::Turn, path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_map() {
+        let path = std::env::temp_dir().join("test_map_buckets_flop.dat");
+        let path = path.to_str().unwrap();
+        let buckets = flop_buckets();
+        bucket_format::write_buckets(path, 1, 3, &buckets).unwrap();
+        let map = BucketMap::map(BettingRound::Flop, path).unwrap();
+        let owned = BucketMap::new(BettingRound::Flop, buckets);
+        assert_eq!(map.n_buckets(), 3);
+        let cards = [51u8, 47, 0, 1, 2];
+        assert_eq!(map.bucket(&cards), owned.bucket(&cards));
+
+        let err = BucketMap::map(BettingRound::Turn, path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // a bucket past the n_buckets of the header
+        drop(map);
+        let mut bytes = std::fs::read(path).unwrap();
+        let start = bucket_format::HEADER_SIZE as usize + 5 * 4;
+        bytes[start..start + 4].copy_from_slice(&3u32.to_le_bytes());
+        std::fs::write(path, &bytes).unwrap();
+        let err = BucketMap::map(BettingRound::Flop, path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("bucket 3 for hand 5"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket table has 3 hands")]
+    fn test_new_size() {
+        BucketMap::new(BettingRound::Flop, vec![0, 1, 2]);
+    }
+}
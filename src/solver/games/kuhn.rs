@@ -0,0 +1,218 @@
+use crate::betting_tree::{build_subtree, offset_infosets, GameNode, TreeConfig};
+use crate::tree::{NodeId, Tree};
+
+/// number of cards in the deck, 0 -> jack, 1 -> queen, 2 -> king
+pub const N_CARDS: usize = 3;
+/// number of decision nodes in the betting tree of a deal
+pub const N_BETTING_INFOSETS: usize = 4;
+/// value of the game for player 0 at a nash equilibrium
+pub const GAME_VALUE: f32 = -1.0 / 18.0;
+
+/// ante of 1 each and a single bet of 1
+fn betting_config() -> TreeConfig {
+    return TreeConfig {
+        stack: 1.0,
+        pot: 2.0,
+        bet_sizes: vec![0.5],
+        allow_raise: false,
+        max_raises: 0,
+    };
+}
+
+/// cards of each player for each child of the root
+pub fn deals() -> Vec<[usize; 2]> {
+    let mut deals = Vec::new();
+    for c0 in 0..N_CARDS {
+        for c1 in 0..N_CARDS {
+            if c0 != c1 {
+                deals.push([c0, c1]);
+            }
+        }
+    }
+    return deals;
+}
+
+/**
+ * Builds the Kuhn poker tree
+ * each player antes 1 and is dealt one card of a 3 card deck,
+ * there is a single bet of 1 and no raises
+ * the root is a chance node dealing the cards, followed by a betting tree per deal
+ * the infoset of a decision node is card * N_BETTING_INFOSETS + the index of
+ * the node in the betting tree, so the player only knows their own card
+ */
+pub fn build_kuhn() -> (Tree<GameNode>, NodeId) {
+    let deals = deals();
+    let mut tree = Tree::new();
+    let probability = 1.0 / deals.len() as f32;
+    let root = tree.create_node(None, GameNode::chance(vec![probability; deals.len()]));
+    for cards in deals {
+        let showdown = |wagers: &[f32; 2]| {
+            if cards[0] > cards[1] {
+                return [wagers[1], -wagers[1]];
+            }
+            return [-wagers[0], wagers[0]];
+        };
+        let deal = build_subtree(&mut tree, root, &betting_config(), &showdown);
+        offset_infosets(
+            &mut tree,
+            deal,
+            [cards[0] * N_BETTING_INFOSETS, cards[1] * N_BETTING_INFOSETS],
+        );
+    }
+    return (tree, root);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infoset_key::InfoSetKey;
+    use crate::tree_cfr::Cfr;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_build_kuhn() {
+        let (tree, root) = build_kuhn();
+        // 6 deals with 9 nodes each
+        assert_eq!(tree.len(), 55);
+        assert_eq!(tree.get_node(root).children.len(), 6);
+        let mut infosets: Vec<usize> = tree.iter_dfs(root).filter_map(|n| n.infoset()).collect();
+        infosets.sort();
+        infosets.dedup();
+        assert_eq!(infosets.len(), N_CARDS * N_BETTING_INFOSETS);
+        // queen against king, bet and call
+        let deal = tree.get_node(root).children[3];
+        let bet = tree.get_node(deal).children[1];
+        assert_eq!(tree.data(bet).infoset(), Some(2 * N_BETTING_INFOSETS + 3));
+        let call = tree.get_node(bet).children[0];
+        assert_eq!(tree.data(call).payoff(), Some([-2.0, 2.0]));
+    }
+
+    #[test]
+    fn test_kuhn_uniform() {
+        let (tree, root) = build_kuhn();
+        let cfr = Cfr::new(&tree, root);
+        assert!(cfr.exploitability(&tree, root) > 0.5);
+    }
+
+    #[test]
+    fn test_kuhn_cfr() {
+        let (tree, root) = build_kuhn();
+        let mut cfr = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 2000);
+        assert!(cfr.exploitability(&tree, root) < 0.01);
+        let value = cfr.best_response_value(&tree, root, 0);
+        assert!((value - GAME_VALUE).abs() < 0.01, "{}", value);
+        // player 1 calls a bet with the king and folds the jack
+        let king = tree.get_node(root).children[1];
+        let bet = tree.get_node(king).children[1];
+        assert!(cfr.average_strategy(&tree, bet)[0] > 0.99);
+        let jack = tree.get_node(root).children[2];
+        let bet = tree.get_node(jack).children[1];
+        assert!(cfr.average_strategy(&tree, bet)[1] > 0.99);
+    }
+
+    #[test]
+    fn test_kuhn_export_strategy() {
+        let (tree, root) = build_kuhn();
+        let mut cfr = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 2000);
+        let strategy = cfr.export_strategy(&tree, root, N_BETTING_INFOSETS);
+        assert_eq!(strategy.len(), N_CARDS * N_BETTING_INFOSETS);
+        for probabilities in strategy.values() {
+            assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+        let (jack, queen, king) = (0, 1, 2);
+        let p = |history: &[u8], card: usize, action: usize| {
+            return strategy[&InfoSetKey::new(history, card)][action];
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 0.05;
+        // player 1 has a unique equilibrium
+        // bluffs a third of the jacks and folds them to a bet
+        assert!(close(p(&[0], jack, 1), 1.0 / 3.0));
+        assert!(close(p(&[1], jack, 1), 1.0));
+        // checks back the queen and calls a third of the bets
+        assert!(close(p(&[0], queen, 0), 1.0));
+        assert!(close(p(&[1], queen, 0), 1.0 / 3.0));
+        // bets and calls the king
+        assert!(close(p(&[0], king, 1), 1.0));
+        assert!(close(p(&[1], king, 0), 1.0));
+        // player 0 bets the jack with some alpha in [0, 1/3], the king 3 alpha
+        // checks the queen and calls a bet after checking it alpha + 1/3
+        let alpha = p(&[], jack, 1);
+        assert!(alpha < 1.0 / 3.0 + 0.05);
+        assert!(close(p(&[], king, 1), 3.0 * alpha));
+        assert!(close(p(&[], queen, 0), 1.0));
+        assert!(close(p(&[0, 1], queen, 0), alpha + 1.0 / 3.0));
+        assert!(close(p(&[0, 1], jack, 1), 1.0));
+        assert!(close(p(&[0, 1], king, 0), 1.0));
+    }
+
+    #[test]
+    fn test_kuhn_cfr_plus() {
+        let (tree, root) = build_kuhn();
+        let mut cfr = Cfr::new(&tree, root);
+        let mut cfr_plus = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 200);
+        cfr_plus.train_cfr_plus(&tree, root, 200);
+        let exploitability = cfr_plus.exploitability(&tree, root);
+        assert!(exploitability < 0.005, "{}", exploitability);
+        assert!(exploitability < cfr.exploitability(&tree, root));
+    }
+
+    #[test]
+    fn test_kuhn_mccfr() {
+        let (tree, root) = build_kuhn();
+        let mut cfr = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 2000);
+        let mut mccfr = Cfr::new(&tree, root);
+        let mut rng = StdRng::seed_from_u64(42);
+        mccfr.train_mccfr(&tree, root, 20_000, &mut rng);
+        let exploitability = mccfr.exploitability(&tree, root);
+        assert!(exploitability < 0.02, "{}", exploitability);
+        // the equilibrium strategy of player 1 is unique
+        for deal in &tree.get_node(root).children {
+            for node in &tree.get_node(*deal).children {
+                let expected = cfr.average_strategy(&tree, *node);
+                let strategy = mccfr.average_strategy(&tree, *node);
+                for (a, b) in expected.iter().zip(&strategy) {
+                    assert!((a - b).abs() < 0.05, "{:?} {:?}", expected, strategy);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_kuhn_mccfr_seed() {
+        let (tree, root) = build_kuhn();
+        let mut first = Cfr::new(&tree, root);
+        let mut second = Cfr::new(&tree, root);
+        first.train_mccfr(&tree, root, 100, &mut StdRng::seed_from_u64(1));
+        second.train_mccfr(&tree, root, 100, &mut StdRng::seed_from_u64(1));
+        for (a, b) in first.infosets().iter().zip(second.infosets()) {
+            assert_eq!(a.strategy_sum, b.strategy_sum);
+        }
+    }
+
+    #[test]
+    fn test_kuhn_cfr_parallel() {
+        let (tree, root) = build_kuhn();
+        let mut cfr = Cfr::new(&tree, root);
+        let mut parallel = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 500);
+        parallel.train_parallel(&tree, root, 500);
+        assert_eq!(parallel.iteration(), 500);
+        for node in tree.iter_dfs(root) {
+            if let Some(infoset) = node.infoset() {
+                let expected = &cfr.infosets()[infoset].strategy_sum;
+                let strategy_sum = &parallel.infosets()[infoset].strategy_sum;
+                for (a, b) in expected.iter().zip(strategy_sum) {
+                    assert!((a - b).abs() <= 1e-4 * a.abs().max(1.0), "{} {}", a, b);
+                }
+            }
+        }
+        assert!(
+            (cfr.exploitability(&tree, root) - parallel.exploitability(&tree, root)).abs() < 1e-4
+        );
+    }
+}
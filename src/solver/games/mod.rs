@@ -0,0 +1,2 @@
+/// small games with known solutions to validate the solver
+pub mod kuhn;
@@ -0,0 +1,189 @@
+use std::fmt;
+
+/// bits per action of a packed history
+const ACTION_BITS: u32 = 4;
+/// max number of actions at a decision node
+pub const MAX_ACTIONS: usize = 1 << ACTION_BITS;
+/// max number of actions in a packed history, one bit is kept for the length marker
+pub const MAX_HISTORY: usize = ((64 - 1) / ACTION_BITS) as usize;
+
+/**
+ * Compact key of an information set to store regrets by instead of a Vec<Action>
+ * the history is the index of each action taken among the actions of its
+ * decision node, packed 4 bits per action below a marker bit so histories of
+ * different lengths differ, e.g. [1, 0] -> 0b1_0001_0000
+ * the bucket is the card abstraction bucket of the acting player
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoSetKey {
+    history: u64,
+    bucket: u32,
+}
+
+impl InfoSetKey {
+    /// key of the empty history in bucket
+    pub fn root(bucket: usize) -> InfoSetKey {
+        if bucket > u32::MAX as usize {
+            panic!("bucket {} doesn't fit in an InfoSetKey", bucket);
+        }
+        return InfoSetKey {
+            history: 1,
+            bucket: bucket as u32,
+        };
+    }
+
+    /// panics if history has more than MAX_HISTORY actions
+    /// or an action index of at least MAX_ACTIONS
+    pub fn new(history: &[u8], bucket: usize) -> InfoSetKey {
+        let mut key = InfoSetKey::root(bucket);
+        for action in history {
+            key = key.push(*action);
+        }
+        return key;
+    }
+
+    /// key of the same bucket after action is taken
+    pub fn push(self, action: u8) -> InfoSetKey {
+        if self.len() == MAX_HISTORY {
+            panic!("history is longer than {} actions", MAX_HISTORY);
+        }
+        if usize::from(action) >= MAX_ACTIONS {
+            panic!("action index {} is not below {}", action, MAX_ACTIONS);
+        }
+        return InfoSetKey {
+            history: (self.history << ACTION_BITS) | u64::from(action),
+            bucket: self.bucket,
+        };
+    }
+
+    /// key of the same history in another bucket
+    pub fn with_bucket(self, bucket: usize) -> InfoSetKey {
+        return InfoSetKey {
+            bucket: InfoSetKey::root(bucket).bucket,
+            ..self
+        };
+    }
+
+    /// number of actions in the history
+    pub fn len(&self) -> usize {
+        return ((64 - 1 - self.history.leading_zeros()) / ACTION_BITS) as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.history == 1;
+    }
+
+    /// action indices of the history from the first action
+    pub fn history(&self) -> Vec<u8> {
+        let mask = (1u64 << ACTION_BITS) - 1;
+        return (0..self.len())
+            .rev()
+            .map(|i| ((self.history >> (i as u32 * ACTION_BITS)) & mask) as u8)
+            .collect();
+    }
+
+    pub fn bucket(&self) -> usize {
+        return self.bucket as usize;
+    }
+}
+
+impl From<(&[u8], usize)> for InfoSetKey {
+    fn from((history, bucket): (&[u8], usize)) -> InfoSetKey {
+        return InfoSetKey::new(history, bucket);
+    }
+}
+
+impl From<InfoSetKey> for (Vec<u8>, usize) {
+    fn from(key: InfoSetKey) -> (Vec<u8>, usize) {
+        return (key.history(), key.bucket());
+    }
+}
+
+/// the bucket in the high 64 bits and the packed history in the low 64 bits
+impl From<InfoSetKey> for u128 {
+    fn from(key: InfoSetKey) -> u128 {
+        return (u128::from(key.bucket) << 64) | u128::from(key.history);
+    }
+}
+
+/// e.g. "B3 [1, 0]"
+impl fmt::Display for InfoSetKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "B{} {:?}", self.bucket, self.history());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_round_trip() {
+        let history: &[u8] = &[1, 0, 15, 3];
+        let key = InfoSetKey::from((history, 7));
+        assert_eq!(key.len(), 4);
+        assert_eq!(key.bucket(), 7);
+        let (decoded, bucket): (Vec<u8>, usize) = key.into();
+        assert_eq!(decoded, history);
+        assert_eq!(bucket, 7);
+
+        let root = InfoSetKey::root(0);
+        assert!(root.is_empty());
+        assert!(root.history().is_empty());
+        let long = vec![15u8; MAX_HISTORY];
+        assert_eq!(InfoSetKey::new(&long, 0).history(), long);
+        assert_eq!(
+            InfoSetKey::new(&[1, 0], 0).push(2),
+            InfoSetKey::new(&[1, 0, 2], 0)
+        );
+        assert_eq!(
+            InfoSetKey::new(&[1, 0], 2).with_bucket(5),
+            InfoSetKey::new(&[1, 0], 5)
+        );
+        assert_eq!(
+            u128::from(InfoSetKey::new(&[1, 0], 2)),
+            (2 << 64) | 0b1_0001_0000
+        );
+        assert_eq!(InfoSetKey::new(&[1, 0], 2).to_string(), "B2 [1, 0]");
+    }
+
+    #[test]
+    fn test_distinct() {
+        // every history of up to 3 actions among 3 in 2 buckets
+        let mut histories: Vec<Vec<u8>> = vec![vec![]];
+        for len in 0..3 {
+            for i in 0..histories.len() {
+                if histories[i].len() == len {
+                    for action in 0..3 {
+                        let mut history = histories[i].clone();
+                        history.push(action);
+                        histories.push(history);
+                    }
+                }
+            }
+        }
+        let mut keys = HashSet::new();
+        for bucket in 0..2 {
+            for history in &histories {
+                assert!(keys.insert(InfoSetKey::new(history, bucket)));
+            }
+        }
+        assert_eq!(keys.len(), 2 * (1 + 3 + 9 + 27));
+        // leading zeros are kept
+        assert_ne!(InfoSetKey::new(&[0], 0), InfoSetKey::root(0));
+        assert_ne!(InfoSetKey::new(&[0, 1], 0), InfoSetKey::new(&[1], 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not below 16")]
+    fn test_action_too_large() {
+        InfoSetKey::root(0).push(16);
+    }
+
+    #[test]
+    #[should_panic(expected = "history is longer than 15 actions")]
+    fn test_history_too_long() {
+        InfoSetKey::new(&[0; MAX_HISTORY + 1], 0);
+    }
+}
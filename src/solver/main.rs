@@ -21,6 +21,11 @@ mod tree_builder;
 mod card_abstraction;
 mod infoset;
 mod cfr;
+mod betting_tree;
+mod tree_cfr;
+mod infoset_key;
+mod games;
+mod bucket_map;
 
 use cfr::MCCFRTrainer;
 use std::time::Instant;
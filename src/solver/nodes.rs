@@ -2,6 +2,7 @@ use crate::action_abstraction::Action;
 use crate::state::BettingRound;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct ActionNode {
     pub actions: Vec<Action>,
     pub index: usize,
@@ -14,6 +15,7 @@ pub struct ActionNode {
  * type: ALLIN, UNCONTESTED, SHOWDOWN
  */
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum TerminalType {
     ALLIN,
     SHOWDOWN,
@@ -31,6 +33,7 @@ impl TerminalType {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct TerminalNode {
     pub value: u32, // size of pot
     pub ttype: TerminalType,
@@ -39,11 +42,13 @@ pub struct TerminalNode {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublicChanceNode {
     pub round: BettingRound
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum GameTreeNode {
     Action(ActionNode),
     Terminal(TerminalNode),
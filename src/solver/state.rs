@@ -4,6 +4,7 @@ use crate::options::Options;
 
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum BettingRound {
     // Preflop,
     Flop,
@@ -1,3 +1,8 @@
+use std::collections::VecDeque;
+#[cfg(feature = "serialize")]
+use std::fs::File;
+#[cfg(feature = "serialize")]
+use std::io::{self, BufReader, BufWriter};
 use std::ops::{Generator, GeneratorState};
 use std::pin::Pin;
 
@@ -8,19 +13,92 @@ use std::pin::Pin;
  * data is contained in a single arena or vector
  * this way there is a single reference
  * nodes instead contain the index of their parent and children in the arena
+ *
+ * removing a subtree frees its slots for reuse by create_node,
+ * the NodeIds of all remaining nodes stay the same
  */
 pub type NodeId = usize;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tree<T> {
-    nodes: Vec<Node<T>>
+    nodes: Vec<Node<T>>,
+    /// slots of removed nodes that can be reused
+    free: Vec<NodeId>,
+    /// the first node created unless set with set_root
+    root: Option<NodeId>
+}
+
+/// iterates over the data of a subtree in depth first pre-order
+/// uses an explicit stack so it works on stable rust
+pub struct DfsIter<'a, T> {
+    tree: &'a Tree<T>,
+    stack: Vec<NodeId>
+}
+
+impl<'a, T> Iterator for DfsIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.tree.get_node(self.stack.pop()?);
+        // push children reversed so the leftmost is visited first
+        self.stack.extend(node.children.iter().rev());
+        return Some(&node.data);
+    }
+}
+
+/// iterates over a subtree in depth first pre-order down to max_depth
+/// yields the NodeId and depth below the start node with the data
+/// children of nodes at max_depth are never pushed
+pub struct DfsDepthIter<'a, T> {
+    tree: &'a Tree<T>,
+    stack: Vec<(NodeId, usize)>,
+    max_depth: usize
+}
+
+impl<'a, T> Iterator for DfsDepthIter<'a, T> {
+    type Item = (NodeId, usize, &'a T);
+    fn next(&mut self) -> Option<(NodeId, usize, &'a T)> {
+        let (id, depth) = self.stack.pop()?;
+        let node = self.tree.get_node(id);
+        if depth < self.max_depth {
+            self.stack.extend(node.children.iter().rev().map(|c| (*c, depth + 1)));
+        }
+        return Some((id, depth, &node.data));
+    }
+}
+
+/// iterates over the data of a subtree in breadth first (level) order
+/// children are visited in insertion order
+pub struct BfsIter<'a, T> {
+    tree: &'a Tree<T>,
+    queue: VecDeque<NodeId>
+}
+
+impl<'a, T> Iterator for BfsIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.tree.get_node(self.queue.pop_front()?);
+        self.queue.extend(node.children.iter());
+        return Some(&node.data);
+    }
+}
+
+/// hooks for a depth first walk of a subtree, see Tree::accept
+/// enter is called on a node before its children and leave after them,
+/// so a pass can keep its own stack of state without walking the tree itself
+pub trait TreeVisitor<T> {
+    fn enter(&mut self, id: NodeId, node: &T);
+    fn leave(&mut self, id: NodeId, node: &T);
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node<T> {
     pub children: Vec<NodeId>,
     parent: Option<NodeId>,
-    pub data: T
+    pub data: T,
+    /// set by remove_subtree until create_node reuses the slot
+    removed: bool
 }
 
 impl<T> Node<T> {
@@ -28,7 +106,8 @@ impl<T> Node<T> {
         Node {
             data: data,
             parent: parent,
-            children: Vec::new()
+            children: Vec::new(),
+            removed: false
         }
     }
     pub fn set_parent(&mut self, parent: NodeId) {
@@ -42,21 +121,206 @@ impl<T> Node<T> {
 impl<T> Tree<T> {
     pub fn new() -> Tree<T> {
         Tree {
-            nodes: Vec::new()
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None
         }
     }
     pub fn create_node(&mut self, parent: Option<NodeId>, data: T) -> NodeId {
-        let index: NodeId = self.nodes.len();
         let node = Node::new(parent, data);
-        self.nodes.push(node);
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        if self.root.is_none() {
+            self.root = Some(index);
+        }
         return index;
     }
+    pub fn root(&self) -> Option<NodeId> {
+        return self.root;
+    }
+    pub fn set_root(&mut self, root: NodeId) {
+        self.root = Some(root);
+    }
+    // creates a node and links it to parent in both directions
+    pub fn create_child(&mut self, parent: NodeId, data: T) -> NodeId {
+        let id = self.create_node(Some(parent), data);
+        self.nodes[parent].add_child(id);
+        return id;
+    }
+    // detaches id from its parent and frees the slots of its subtree
+    // the removed NodeIds must not be used until create_node hands them out again
+    // does nothing if id was already removed, root is cleared if it was in the subtree
+    pub fn remove_subtree(&mut self, id: NodeId) {
+        if self.nodes[id].removed {
+            return;
+        }
+        if let Some(parent) = self.nodes[id].parent {
+            self.nodes[parent].children.retain(|c| *c != id);
+        }
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if self.root == Some(id) {
+                self.root = None;
+            }
+            let node = &mut self.nodes[id];
+            stack.append(&mut node.children);
+            node.parent = None;
+            node.removed = true;
+            self.free.push(id);
+        }
+    }
     pub fn get_node_mut(&mut self, idx: NodeId) -> &mut Node<T> {
         return &mut self.nodes[idx];
     }
     pub fn get_node(&self, idx: NodeId) -> &Node<T> {
         return &self.nodes[idx];
     }
+    pub fn data(&self, id: NodeId) -> &T {
+        return &self.nodes[id].data;
+    }
+    pub fn data_mut(&mut self, id: NodeId) -> &mut T {
+        return &mut self.nodes[id].data;
+    }
+    // returns the parent of a node, None for the root
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        return self.nodes[id].parent;
+    }
+    // returns the parents of a node up to and including the root
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        return std::iter::successors(self.parent(id), move |p| self.parent(*p));
+    }
+    // returns the number of nodes in the tree
+    pub fn len(&self) -> usize {
+        return self.nodes.len() - self.free.len();
+    }
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+    // returns the number of edges on the longest path from root to a leaf
+    pub fn depth(&self, root: NodeId) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            if depth > max_depth {
+                max_depth = depth;
+            }
+            for child in &self.nodes[id].children {
+                stack.push((*child, depth + 1));
+            }
+        }
+        return max_depth;
+    }
+    // returns the nodes without children in the subtree of root
+    // in depth first order
+    pub fn leaves(&self, root: NodeId) -> Vec<NodeId> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let children = &self.nodes[id].children;
+            if children.is_empty() {
+                leaves.push(id);
+            }
+            stack.extend(children.iter().rev());
+        }
+        return leaves;
+    }
+    // returns the nodes from the root down to id, root first and id last
+    // the root's path only contains the root
+    pub fn path_from_root(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path: Vec<NodeId> = self.ancestors(id).collect();
+        path.reverse();
+        path.push(id);
+        return path;
+    }
+    // returns an iterator over node and its subtree
+    // in the same order as generator (node, then children left to right)
+    // prefer this over generator, which needs nightly
+    pub fn iter_dfs(&self, node: NodeId) -> DfsIter<'_, T> {
+        DfsIter {
+            tree: self,
+            stack: vec![node]
+        }
+    }
+    // returns an iterator over node and the levels below it up to max_depth
+    // the start node has depth 0, e.g. max_depth 0 only yields node
+    // lets huge trees be inspected without enumerating every leaf
+    pub fn iter_dfs_depth(&self, node: NodeId, max_depth: usize) -> DfsDepthIter<'_, T> {
+        DfsDepthIter {
+            tree: self,
+            stack: vec![(node, 0)],
+            max_depth: max_depth
+        }
+    }
+    // returns an iterator over the whole tree starting at root
+    // empty if the tree has no root
+    pub fn iter(&self) -> DfsIter<'_, T> {
+        DfsIter {
+            tree: self,
+            stack: self.root.into_iter().collect()
+        }
+    }
+    // returns an iterator over root and its subtree one level at a time
+    pub fn iter_bfs(&self, root: NodeId) -> BfsIter<'_, T> {
+        let mut queue = VecDeque::new();
+        queue.push_back(root);
+        BfsIter {
+            tree: self,
+            queue: queue
+        }
+    }
+    // calls f on every node of the subtree of root in depth first pre-order
+    // can be used to update the data of each node in place
+    pub fn for_each_mut<F: FnMut(NodeId, &mut T)>(&mut self, root: NodeId, mut f: F) {
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let node = &mut self.nodes[id];
+            f(id, &mut node.data);
+            stack.extend(node.children.iter().rev());
+        }
+    }
+    // renders the subtree of root with one label per line
+    // children are indented by two spaces more than their parent
+    pub fn format_tree<F: Fn(&T) -> String>(&self, root: NodeId, f: F) -> String {
+        let mut out = String::new();
+        let mut stack = vec![(root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id];
+            out.push_str(&"  ".repeat(depth));
+            out.push_str(&f(&node.data));
+            out.push('\n');
+            stack.extend(node.children.iter().rev().map(|c| (*c, depth + 1)));
+        }
+        return out;
+    }
+    // prints the subtree of root like format_tree
+    pub fn print_tree<F: Fn(&T) -> String>(&self, root: NodeId, f: F) {
+        print!("{}", self.format_tree(root, f));
+    }
+    // walks the subtree of root depth first, children left to right,
+    // calling visitor.enter before and visitor.leave after the children of each node
+    // uses an explicit stack so deep trees don't overflow
+    pub fn accept<V: TreeVisitor<T>>(&self, root: NodeId, visitor: &mut V) {
+        // (node, whether its children were pushed)
+        let mut stack = vec![(root, false)];
+        while let Some((id, entered)) = stack.pop() {
+            let node = &self.nodes[id];
+            if entered {
+                visitor.leave(id, &node.data);
+                continue;
+            }
+            visitor.enter(id, &node.data);
+            stack.push((id, true));
+            stack.extend(node.children.iter().rev().map(|c| (*c, false)));
+        }
+    }
     // returns a recursive generator for node a specified node
     pub fn generator(&self, node: NodeId) -> Box<dyn Generator<Yield = &T, Return = ()> + '_> {
         Box::new(move || {
@@ -73,4 +337,362 @@ impl<T> Tree<T> {
             }
         })
     }
+    // like generator but yields the NodeId of each node with its data
+    // in the same pre-order, e.g. to collect the ids of terminal nodes
+    pub fn generator_with_id(&self, node: NodeId) -> Box<dyn Generator<Yield = (NodeId, &T), Return = ()> + '_> {
+        Box::new(move || {
+            let n = self.get_node(node);
+            yield (node, &n.data);
+            for i in &n.children {
+                let mut subgen = Box::into_pin(self.generator_with_id(*i));
+                loop {
+                    match subgen.as_mut().resume(()) {
+                        GeneratorState::Yielded(item) => { yield item; },
+                        GeneratorState::Complete(_) => { break; }
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl<T: Clone> Tree<T> {
+    // copies the subtree of root into a new tree, e.g. to ship it to another worker
+    // nodes are renumbered in depth first pre-order so root becomes node 0,
+    // children keep their order, returns the new tree and its root
+    pub fn extract_subtree(&self, root: NodeId) -> (Tree<T>, NodeId) {
+        let mut tree = Tree::new();
+        let new_root = tree.create_node(None, self.nodes[root].data.clone());
+        // (node in self, its parent in tree)
+        let mut stack: Vec<(NodeId, NodeId)> =
+            self.nodes[root].children.iter().rev().map(|c| (*c, new_root)).collect();
+        while let Some((id, parent)) = stack.pop() {
+            let node = &self.nodes[id];
+            let new_id = tree.create_child(parent, node.data.clone());
+            stack.extend(node.children.iter().rev().map(|c| (*c, new_id)));
+        }
+        return (tree, new_root);
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Tree<T> {
+    // writes the tree to a binary file
+    // NodeIds are indices into nodes so they are the same after loading
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        return bincode::serialize_into(file, self)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+    }
+    // reads a tree written by save
+    pub fn load(path: &str) -> io::Result<Tree<T>> {
+        let file = BufReader::new(File::open(path)?);
+        return bincode::deserialize_from(file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_tree() -> Tree<usize> {
+        //       0
+        //     / | \
+        //    1  4  5
+        //   / \    \
+        //  2   3    6
+        let mut tree = Tree::new();
+        let parents = [None, Some(0), Some(1), Some(1), Some(0), Some(0), Some(5)];
+        for (i, parent) in parents.iter().enumerate() {
+            let id = tree.create_node(*parent, i);
+            if let Some(p) = parent {
+                tree.get_node_mut(*p).add_child(id);
+            }
+        }
+        return tree;
+    }
+
+    #[test]
+    fn test_iter_dfs() {
+        let tree = build_tree();
+        let order: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(order, vec![0, 1, 2, 3, 4, 5, 6]);
+        let order: Vec<usize> = tree.iter_dfs(5).map(|d| *d).collect();
+        assert_eq!(order, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_iter_dfs_depth() {
+        let tree = build_tree();
+        let order: Vec<(NodeId, usize, usize)> =
+            tree.iter_dfs_depth(0, 1).map(|(id, depth, d)| (id, depth, *d)).collect();
+        assert_eq!(order, vec![(0, 0, 0), (1, 1, 1), (4, 1, 4), (5, 1, 5)]);
+        let ids: Vec<NodeId> = tree.iter_dfs_depth(0, 2).map(|(id, _, _)| id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5, 6]);
+        let ids: Vec<NodeId> = tree.iter_dfs_depth(5, 0).map(|(id, _, _)| id).collect();
+        assert_eq!(ids, vec![5]);
+    }
+
+    /// records the walk and the size of each subtree
+    struct Recorder {
+        events: Vec<(bool, NodeId)>,
+        /// subtree sizes of the nodes entered but not left
+        sizes: Vec<usize>,
+        subtree_sizes: Vec<(NodeId, usize)>
+    }
+
+    impl TreeVisitor<usize> for Recorder {
+        fn enter(&mut self, id: NodeId, _: &usize) {
+            self.events.push((true, id));
+            self.sizes.push(1);
+        }
+        fn leave(&mut self, id: NodeId, node: &usize) {
+            assert_eq!(id, *node);
+            self.events.push((false, id));
+            let size = self.sizes.pop().unwrap();
+            if let Some(parent) = self.sizes.last_mut() {
+                *parent += size;
+            }
+            self.subtree_sizes.push((id, size));
+        }
+    }
+
+    #[test]
+    fn test_accept() {
+        let tree = build_tree();
+        let mut recorder = Recorder {
+            events: Vec::new(),
+            sizes: Vec::new(),
+            subtree_sizes: Vec::new()
+        };
+        tree.accept(0, &mut recorder);
+        let entered: Vec<NodeId> = recorder.events.iter().filter(|e| e.0).map(|e| e.1).collect();
+        assert_eq!(entered, vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(&recorder.events[..4], &[(true, 0), (true, 1), (true, 2), (false, 2)]);
+        assert_eq!(recorder.events.last(), Some(&(false, 0)));
+        assert_eq!(
+            recorder.subtree_sizes,
+            vec![(2, 1), (3, 1), (1, 3), (4, 1), (6, 1), (5, 2), (0, 7)]
+        );
+    }
+
+    #[test]
+    fn test_extract_subtree() {
+        let mut tree = build_tree();
+        // reuse a freed slot so ids and dfs positions differ
+        tree.remove_subtree(2);
+        let id = tree.create_child(1, 7);
+        tree.create_child(id, 8);
+        let (subtree, root) = tree.extract_subtree(1);
+        assert_eq!(root, 0);
+        assert_eq!(subtree.root(), Some(0));
+        assert_eq!(subtree.len(), 4);
+        let expected: Vec<usize> = tree.iter_dfs(1).cloned().collect();
+        assert_eq!(subtree.iter_dfs(root).cloned().collect::<Vec<usize>>(), expected);
+        assert_eq!(expected, vec![1, 3, 7, 8]);
+        // parents and children link up in the new numbering
+        for (id, _, _) in subtree.iter_dfs_depth(root, usize::MAX) {
+            for child in &subtree.get_node(id).children {
+                assert_eq!(subtree.parent(*child), Some(id));
+            }
+        }
+        assert_eq!(subtree.parent(root), None);
+        assert_eq!(subtree.path_from_root(3), vec![0, 2, 3]);
+
+        let (leaf, root) = tree.extract_subtree(6);
+        assert_eq!(leaf.len(), 1);
+        assert_eq!(*leaf.data(root), 6);
+    }
+
+    #[test]
+    fn test_iter_bfs() {
+        let tree = build_tree();
+        let order: Vec<usize> = tree.iter_bfs(0).map(|d| *d).collect();
+        assert_eq!(order, vec![0, 1, 4, 5, 2, 3, 6]);
+        let order: Vec<usize> = tree.iter_bfs(1).map(|d| *d).collect();
+        assert_eq!(order, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let tree = build_tree();
+        assert_eq!(tree.parent(0), None);
+        assert_eq!(tree.parent(6), Some(5));
+        let chain: Vec<NodeId> = tree.ancestors(3).collect();
+        assert_eq!(chain, vec![1, 0]);
+        assert_eq!(tree.ancestors(0).count(), 0);
+    }
+
+    #[test]
+    fn test_path_from_root() {
+        let tree = build_tree();
+        assert_eq!(tree.path_from_root(6), vec![0, 5, 6]);
+        assert_eq!(tree.path_from_root(2), vec![0, 1, 2]);
+        assert_eq!(tree.path_from_root(0), vec![0]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let tree = build_tree();
+        assert_eq!(tree.len(), 7);
+        assert_eq!(tree.depth(0), 2);
+        assert_eq!(tree.depth(4), 0);
+        assert_eq!(tree.leaves(0), vec![2, 3, 4, 6]);
+        assert_eq!(tree.leaves(5), vec![6]);
+    }
+
+    #[test]
+    fn test_for_each_mut() {
+        let mut tree = build_tree();
+        let mut order = Vec::new();
+        tree.for_each_mut(1, |id, data| {
+            order.push(id);
+            *data += 10;
+        });
+        assert_eq!(order, vec![1, 2, 3]);
+        let data: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(data, vec![0, 11, 12, 13, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_format_tree() {
+        let tree = build_tree();
+        let expected = "node 0\n  node 1\n    node 2\n    node 3\n  node 4\n  node 5\n    node 6\n";
+        assert_eq!(tree.format_tree(0, |d| format!("node {}", d)), expected);
+        assert_eq!(tree.format_tree(5, |d| d.to_string()), "5\n  6\n");
+    }
+
+    #[test]
+    fn test_remove_subtree() {
+        let mut tree = build_tree();
+        tree.remove_subtree(1);
+        let order: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(order, vec![0, 4, 5, 6]);
+        assert_eq!(tree.len(), 4);
+
+        // freed slots are reused, other ids are unchanged
+        let id = tree.create_node(Some(4), 7);
+        tree.get_node_mut(4).add_child(id);
+        assert!(id == 1 || id == 2 || id == 3);
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.get_node(6).data, 6);
+        assert_eq!(tree.path_from_root(id), vec![0, 4, id]);
+        let order: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(order, vec![0, 4, 7, 5, 6]);
+    }
+
+    #[test]
+    fn test_remove_subtree_twice() {
+        let mut tree = build_tree();
+        tree.remove_subtree(1);
+        // again, and a node inside the removed subtree
+        tree.remove_subtree(1);
+        tree.remove_subtree(2);
+        assert_eq!(tree.len(), 4);
+        let mut ids: Vec<NodeId> = (0..3).map(|i| tree.create_node(None, 7 + i)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(tree.create_node(None, 10), 7);
+    }
+
+    #[test]
+    fn test_remove_subtree_root() {
+        // the root is inside the removed subtree
+        let mut tree = build_tree();
+        tree.set_root(6);
+        tree.remove_subtree(5);
+        assert_eq!(tree.root(), None);
+        let mut tree = build_tree();
+        tree.set_root(5);
+        tree.remove_subtree(1);
+        assert_eq!(tree.root(), Some(5));
+    }
+
+    #[test]
+    fn test_create_child() {
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, 0);
+        let a = tree.create_child(root, 1);
+        let b = tree.create_child(root, 2);
+        let c = tree.create_child(a, 3);
+        assert_eq!(tree.get_node(root).children, vec![a, b]);
+        assert_eq!(tree.get_node(a).children, vec![c]);
+        assert_eq!(tree.parent(a), Some(root));
+        assert_eq!(tree.parent(b), Some(root));
+        assert_eq!(tree.parent(c), Some(a));
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_save_load() {
+        let mut tree = build_tree();
+        tree.remove_subtree(4);
+        let path = std::env::temp_dir().join("tree_test_save_load.dat");
+        let path = path.to_str().unwrap();
+        tree.save(path).unwrap();
+        let loaded: Tree<usize> = Tree::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let expected: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        let order: Vec<usize> = loaded.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(order, expected);
+        assert_eq!(loaded.len(), tree.len());
+        assert_eq!(loaded.path_from_root(3), vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn test_root() {
+        let mut tree = build_tree();
+        assert_eq!(tree.root(), Some(0));
+        let order: Vec<usize> = tree.iter().map(|d| *d).collect();
+        assert_eq!(order, vec![0, 1, 2, 3, 4, 5, 6]);
+
+        tree.set_root(5);
+        let order: Vec<usize> = tree.iter().map(|d| *d).collect();
+        assert_eq!(order, vec![5, 6]);
+
+        tree.remove_subtree(5);
+        assert_eq!(tree.root(), None);
+        assert_eq!(tree.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_data() {
+        let mut tree = build_tree();
+        *tree.data_mut(3) = 30;
+        assert_eq!(*tree.data(3), 30);
+        assert_eq!(tree.get_node(3).data, 30);
+    }
+
+    #[test]
+    fn test_iter_dfs_matches_generator() {
+        let tree = build_tree();
+        let mut gen = Box::into_pin(tree.generator(0));
+        let mut expected = Vec::new();
+        while let GeneratorState::Yielded(data) = gen.as_mut().resume(()) {
+            expected.push(*data);
+        }
+        let order: Vec<usize> = tree.iter_dfs(0).map(|d| *d).collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn test_generator_with_id() {
+        let mut tree = build_tree();
+        *tree.data_mut(2) = 20;
+        let mut gen = Box::into_pin(tree.generator_with_id(1));
+        let mut order = Vec::new();
+        while let GeneratorState::Yielded((id, data)) = gen.as_mut().resume(()) {
+            order.push((id, *data));
+        }
+        assert_eq!(order, vec![(1, 1), (2, 20), (3, 3)]);
+
+        let mut gen = Box::into_pin(tree.generator_with_id(0));
+        let mut ids = Vec::new();
+        while let GeneratorState::Yielded((id, _)) = gen.as_mut().resume(()) {
+            ids.push(id);
+        }
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5, 6]);
+    }
 }
@@ -77,7 +77,7 @@ impl<'a> TreeBuilder<'a>{
         ));
         self.n_actions += 1;
 
-        match &self.tree.get_node(node_id).data {
+        match self.tree.data(node_id) {
             GameTreeNode::Action(_) => {
                 for action in state.valid_actions(&self.options.action_abstraction, round_idx.into()) {
                     self.build_action(node_id, round_idx, state, action);
@@ -106,7 +106,7 @@ impl<'a> TreeBuilder<'a>{
         }
 
         self.tree.get_node_mut(node).add_child(child);
-        match &mut self.tree.get_node_mut(node).data {
+        match self.tree.data_mut(node) {
             GameTreeNode::Action(an) => {
                 an.actions.push(action);
             },
@@ -0,0 +1,747 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::RngCore;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+use crate::betting_tree::GameNode;
+use crate::infoset_key::InfoSetKey;
+use crate::tree::{NodeId, Tree};
+
+/// cumulative regrets and strategy of an information set
+#[derive(Debug, Clone)]
+pub struct CfrInfoset {
+    pub regrets: Vec<f32>,
+    pub strategy_sum: Vec<f32>,
+}
+
+impl CfrInfoset {
+    fn init(n_actions: usize) -> CfrInfoset {
+        return CfrInfoset {
+            regrets: vec![0.0; n_actions],
+            strategy_sum: vec![0.0; n_actions],
+        };
+    }
+}
+
+/**
+ * Vanilla counterfactual regret minimization over a tree of GameNodes
+ * the regrets are stored per infoset so decision nodes sharing an infoset
+ * share a strategy
+ */
+#[derive(Debug)]
+pub struct Cfr {
+    infosets: Vec<CfrInfoset>,
+    /// number of iterations trained so far
+    iteration: usize,
+}
+
+impl Cfr {
+    /// creates empty regrets for the infosets of the decision nodes under root
+    pub fn new(tree: &Tree<GameNode>, root: NodeId) -> Cfr {
+        let mut infosets: Vec<CfrInfoset> = Vec::new();
+        for node in tree.iter_dfs(root) {
+            if let GameNode::Decision {
+                infoset, actions, ..
+            } = node
+            {
+                if *infoset >= infosets.len() {
+                    infosets.resize(*infoset + 1, CfrInfoset::init(0));
+                }
+                let n_actions = infosets[*infoset].regrets.len();
+                if n_actions == 0 {
+                    infosets[*infoset] = CfrInfoset::init(actions.len());
+                } else if n_actions != actions.len() {
+                    panic!(
+                        "infoset {} has nodes with {} and {} actions",
+                        infoset,
+                        n_actions,
+                        actions.len()
+                    );
+                }
+            }
+        }
+        return Cfr {
+            infosets,
+            iteration: 0,
+        };
+    }
+
+    pub fn infosets(&self) -> &[CfrInfoset] {
+        return &self.infosets;
+    }
+
+    /**
+     * runs iterations of cfr, each iteration updates player 0 then player 1
+     * the strategies are fixed during the traversal of a player
+     */
+    pub fn train(&mut self, tree: &Tree<GameNode>, root: NodeId, iterations: usize) {
+        self.train_with(tree, root, iterations, false);
+    }
+
+    /**
+     * runs iterations of cfr+
+     * regrets are floored at 0 after each update and the strategy of
+     * iteration t is weighted by t in the average
+     */
+    pub fn train_cfr_plus(&mut self, tree: &Tree<GameNode>, root: NodeId, iterations: usize) {
+        self.train_with(tree, root, iterations, true);
+    }
+
+    /**
+     * runs iterations of chance sampling monte carlo cfr
+     * each traversal follows a single outcome of the chance nodes drawn from rng
+     * instead of visiting all of them
+     */
+    pub fn train_mccfr<R: RngCore>(
+        &mut self,
+        tree: &Tree<GameNode>,
+        root: NodeId,
+        iterations: usize,
+        rng: &mut R,
+    ) {
+        for _ in 0..iterations {
+            self.iteration += 1;
+            for player in 0..2 {
+                let mut deltas = self.zeroed();
+                self.traverse(tree, root, player, 1.0, 1.0, &mut deltas, Some(&mut *rng));
+                self.apply(&deltas, false);
+            }
+        }
+    }
+
+    pub fn iteration(&self) -> usize {
+        return self.iteration;
+    }
+
+    fn train_with(&mut self, tree: &Tree<GameNode>, root: NodeId, iterations: usize, plus: bool) {
+        for _ in 0..iterations {
+            self.iteration += 1;
+            for player in 0..2 {
+                let mut deltas = self.zeroed();
+                self.traverse(tree, root, player, 1.0, 1.0, &mut deltas, None);
+                self.apply(&deltas, plus);
+            }
+        }
+    }
+
+    /**
+     * runs iterations of cfr like train, traversing the subtrees
+     * of the children of root in parallel
+     * each rayon job adds the updates of its subtrees to its own deltas,
+     * which are summed before they are applied, so the result only differs
+     * from train by the order of the float additions, which depends on
+     * how rayon splits the children
+     */
+    pub fn train_parallel(&mut self, tree: &Tree<GameNode>, root: NodeId, iterations: usize) {
+        for _ in 0..iterations {
+            self.iteration += 1;
+            for player in 0..2 {
+                let deltas = self.traverse_parallel(tree, root, player);
+                self.apply(&deltas, false);
+            }
+        }
+    }
+
+    /// traverse of root with reach 1 where the children are traversed in parallel
+    fn traverse_parallel(
+        &self,
+        tree: &Tree<GameNode>,
+        root: NodeId,
+        player: u8,
+    ) -> Vec<CfrInfoset> {
+        let children = &tree.get_node(root).children;
+        // probability of the player and of the opponent and chance to play to each child
+        let (strategy, reaches): (Vec<f32>, Vec<(f32, f32)>) = match tree.data(root) {
+            GameNode::Terminal { .. } => return self.zeroed(),
+            GameNode::Chance { outcomes } => (
+                outcomes.clone(),
+                outcomes.iter().map(|p| (1.0, *p)).collect(),
+            ),
+            GameNode::Decision {
+                player: acting,
+                infoset,
+                ..
+            } => {
+                let strategy = regret_match(&self.infosets[*infoset].regrets);
+                let reaches = strategy
+                    .iter()
+                    .map(|p| {
+                        if *acting == player {
+                            (*p, 1.0)
+                        } else {
+                            (1.0, *p)
+                        }
+                    })
+                    .collect();
+                (strategy, reaches)
+            }
+        };
+        // one deltas per job instead of per child, merged pairwise
+        let (child_values, mut deltas) = children
+            .par_iter()
+            .zip(reaches.par_iter())
+            .enumerate()
+            .fold(
+                || (Vec::new(), self.zeroed()),
+                |(mut values, mut deltas), (i, (child, (reach, cf_reach)))| {
+                    let value =
+                        self.traverse(tree, *child, player, *reach, *cf_reach, &mut deltas, None);
+                    values.push((i, value));
+                    return (values, deltas);
+                },
+            )
+            .reduce_with(|(mut values, mut deltas), (other_values, other_deltas)| {
+                values.extend(other_values);
+                add_deltas(&mut deltas, &other_deltas);
+                return (values, deltas);
+            })
+            .unwrap_or_else(|| (Vec::new(), self.zeroed()));
+        let mut values = vec![0f32; children.len()];
+        for (i, value) in child_values {
+            values[i] = value;
+        }
+        if let GameNode::Decision {
+            player: acting,
+            infoset,
+            ..
+        } = tree.data(root)
+        {
+            if *acting == player {
+                let value: f32 = values.iter().zip(&strategy).map(|(v, p)| p * v).sum();
+                let delta = &mut deltas[*infoset];
+                for (a, v) in values.iter().enumerate() {
+                    delta.regrets[a] += v - value;
+                    delta.strategy_sum[a] += strategy[a];
+                }
+            }
+        }
+        return deltas;
+    }
+
+    /// current strategy of a decision node from regret matching
+    pub fn current_strategy(&self, tree: &Tree<GameNode>, node: NodeId) -> Vec<f32> {
+        return regret_match(&self.infosets[decision_infoset(tree, node)].regrets);
+    }
+
+    /// average strategy of a decision node, uniform if it was never reached
+    pub fn average_strategy(&self, tree: &Tree<GameNode>, node: NodeId) -> Vec<f32> {
+        return self.average(decision_infoset(tree, node));
+    }
+
+    fn average(&self, infoset: usize) -> Vec<f32> {
+        let strategy_sum = &self.infosets[infoset].strategy_sum;
+        let norm_sum: f32 = strategy_sum.iter().sum();
+        if norm_sum > 0.0 {
+            return strategy_sum.iter().map(|s| s / norm_sum).collect();
+        }
+        return vec![1.0 / strategy_sum.len() as f32; strategy_sum.len()];
+    }
+
+    /**
+     * average strategy of every infoset under root as probabilities of its actions,
+     * uniform for infosets that were never reached
+     * the key of a decision node is the index of each action taken on the way
+     * from root, chance outcomes are skipped, and its bucket
+     * infoset / infosets_per_bucket, the layout of build_bucketed_tree and build_kuhn
+     * panics if two infosets get the same key
+     */
+    pub fn export_strategy(
+        &self,
+        tree: &Tree<GameNode>,
+        root: NodeId,
+        infosets_per_bucket: usize,
+    ) -> HashMap<InfoSetKey, Vec<f32>> {
+        let mut strategy = HashMap::new();
+        let mut key_infosets: HashMap<InfoSetKey, usize> = HashMap::new();
+        let mut stack = vec![(root, InfoSetKey::root(0))];
+        while let Some((node, history)) = stack.pop() {
+            let children = &tree.get_node(node).children;
+            match tree.data(node) {
+                GameNode::Decision { infoset, .. } => {
+                    let key = history.with_bucket(infoset / infosets_per_bucket);
+                    let other = *key_infosets.entry(key).or_insert(*infoset);
+                    if other != *infoset {
+                        panic!("infosets {} and {} share key {}", other, infoset, key);
+                    }
+                    strategy
+                        .entry(key)
+                        .or_insert_with(|| self.average(*infoset));
+                    for (a, child) in children.iter().enumerate().rev() {
+                        stack.push((*child, history.push(a as u8)));
+                    }
+                }
+                GameNode::Chance { .. } => {
+                    stack.extend(children.iter().rev().map(|child| (*child, history)));
+                }
+                GameNode::Terminal { .. } => {}
+            }
+        }
+        return strategy;
+    }
+
+    /**
+     * value of root for player when they best respond to the average strategy
+     * of the opponent, the best response picks one action per infoset
+     */
+    pub fn best_response_value(&self, tree: &Tree<GameNode>, root: NodeId, player: u8) -> f32 {
+        let mut br = BestResponse {
+            cfr: self,
+            tree,
+            player,
+            nodes: vec![Vec::new(); self.infosets.len()],
+            actions: vec![None; self.infosets.len()],
+            values: HashMap::new(),
+        };
+        br.collect_nodes(root, 1.0);
+        return br.value(root);
+    }
+
+    /**
+     * sum of what both players gain by best responding to the average strategy
+     * 0 at a nash equilibrium of a zero sum game
+     */
+    pub fn exploitability(&self, tree: &Tree<GameNode>, root: NodeId) -> f32 {
+        return self.best_response_value(tree, root, 0) + self.best_response_value(tree, root, 1);
+    }
+
+    fn zeroed(&self) -> Vec<CfrInfoset> {
+        return self
+            .infosets
+            .iter()
+            .map(|i| CfrInfoset::init(i.regrets.len()))
+            .collect();
+    }
+
+    fn apply(&mut self, deltas: &[CfrInfoset], plus: bool) {
+        let weight = if plus { self.iteration as f32 } else { 1.0 };
+        for (infoset, delta) in self.infosets.iter_mut().zip(deltas) {
+            for a in 0..infoset.regrets.len() {
+                infoset.regrets[a] += delta.regrets[a];
+                if plus {
+                    infoset.regrets[a] = infoset.regrets[a].max(0.0);
+                }
+                infoset.strategy_sum[a] += weight * delta.strategy_sum[a];
+            }
+        }
+    }
+
+    /**
+     * returns the value of node for player
+     * reach: probability of player playing to node
+     * cf_reach: probability of the opponent and chance playing to node
+     * the regret and strategy updates of player are added to deltas
+     * with rng a single outcome of each chance node is sampled, its value is
+     * an unbiased estimate as the sampling probability cancels the chance reach
+     */
+    fn traverse(
+        &self,
+        tree: &Tree<GameNode>,
+        node: NodeId,
+        player: u8,
+        reach: f32,
+        cf_reach: f32,
+        deltas: &mut [CfrInfoset],
+        mut rng: Option<&mut dyn RngCore>,
+    ) -> f32 {
+        let children = &tree.get_node(node).children;
+        match tree.data(node) {
+            GameNode::Terminal { payoff } => {
+                return payoff[usize::from(player)];
+            }
+            GameNode::Chance { outcomes } => {
+                if let Some(rng) = rng {
+                    let outcome = WeightedIndex::new(outcomes).unwrap().sample(rng);
+                    return self.traverse(
+                        tree,
+                        children[outcome],
+                        player,
+                        reach,
+                        cf_reach,
+                        deltas,
+                        Some(rng),
+                    );
+                }
+                let mut value = 0.0;
+                for (child, p) in children.iter().zip(outcomes) {
+                    value +=
+                        p * self.traverse(tree, *child, player, reach, cf_reach * p, deltas, None);
+                }
+                return value;
+            }
+            GameNode::Decision {
+                player: acting,
+                infoset,
+                ..
+            } => {
+                let strategy = regret_match(&self.infosets[*infoset].regrets);
+                if *acting != player {
+                    let mut value = 0.0;
+                    for (a, child) in children.iter().enumerate() {
+                        value += strategy[a]
+                            * self.traverse(
+                                tree,
+                                *child,
+                                player,
+                                reach,
+                                cf_reach * strategy[a],
+                                deltas,
+                                reborrow(&mut rng),
+                            );
+                    }
+                    return value;
+                }
+                let mut values = vec![0.0; children.len()];
+                let mut value = 0.0;
+                for (a, child) in children.iter().enumerate() {
+                    values[a] = self.traverse(
+                        tree,
+                        *child,
+                        player,
+                        reach * strategy[a],
+                        cf_reach,
+                        deltas,
+                        reborrow(&mut rng),
+                    );
+                    value += strategy[a] * values[a];
+                }
+                let delta = &mut deltas[*infoset];
+                for a in 0..children.len() {
+                    delta.regrets[a] += cf_reach * (values[a] - value);
+                    delta.strategy_sum[a] += reach * strategy[a];
+                }
+                return value;
+            }
+        }
+    }
+}
+
+/// best response of player against the average strategy of cfr
+struct BestResponse<'a> {
+    cfr: &'a Cfr,
+    tree: &'a Tree<GameNode>,
+    player: u8,
+    /// nodes of each infoset of player with their opponent and chance reach
+    nodes: Vec<Vec<(NodeId, f32)>>,
+    /// best action of each infoset of player once computed
+    actions: Vec<Option<usize>>,
+    values: HashMap<NodeId, f32>,
+}
+
+impl<'a> BestResponse<'a> {
+    fn collect_nodes(&mut self, node: NodeId, cf_reach: f32) {
+        let children = &self.tree.get_node(node).children;
+        match self.tree.data(node) {
+            GameNode::Terminal { .. } => {}
+            GameNode::Chance { outcomes } => {
+                for (child, p) in children.iter().zip(outcomes) {
+                    self.collect_nodes(*child, cf_reach * p);
+                }
+            }
+            GameNode::Decision {
+                player, infoset, ..
+            } => {
+                if *player == self.player {
+                    self.nodes[*infoset].push((node, cf_reach));
+                    for child in children {
+                        self.collect_nodes(*child, cf_reach);
+                    }
+                } else {
+                    let strategy = self.cfr.average(*infoset);
+                    for (child, p) in children.iter().zip(strategy) {
+                        self.collect_nodes(*child, cf_reach * p);
+                    }
+                }
+            }
+        }
+    }
+
+    fn value(&mut self, node: NodeId) -> f32 {
+        if let Some(value) = self.values.get(&node) {
+            return *value;
+        }
+        let tree = self.tree;
+        let children = &tree.get_node(node).children;
+        let value = match tree.data(node) {
+            GameNode::Terminal { payoff } => payoff[usize::from(self.player)],
+            GameNode::Chance { outcomes } => {
+                let mut value = 0.0;
+                for (child, p) in children.iter().zip(outcomes) {
+                    value += p * self.value(*child);
+                }
+                value
+            }
+            GameNode::Decision {
+                player, infoset, ..
+            } => {
+                if *player == self.player {
+                    let action = self.best_action(*infoset);
+                    self.value(children[action])
+                } else {
+                    let strategy = self.cfr.average(*infoset);
+                    let mut value = 0.0;
+                    for (child, p) in children.iter().zip(strategy) {
+                        value += p * self.value(*child);
+                    }
+                    value
+                }
+            }
+        };
+        self.values.insert(node, value);
+        return value;
+    }
+
+    /// action with the highest value summed over the nodes of infoset
+    fn best_action(&mut self, infoset: usize) -> usize {
+        if let Some(action) = self.actions[infoset] {
+            return action;
+        }
+        let nodes = self.nodes[infoset].clone();
+        let n_actions = self.cfr.infosets[infoset].regrets.len();
+        let mut best = (0, f32::NEG_INFINITY);
+        for a in 0..n_actions {
+            let mut value = 0.0;
+            for (node, cf_reach) in &nodes {
+                value += cf_reach * self.value(self.tree.get_node(*node).children[a]);
+            }
+            if value > best.1 {
+                best = (a, value);
+            }
+        }
+        self.actions[infoset] = Some(best.0);
+        return best.0;
+    }
+}
+
+/// shortens the lifetime of rng to pass it down the recursion
+fn reborrow<'a>(rng: &'a mut Option<&mut dyn RngCore>) -> Option<&'a mut dyn RngCore> {
+    return Some(&mut **rng.as_mut()?);
+}
+
+/// adds the regrets and strategy sums of other to deltas
+fn add_deltas(deltas: &mut [CfrInfoset], other: &[CfrInfoset]) {
+    for (delta, other_delta) in deltas.iter_mut().zip(other) {
+        for a in 0..delta.regrets.len() {
+            delta.regrets[a] += other_delta.regrets[a];
+            delta.strategy_sum[a] += other_delta.strategy_sum[a];
+        }
+    }
+}
+
+fn decision_infoset(tree: &Tree<GameNode>, node: NodeId) -> usize {
+    match tree.data(node).infoset() {
+        Some(infoset) => return infoset,
+        None => panic!("node {} is not a decision node", node),
+    }
+}
+
+/**
+ * Regret matching
+ * returns a strategy proportional to the positive regrets,
+ * uniform if no regret is positive
+ */
+pub fn regret_match(regrets: &[f32]) -> Vec<f32> {
+    let norm_sum: f32 = regrets.iter().filter(|r| **r > 0.0).sum();
+    if norm_sum > 0.0 {
+        return regrets.iter().map(|r| r.max(0.0) / norm_sum).collect();
+    }
+    return vec![1.0 / regrets.len() as f32; regrets.len()];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_abstraction::Action;
+
+    /**
+     * player 0 and 1 pick A or B at the same time
+     * player 0 wins 2 on AA, 1 on BB and loses 1 otherwise
+     * both play A with probability 2/5 at equilibrium
+     */
+    fn matching_game() -> (Tree<GameNode>, NodeId) {
+        let mut tree = Tree::new();
+        let actions = vec![Action::Bet(1.0), Action::Check];
+        let root = tree.create_node(None, GameNode::decision(0, 0, actions.clone()));
+        let payoffs = [[2.0, -1.0], [-1.0, 1.0]];
+        for row in payoffs.iter() {
+            let node = tree.create_child(root, GameNode::decision(1, 1, actions.clone()));
+            for payoff in row.iter() {
+                tree.create_child(node, GameNode::terminal([*payoff, -payoff]));
+            }
+        }
+        return (tree, root);
+    }
+
+    #[test]
+    fn test_regret_match() {
+        assert_eq!(regret_match(&[1.0, 3.0]), vec![0.25, 0.75]);
+        // negative regrets are ignored
+        assert_eq!(
+            regret_match(&[-2.0, 1.0, 0.0, 3.0]),
+            vec![0.0, 0.25, 0.0, 0.75]
+        );
+        assert_eq!(regret_match(&[0.0; 4]), vec![0.25; 4]);
+        assert_eq!(regret_match(&[-1.0, 0.0]), vec![0.5, 0.5]);
+        assert!(regret_match(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_cfr_single_player() {
+        // player 0 picks between winning 1 and losing 1
+        let mut tree = Tree::new();
+        let root = tree.create_node(
+            None,
+            GameNode::decision(0, 0, vec![Action::Check, Action::Fold]),
+        );
+        tree.create_child(root, GameNode::terminal([1.0, -1.0]));
+        tree.create_child(root, GameNode::terminal([-1.0, 1.0]));
+        let mut cfr = Cfr::new(&tree, root);
+        assert_eq!(cfr.average_strategy(&tree, root), vec![0.5, 0.5]);
+        cfr.train(&tree, root, 100);
+        assert_eq!(cfr.current_strategy(&tree, root), vec![1.0, 0.0]);
+        assert!(cfr.average_strategy(&tree, root)[0] > 0.99);
+    }
+
+    #[test]
+    fn test_cfr_matching_game() {
+        let (tree, root) = matching_game();
+        let mut cfr = Cfr::new(&tree, root);
+        assert_eq!(cfr.infosets().len(), 2);
+        cfr.train(&tree, root, 10_000);
+        let p0 = cfr.average_strategy(&tree, root);
+        let p1 = cfr.average_strategy(&tree, tree.get_node(root).children[1]);
+        assert!((p0[0] - 0.4).abs() < 0.02, "{:?}", p0);
+        assert!((p1[0] - 0.4).abs() < 0.02, "{:?}", p1);
+    }
+
+    #[test]
+    fn test_export_strategy() {
+        let (tree, root) = matching_game();
+        let mut cfr = Cfr::new(&tree, root);
+        // never reached, uniform
+        let strategy = cfr.export_strategy(&tree, root, usize::MAX);
+        assert_eq!(strategy.len(), 3);
+        assert_eq!(strategy[&InfoSetKey::root(0)], vec![0.5, 0.5]);
+        cfr.train(&tree, root, 100);
+        let strategy = cfr.export_strategy(&tree, root, usize::MAX);
+        // both histories of player 1 are the same infoset
+        let p1 = cfr.average_strategy(&tree, tree.get_node(root).children[0]);
+        assert_eq!(strategy[&InfoSetKey::new(&[0], 0)], p1);
+        assert_eq!(strategy[&InfoSetKey::new(&[1], 0)], p1);
+        // one bucket per infoset
+        let strategy = cfr.export_strategy(&tree, root, 1);
+        assert_eq!(strategy[&InfoSetKey::new(&[1], 1)], p1);
+    }
+
+    #[test]
+    #[should_panic(expected = "infosets 0 and 1 share key")]
+    fn test_export_strategy_shared_key() {
+        // two deals with their own infoset at the same history in the same bucket
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, GameNode::chance(vec![0.5, 0.5]));
+        for infoset in 0..2 {
+            let node = tree.create_child(root, GameNode::decision(0, infoset, vec![Action::Call]));
+            tree.create_child(node, GameNode::terminal([0.0, 0.0]));
+        }
+        let cfr = Cfr::new(&tree, root);
+        cfr.export_strategy(&tree, root, usize::MAX);
+    }
+
+    #[test]
+    fn test_cfr_chance() {
+        // a coin flip decides if player 0 should pick the first or second action
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, GameNode::chance(vec![0.25, 0.75]));
+        for (infoset, payoff) in [1.0f32, -1.0].iter().enumerate() {
+            let node = tree.create_child(
+                root,
+                GameNode::decision(0, infoset, vec![Action::Check, Action::Fold]),
+            );
+            tree.create_child(node, GameNode::terminal([*payoff, -payoff]));
+            tree.create_child(node, GameNode::terminal([-payoff, *payoff]));
+        }
+        let mut cfr = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 1000);
+        let children = &tree.get_node(root).children;
+        assert!(cfr.average_strategy(&tree, children[0])[0] > 0.99);
+        assert!(cfr.average_strategy(&tree, children[1])[1] > 0.99);
+    }
+
+    #[test]
+    fn test_cfr_plus_matching_game() {
+        let (tree, root) = matching_game();
+        let node = tree.get_node(root).children[1];
+        // distance of both average strategies to the equilibrium
+        let error = |cfr: &Cfr| {
+            let p0 = cfr.average_strategy(&tree, root)[0];
+            let p1 = cfr.average_strategy(&tree, node)[0];
+            return (p0 - 0.4).abs() + (p1 - 0.4).abs();
+        };
+        let mut cfr = Cfr::new(&tree, root);
+        let mut cfr_plus = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 1000);
+        cfr_plus.train_cfr_plus(&tree, root, 1000);
+        assert_eq!(cfr_plus.iteration(), 1000);
+        assert!(error(&cfr_plus) < 0.005, "{}", error(&cfr_plus));
+        assert!(error(&cfr_plus) < error(&cfr));
+        for infoset in cfr_plus.infosets() {
+            assert!(infoset.regrets.iter().all(|r| *r >= 0.0));
+        }
+    }
+
+    #[test]
+    fn test_exploitability_matching_game() {
+        let (tree, root) = matching_game();
+        let mut cfr = Cfr::new(&tree, root);
+        // player 0 picks A against uniform, player 1 picks B
+        assert!((cfr.best_response_value(&tree, root, 0) - 0.5).abs() < 1e-6);
+        assert!(cfr.best_response_value(&tree, root, 1).abs() < 1e-6);
+        assert!((cfr.exploitability(&tree, root) - 0.5).abs() < 1e-6);
+        cfr.train_cfr_plus(&tree, root, 1000);
+        // game value of 1/5 for player 0
+        assert!((cfr.best_response_value(&tree, root, 0) - 0.2).abs() < 0.01);
+        assert!(cfr.exploitability(&tree, root) < 0.01);
+    }
+
+    #[test]
+    fn test_best_response_infoset() {
+        // player 1 can't see the coin flip, so they can't win both branches
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, GameNode::chance(vec![0.5, 0.5]));
+        for payoff in [1.0f32, -1.0].iter() {
+            let node = tree.create_child(
+                root,
+                GameNode::decision(1, 0, vec![Action::Check, Action::Fold]),
+            );
+            tree.create_child(node, GameNode::terminal([*payoff, -payoff]));
+            tree.create_child(node, GameNode::terminal([-payoff, *payoff]));
+        }
+        let cfr = Cfr::new(&tree, root);
+        assert_eq!(cfr.best_response_value(&tree, root, 1), 0.0);
+        assert_eq!(cfr.best_response_value(&tree, root, 0), 0.0);
+    }
+
+    #[test]
+    fn test_cfr_parallel_matching_game() {
+        // the root is a decision of player 0
+        let (tree, root) = matching_game();
+        let mut cfr = Cfr::new(&tree, root);
+        let mut parallel = Cfr::new(&tree, root);
+        cfr.train(&tree, root, 100);
+        parallel.train_parallel(&tree, root, 100);
+        for (a, b) in cfr.infosets().iter().zip(parallel.infosets()) {
+            assert_eq!(a.regrets, b.regrets);
+            assert_eq!(a.strategy_sum, b.strategy_sum);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "infoset 0 has nodes with 2 and 1 actions")]
+    fn test_cfr_infoset_mismatch() {
+        let mut tree = Tree::new();
+        let root = tree.create_node(None, GameNode::chance(vec![0.5, 0.5]));
+        let actions = vec![Action::Check, Action::Fold];
+        tree.create_child(root, GameNode::decision(0, 0, actions));
+        tree.create_child(root, GameNode::decision(0, 0, vec![Action::Check]));
+        Cfr::new(&tree, root);
+    }
+}